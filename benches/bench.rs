@@ -3,10 +3,400 @@
 extern crate coco;
 extern crate test;
 
-use coco::epoch;
-use test::Bencher;
+use std::sync::{Arc, Mutex, mpsc};
+use std::sync::atomic::{AtomicBool, AtomicPtr};
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, SeqCst};
+use std::thread;
+
+use coco::epoch::{self, Atomic, Owned, Ptr, Scope};
+use test::{Bencher, black_box};
 
 #[bench]
 fn pin_empty(b: &mut Bencher) {
     b.iter(|| epoch::pin(|_| ()))
 }
+
+#[bench]
+fn defer_free_with_idle_pinners(b: &mut Bencher) {
+    // These threads don't produce any garbage, but help collecting it while pinning.
+    let done = Arc::new(AtomicBool::new(false));
+    let threads = (0..3).map(|_| {
+        let done = done.clone();
+        thread::spawn(move || {
+            while !done.load(SeqCst) {
+                epoch::pin(|_| ());
+            }
+        })
+    }).collect::<Vec<_>>();
+
+    b.iter(|| {
+        epoch::pin(|scope| unsafe {
+            scope.defer_free(Owned::new(1u64).into_ptr(scope));
+        })
+    });
+
+    done.store(true, SeqCst);
+    for t in threads {
+        t.join().unwrap();
+    }
+}
+
+/// Pins while `idle` other threads are registered, so that advancing the epoch during periodic
+/// collections has to traverse all of them.
+fn pin_with_registered(b: &mut Bencher, idle: usize) {
+    let (registered_tx, registered_rx) = mpsc::channel();
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    let done_rx = Arc::new(Mutex::new(done_rx));
+
+    let threads = (0..idle).map(|_| {
+        let registered_tx = registered_tx.clone();
+        let done_rx = done_rx.clone();
+        thread::spawn(move || {
+            epoch::pin(|_| ());
+            registered_tx.send(()).unwrap();
+            let _ = done_rx.lock().unwrap().recv();
+        })
+    }).collect::<Vec<_>>();
+    for _ in 0..idle {
+        registered_rx.recv().unwrap();
+    }
+
+    b.iter(|| epoch::pin(|_| ()));
+
+    drop(done_tx);
+    for t in threads {
+        t.join().unwrap();
+    }
+}
+
+#[bench]
+fn pin_with_4_registered(b: &mut Bencher) {
+    pin_with_registered(b, 4);
+}
+
+#[bench]
+fn pin_with_64_registered(b: &mut Bencher) {
+    pin_with_registered(b, 64);
+}
+
+#[bench]
+fn pin_with_256_registered(b: &mut Bencher) {
+    pin_with_registered(b, 256);
+}
+
+#[bench]
+fn flush_bags(b: &mut Bencher) {
+    // Every iteration pushes a bag into the queue and replaces it with an empty one, so this
+    // measures how cheaply bags are allocated and recycled.
+    b.iter(|| {
+        epoch::pin(|scope| unsafe {
+            scope.defer_free(Owned::new(1u64).into_ptr(scope));
+            scope.flush();
+        })
+    });
+}
+
+#[bench]
+fn flush_with_busy_pinners(b: &mut Bencher) {
+    // Every iteration flushes a bag and thus tries advancing the epoch while other threads keep
+    // pinning and collecting.
+    let done = Arc::new(AtomicBool::new(false));
+    let threads = (0..3).map(|_| {
+        let done = done.clone();
+        thread::spawn(move || {
+            while !done.load(SeqCst) {
+                epoch::pin(|scope| scope.flush());
+            }
+        })
+    }).collect::<Vec<_>>();
+
+    b.iter(|| {
+        epoch::pin(|scope| unsafe {
+            scope.defer_free(Owned::new(1u64).into_ptr(scope));
+            scope.flush();
+        })
+    });
+
+    done.store(true, SeqCst);
+    for t in threads {
+        t.join().unwrap();
+    }
+}
+
+#[bench]
+fn flush_in_long_pin(b: &mut Bencher) {
+    // Every iteration flushes many bags within a single pinned region while another thread keeps
+    // advancing the epoch. Once the epoch has moved past the one this thread is pinned in, the
+    // remaining attempts at advancing it are cut short by relaxed loads instead of traversing the
+    // participants with `SeqCst` loads.
+    let done = Arc::new(AtomicBool::new(false));
+    let advancer = {
+        let done = done.clone();
+        thread::spawn(move || {
+            while !done.load(SeqCst) {
+                epoch::pin(|scope| scope.flush());
+            }
+        })
+    };
+
+    b.iter(|| {
+        epoch::pin(|scope| unsafe {
+            for _ in 0..64 {
+                scope.defer_free(Owned::new(1u64).into_ptr(scope));
+                scope.flush();
+            }
+        })
+    });
+
+    done.store(true, SeqCst);
+    advancer.join().unwrap();
+}
+
+#[bench]
+fn flush_contended(b: &mut Bencher) {
+    // One thread per CPU keeps pushing bags into the global queue, contending with the measured
+    // thread on the queue (or its shards).
+    let cpus = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let done = Arc::new(AtomicBool::new(false));
+    let threads = (0..cpus).map(|_| {
+        let done = done.clone();
+        thread::spawn(move || {
+            while !done.load(SeqCst) {
+                epoch::pin(|scope| unsafe {
+                    scope.defer_free(Owned::new(1u64).into_ptr(scope));
+                    scope.flush();
+                })
+            }
+        })
+    }).collect::<Vec<_>>();
+
+    b.iter(|| {
+        epoch::pin(|scope| unsafe {
+            scope.defer_free(Owned::new(1u64).into_ptr(scope));
+            scope.flush();
+        })
+    });
+
+    done.store(true, SeqCst);
+    for t in threads {
+        t.join().unwrap();
+    }
+}
+
+#[cfg(feature = "internals")]
+mod fence {
+    use std::sync::atomic::AtomicUsize;
+
+    use coco::epoch::FenceStrategy;
+    use test::{self, Bencher};
+
+    fn bench_strategy(b: &mut Bencher, strategy: FenceStrategy) {
+        let a = AtomicUsize::new(0);
+        let mut i = 0;
+        b.iter(|| {
+            i += 1;
+            strategy.store(test::black_box(&a), i);
+        });
+    }
+
+    #[bench]
+    fn fence(b: &mut Bencher) {
+        bench_strategy(b, FenceStrategy::Fence);
+    }
+
+    #[bench]
+    fn swap(b: &mut Bencher) {
+        bench_strategy(b, FenceStrategy::Swap);
+    }
+
+    #[bench]
+    fn compare_and_swap(b: &mut Bencher) {
+        bench_strategy(b, FenceStrategy::CompareAndSwap);
+    }
+}
+
+#[bench]
+fn defer_free_with_stalled_thread(b: &mut Bencher) {
+    // This thread stays pinned, so the epoch can't advance and garbage can't be collected.
+    let done = Arc::new(AtomicBool::new(false));
+    let t = {
+        let done = done.clone();
+        thread::spawn(move || {
+            epoch::pin(|_| {
+                while !done.load(SeqCst) {
+                    thread::yield_now();
+                }
+            })
+        })
+    };
+
+    b.iter(|| {
+        epoch::pin(|scope| unsafe {
+            scope.defer_free(Owned::new(1u64).into_ptr(scope));
+        })
+    });
+
+    done.store(true, SeqCst);
+    t.join().unwrap();
+}
+
+#[bench]
+fn pin_stalled_epoch(b: &mut Bencher) {
+    // Another thread stays pinned for the whole benchmark, so the epoch can't advance and no
+    // garbage can be collected. Collection attempts made while pinning are wasted work.
+    let (pinned_tx, pinned_rx) = mpsc::channel();
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    let t = thread::spawn(move || {
+        epoch::pin(|_| {
+            pinned_tx.send(()).unwrap();
+            done_rx.recv().unwrap();
+        })
+    });
+    pinned_rx.recv().unwrap();
+
+    epoch::pin(|scope| unsafe {
+        for _ in 0..1000 {
+            scope.defer_free(Owned::new(1u64).into_ptr(scope));
+        }
+        scope.flush();
+    });
+
+    b.iter(|| epoch::pin(|_| ()));
+
+    done_tx.send(()).unwrap();
+    t.join().unwrap();
+}
+
+#[bench]
+fn atomic_load(b: &mut Bencher) {
+    let a = Atomic::new(1u64);
+    epoch::pin(|scope| b.iter(|| black_box(a.load(Acquire, scope))));
+}
+
+#[bench]
+fn atomic_ptr_load(b: &mut Bencher) {
+    // Baseline for `atomic_load`: the same operation on a plain `AtomicPtr`.
+    let a = AtomicPtr::new(Box::into_raw(Box::new(1u64)));
+    b.iter(|| black_box(a.load(Acquire)));
+}
+
+#[bench]
+fn atomic_cas(b: &mut Bencher) {
+    let a = Atomic::new(1u64);
+    epoch::pin(|scope| {
+        let p = a.load(Acquire, scope);
+        b.iter(|| black_box(a.compare_and_swap(p, p, AcqRel, scope)))
+    });
+}
+
+#[bench]
+fn atomic_ptr_cas(b: &mut Bencher) {
+    // Baseline for `atomic_cas`: the same operation on a plain `AtomicPtr`.
+    let a = AtomicPtr::new(Box::into_raw(Box::new(1u64)));
+    let p = a.load(Acquire);
+    b.iter(|| black_box(a.compare_exchange(p, p, AcqRel, Acquire)))
+}
+
+struct Node {
+    _value: u64,
+    next: Atomic<Node>,
+}
+
+fn push_contended<F>(b: &mut Bencher, push: F)
+where
+    F: Fn(&Atomic<Node>, &Scope),
+{
+    // These threads keep flipping the tag of the head, so that pushes often have to retry.
+    let head = Arc::new(Atomic::null());
+    let done = Arc::new(AtomicBool::new(false));
+    let threads = (0..3).map(|_| {
+        let head = head.clone();
+        let done = done.clone();
+        thread::spawn(move || {
+            while !done.load(SeqCst) {
+                epoch::pin(|scope| {
+                    let h = head.load(Acquire, scope);
+                    let _ = head.compare_and_swap(h, h.with_tag(h.tag() ^ 1), AcqRel, scope);
+                })
+            }
+        })
+    }).collect::<Vec<_>>();
+
+    b.iter(|| {
+        epoch::pin(|scope| unsafe {
+            push(&head, scope);
+            scope.defer_free(head.swap_null(AcqRel, scope));
+        })
+    });
+
+    done.store(true, SeqCst);
+    for t in threads {
+        t.join().unwrap();
+    }
+}
+
+#[bench]
+fn push_like_contended(b: &mut Bencher) {
+    push_contended(b, |head, scope| {
+        head.push_like(
+            |h| Box::new(Node { _value: 1, next: Atomic::from_ptr(h) }),
+            |node, h| node.next.store(h, Relaxed),
+            AcqRel,
+            scope,
+        );
+    });
+}
+
+#[bench]
+fn push_realloc_contended(b: &mut Bencher) {
+    // Baseline for `push_like_contended`: a naive loop allocating a new node on every attempt.
+    push_contended(b, |head, scope| loop {
+        let h = head.load(Acquire, scope);
+        let node = Owned::new(Node { _value: 1, next: Atomic::from_ptr(h) });
+        match head.compare_and_swap_weak_owned(h, node, AcqRel, scope) {
+            Ok(_) => break,
+            Err((_, node)) => drop(node.into_box()),
+        }
+    });
+}
+
+/// Builds a circular list of `len` nodes.
+fn ring(len: usize) -> Vec<Box<Node>> {
+    let nodes = (0..len)
+        .map(|i| Box::new(Node { _value: i as u64, next: Atomic::null() }))
+        .collect::<Vec<_>>();
+    for i in 0..len {
+        let next = &*nodes[(i + 1) % len] as *const Node;
+        nodes[i].next.store(unsafe { Ptr::from_raw(next) }, Relaxed);
+    }
+    nodes
+}
+
+#[bench]
+fn traverse_checked(b: &mut Bencher) {
+    let nodes = ring(64);
+    epoch::pin(|scope| {
+        b.iter(|| {
+            let mut node = &*nodes[0];
+            for _ in 0..1000 {
+                node = unsafe { node.next.load(Acquire, scope).as_ref() }.unwrap();
+            }
+            black_box(node);
+        })
+    });
+}
+
+#[bench]
+fn traverse_unchecked(b: &mut Bencher) {
+    // The same traversal as `traverse_checked`, but without null checks.
+    let nodes = ring(64);
+    epoch::pin(|scope| {
+        b.iter(|| {
+            let mut node = &*nodes[0];
+            for _ in 0..1000 {
+                node = unsafe { node.next.load_unchecked(Acquire, scope) };
+            }
+            black_box(node);
+        })
+    });
+}
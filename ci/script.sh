@@ -4,6 +4,7 @@ set -ex
 
 cargo test
 cargo test --features strict_gc
+RUSTFLAGS="--cfg loom" cargo test --release --lib model
 
 # if [[ "$TRAVIS_RUST_VERSION" == "nightly" ]]; then
 #     cd sanitize
@@ -17,4 +18,6 @@ cargo test --features strict_gc
 #     for _ in {1..10}; do
 #         cargo test --release --features coco/strict_gc
 #     done
+#
+#     cargo test --release --features shuttle --test fuzz
 # fi
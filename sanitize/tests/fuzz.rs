@@ -0,0 +1,121 @@
+//! Randomized interleavings of pinning, deferral, collection, and unregistration.
+//!
+//! Shuttle runs the threads as coroutines and picks a random schedule in every iteration, so many
+//! more operations fit into a test than with loom's exhaustive exploration. Threads switch only at
+//! shuttle's yield points, which are placed between operations and inside pinned regions.
+//!
+//! All threads of an iteration run on the same OS thread and would therefore share a thread-local
+//! registration, so each of them registers its own participant with `epoch::register` instead,
+//! and pins and collects garbage only through it.
+//!
+//! Memory is allocated through the checking allocator. Objects are never read before it confirms
+//! that they are still live, so a premature reclamation fails the test without reading freed
+//! memory, while double frees and writes after free abort the process.
+//!
+//! Run with `cargo test --features shuttle --test fuzz`.
+
+#![cfg(feature = "shuttle")]
+
+extern crate coco;
+extern crate shuttle;
+
+use std::sync::Arc;
+use std::sync::atomic::Ordering::{AcqRel, Acquire};
+
+use coco::checked_alloc::{self, CheckedAlloc};
+use coco::epoch::{self, Atomic, Owned, Ptr, Scope};
+use shuttle::thread;
+
+#[global_allocator]
+static ALLOC: CheckedAlloc = CheckedAlloc;
+
+/// Number of shared slots the threads fight over.
+const SLOTS: usize = 3;
+/// Number of threads per iteration.
+const THREADS: usize = 3;
+/// Number of operations every thread performs per iteration.
+const OPS: usize = 40;
+/// Number of iterations, each with a fresh random schedule.
+const ITERATIONS: usize = 2000;
+
+/// Asserts that the object `p` points to hasn't been freed.
+fn check_live(p: Ptr<u64>) {
+    assert!(
+        checked_alloc::is_live(p.as_raw() as *const u8),
+        "use after free at {:p}",
+        p.as_raw()
+    );
+}
+
+/// Replaces the object in `slot` with a new one and defers destruction of the old one.
+fn replace(slot: &Atomic<u64>, value: u64, scope: &Scope) {
+    let old = slot.swap(Owned::new(value).into_ptr(scope), AcqRel, scope);
+    thread::yield_now();
+    unsafe { scope.defer_free(old) }
+}
+
+/// Performs a sequence of operations on the slots, yielding to the scheduler between them.
+fn worker(slots: Arc<Vec<Atomic<u64>>>, t: usize) {
+    let mut handle = epoch::register();
+
+    for i in 0..OPS {
+        let slot = &slots[(t + i) % SLOTS];
+
+        match (t * 7 + i * 13) % 10 {
+            0..=4 => handle.pin(|scope| {
+                let p = slot.load(Acquire, scope);
+                if !p.is_null() {
+                    // Other threads may unlink and defer the object in the meantime, but it must
+                    // stay alive while this thread is pinned.
+                    check_live(p);
+                    thread::yield_now();
+                    check_live(p);
+                    assert!(unsafe { *p.deref() } < (THREADS * OPS) as u64);
+                }
+            }),
+            5 | 6 => handle.pin(|scope| replace(slot, (t * OPS + i) as u64, scope)),
+            7 => handle.pin(|scope| scope.flush()),
+            8 => {
+                handle.collect_all();
+            }
+            _ => {
+                // Unregister, moving the remaining garbage into the global queue, and register
+                // anew.
+                drop(handle);
+                thread::yield_now();
+                handle = epoch::register();
+            }
+        }
+
+        thread::yield_now();
+    }
+}
+
+#[test]
+fn fuzz_gc() {
+    shuttle::check_random(
+        || {
+            let slots = Arc::new((0..SLOTS).map(|i| Atomic::new(i as u64)).collect::<Vec<_>>());
+
+            let threads = (0..THREADS)
+                .map(|t| {
+                    let slots = slots.clone();
+                    thread::spawn(move || worker(slots, t))
+                })
+                .collect::<Vec<_>>();
+
+            for t in threads {
+                t.join().unwrap();
+            }
+
+            let handle = epoch::register();
+            handle.pin(|scope| {
+                for slot in slots.iter() {
+                    unsafe { scope.defer_free(slot.swap_null(AcqRel, scope)) }
+                }
+            });
+            handle.collect_all();
+        },
+        ITERATIONS,
+    );
+}
@@ -0,0 +1,680 @@
+//! A lock-free bounded multi-producer multi-consumer channel.
+//!
+//! This is an implementation of Dmitry Vyukov's bounded MPMC queue. Messages are stored in a ring
+//! buffer of slots, and every slot carries a stamp that tells whether it is ready for writing or
+//! for reading in the current lap around the buffer. Senders and receivers synchronize through
+//! the stamps and two indices instead of chasing pointers, so a message takes a single CAS to send
+//! and another one to receive.
+//!
+//! The buffer is kept behind an [`Atomic`] and accessed within a pinned scope, just like the
+//! buffer of a [`deque`]. When the channel gets resized, the old buffer is closed, its messages
+//! are moved into a new buffer, and the old one is destroyed by the epoch GC once no thread can be
+//! accessing it anymore.
+//!
+//! The implementation is based on:
+//!
+//! 1. Bounded MPMC queue
+//!    <sup>[article][vyukov]</sup>
+//!
+//! [vyukov]: http://www.1024cores.net/home/lock-free-algorithms/queues/bounded-mpmc-queue
+//! [`Atomic`]: ../epoch/struct.Atomic.html
+//! [`deque`]: ../deque/index.html
+//!
+//! # Examples
+//!
+//! ```
+//! use coco::channel;
+//! use std::thread;
+//!
+//! let (tx, rx) = channel::bounded(16);
+//!
+//! let t = thread::spawn(move || {
+//!     for i in 0..100 {
+//!         // Wait for a free slot.
+//!         let mut msg = i;
+//!         while let Err(m) = tx.try_send(msg) {
+//!             msg = m;
+//!             thread::yield_now();
+//!         }
+//!     }
+//! });
+//!
+//! let mut received = 0;
+//! while received < 100 {
+//!     if let Some(i) = rx.try_recv() {
+//!         assert_eq!(i, received);
+//!         received += 1;
+//!     }
+//! }
+//! t.join().unwrap();
+//! ```
+
+use std::cmp;
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, fence};
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release, SeqCst};
+use std::thread;
+
+use epoch::{self, Atomic, Owned};
+
+/// Why an operation on a buffer didn't go through.
+enum Failure {
+    /// The buffer is full (when sending) or empty (when receiving).
+    Unavailable,
+    /// The buffer is closed because it's being replaced by a new one.
+    Closed,
+}
+
+/// A ring buffer of slots.
+struct Buffer<T> {
+    /// Pointer to the allocated memory for messages.
+    ptr: *mut T,
+    /// Stamps of the slots. If a stamp equals the tail index, the slot is ready for writing. If it
+    /// equals the head index plus one, the slot holds a message ready for reading.
+    stamps: Box<[AtomicUsize]>,
+    /// Capacity of the buffer.
+    cap: usize,
+    /// The smallest power of two greater than `cap`. When set in the tail, the buffer is closed.
+    mark_bit: usize,
+    /// Twice `mark_bit`. An index is the position in the buffer plus a multiple of `one_lap`,
+    /// which counts the laps around the buffer.
+    one_lap: usize,
+    /// The index of the next slot to receive from.
+    head: AtomicUsize,
+    /// The index of the next slot to send into, possibly marked with `mark_bit`.
+    tail: AtomicUsize,
+}
+
+// Dropping a buffer only deallocates the memory without touching the messages, so it may happen
+// on any thread, e.g. the one collecting the buffer as garbage.
+unsafe impl<T> Send for Buffer<T> {}
+
+impl<T> Buffer<T> {
+    /// Returns a new buffer with capacity `cap`.
+    fn new(cap: usize) -> Self {
+        let mut v = Vec::with_capacity(cap);
+        let ptr = v.as_mut_ptr();
+        mem::forget(v);
+
+        // Initially, every slot is ready for writing in the first lap.
+        let stamps = (0..cap).map(AtomicUsize::new).collect::<Vec<_>>().into_boxed_slice();
+
+        let mark_bit = (cap + 1).next_power_of_two();
+        Buffer {
+            ptr,
+            stamps,
+            cap,
+            mark_bit,
+            one_lap: mark_bit * 2,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the index following `index`, which points to the slot at `pos`.
+    fn next_index(&self, index: usize, pos: usize) -> usize {
+        if pos + 1 < self.cap {
+            // Same lap, next slot.
+            index + 1
+        } else {
+            // Next lap, first slot.
+            (index & !(self.one_lap - 1)).wrapping_add(self.one_lap)
+        }
+    }
+
+    /// Closes the buffer, so that no new messages can be sent into it.
+    ///
+    /// Senders that have already claimed a slot still finish writing into it.
+    fn close(&self) {
+        self.tail.fetch_or(self.mark_bit, SeqCst);
+    }
+
+    /// Attempts to send a message.
+    fn try_send(&self, msg: T) -> Result<(), (T, Failure)> {
+        let mut tail = self.tail.load(Relaxed);
+
+        loop {
+            if tail & self.mark_bit != 0 {
+                return Err((msg, Failure::Closed));
+            }
+
+            let pos = tail & (self.mark_bit - 1);
+            let stamp = self.stamps[pos].load(Acquire);
+
+            if stamp == tail {
+                // The slot is ready for writing. Try claiming it. This fails if the buffer has
+                // been closed in the meantime.
+                let next = self.next_index(tail, pos);
+                match self.tail.compare_exchange_weak(tail, next, SeqCst, Relaxed) {
+                    Ok(_) => {
+                        unsafe { ptr::write(self.ptr.add(pos), msg) }
+                        self.stamps[pos].store(tail + 1, Release);
+                        return Ok(());
+                    }
+                    Err(t) => tail = t,
+                }
+            } else if stamp.wrapping_add(self.one_lap) == tail + 1 {
+                // The slot still holds a message from the previous lap. If the head is a lap
+                // behind, the buffer is full.
+                fence(SeqCst);
+                let head = self.head.load(Relaxed);
+                if head.wrapping_add(self.one_lap) == tail {
+                    return Err((msg, Failure::Unavailable));
+                }
+                tail = self.tail.load(Relaxed);
+            } else {
+                // Another sender has claimed the slot, but hasn't written into it yet.
+                thread::yield_now();
+                tail = self.tail.load(Relaxed);
+            }
+        }
+    }
+
+    /// Attempts to receive a message.
+    ///
+    /// A closed buffer is reported as such only once it's empty.
+    fn try_recv(&self) -> Result<T, Failure> {
+        let mut head = self.head.load(Relaxed);
+
+        loop {
+            let pos = head & (self.mark_bit - 1);
+            let stamp = self.stamps[pos].load(Acquire);
+
+            if stamp == head + 1 {
+                // The slot holds a message. Try claiming it.
+                let next = self.next_index(head, pos);
+                match self.head.compare_exchange_weak(head, next, SeqCst, Relaxed) {
+                    Ok(_) => {
+                        let msg = unsafe { ptr::read(self.ptr.add(pos)) };
+                        self.stamps[pos].store(head.wrapping_add(self.one_lap), Release);
+                        return Ok(msg);
+                    }
+                    Err(h) => head = h,
+                }
+            } else if stamp == head {
+                // The slot is ready for writing. If the tail is here too, the buffer is empty.
+                fence(SeqCst);
+                let tail = self.tail.load(Relaxed);
+                if tail & !self.mark_bit == head {
+                    if tail & self.mark_bit != 0 {
+                        return Err(Failure::Closed);
+                    }
+                    return Err(Failure::Unavailable);
+                }
+                head = self.head.load(Relaxed);
+            } else {
+                // Another receiver has claimed the slot, but hasn't read from it yet.
+                thread::yield_now();
+                head = self.head.load(Relaxed);
+            }
+        }
+    }
+}
+
+impl<T> Drop for Buffer<T> {
+    fn drop(&mut self) {
+        unsafe {
+            drop(Vec::from_raw_parts(self.ptr, 0, self.cap));
+        }
+    }
+}
+
+/// The state shared by all senders and receivers.
+///
+/// The buffer pointer is tagged with 1 while the channel is being resized.
+struct Channel<T> {
+    buffer: Atomic<Buffer<T>>,
+}
+
+unsafe impl<T: Send> Send for Channel<T> {}
+unsafe impl<T: Send> Sync for Channel<T> {}
+
+impl<T> Channel<T> {
+    /// Returns a new channel with capacity `cap`.
+    fn new(cap: usize) -> Self {
+        assert!(cap > 0, "capacity must be positive");
+        Channel { buffer: Atomic::new(Buffer::new(cap)) }
+    }
+
+    /// Returns the capacity of the channel.
+    fn capacity(&self) -> usize {
+        epoch::pin(|scope| unsafe { self.buffer.load(Acquire, scope).deref().cap })
+    }
+
+    /// Attempts to send a message, returning it back if the channel is full.
+    fn try_send(&self, mut msg: T) -> Result<(), T> {
+        epoch::pin(|scope| loop {
+            let buffer = unsafe { self.buffer.load(Acquire, scope).deref() };
+            match buffer.try_send(msg) {
+                Ok(()) => return Ok(()),
+                Err((m, Failure::Unavailable)) => return Err(m),
+                Err((m, Failure::Closed)) => {
+                    // The buffer is being replaced. Wait for the new one.
+                    msg = m;
+                    thread::yield_now();
+                }
+            }
+        })
+    }
+
+    /// Attempts to receive a message, returning `None` if the channel is empty.
+    fn try_recv(&self) -> Option<T> {
+        epoch::pin(|scope| loop {
+            let buffer = unsafe { self.buffer.load(Acquire, scope).deref() };
+            match buffer.try_recv() {
+                Ok(msg) => return Some(msg),
+                Err(Failure::Unavailable) => return None,
+                Err(Failure::Closed) => {
+                    // The messages are being moved into a new buffer. Wait for it.
+                    thread::yield_now();
+                }
+            }
+        })
+    }
+
+    /// Replaces the buffer with one of capacity `cap`, moving the messages over.
+    fn resize(&self, cap: usize) {
+        assert!(cap > 0, "capacity must be positive");
+
+        epoch::pin(|scope| {
+            // Lock the channel by tagging the buffer pointer, so that only one thread resizes it.
+            let mut old = self.buffer.load(Acquire, scope);
+            loop {
+                if old.tag() == 0 {
+                    match self.buffer.compare_and_swap_weak(old, old.with_tag(1), AcqRel, scope) {
+                        Ok(()) => break,
+                        Err(p) => old = p,
+                    }
+                } else {
+                    thread::yield_now();
+                    old = self.buffer.load(Acquire, scope);
+                }
+            }
+            let buffer = unsafe { old.deref() };
+
+            // Stop new messages from coming in and move out the ones that are already there.
+            // Receivers might take some of them concurrently, which is fine.
+            buffer.close();
+            let mut msgs = Vec::new();
+            while let Ok(msg) = buffer.try_recv() {
+                msgs.push(msg);
+            }
+
+            // No message may be lost, so the new buffer must have room for all of them.
+            let new = Buffer::new(cmp::max(cap, msgs.len()));
+            for msg in msgs {
+                assert!(new.try_send(msg).is_ok());
+            }
+
+            // Install the new buffer, which also unlocks the channel.
+            self.buffer.store_owned(Owned::new(new), Release);
+
+            unsafe {
+                // Destroy the old buffer later.
+                scope.defer_drop(old);
+
+                // If the size of the buffer at least 1KB, then flush the thread-local garbage in
+                // order to destroy it sooner.
+                if mem::size_of::<T>() * buffer.cap >= 1 << 10 {
+                    scope.flush();
+                }
+            }
+        })
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        unsafe {
+            epoch::unprotected(|scope| {
+                let buffer = self.buffer.load(Relaxed, scope);
+
+                // Drop the messages still in the channel. The buffer itself doesn't own them.
+                while buffer.deref().try_recv().is_ok() {}
+
+                // Hand the buffer over to the epoch GC. If the current thread can't be pinned
+                // anymore (e.g. because it's exiting), nobody else can access the channel, so the
+                // buffer can be destroyed right away.
+                if epoch::try_pin(|scope| scope.defer_drop(buffer)).is_none() {
+                    drop(buffer.into_box());
+                }
+            })
+        }
+    }
+}
+
+/// The sending side of a channel.
+///
+/// Senders may be cloned in order to send from multiple threads.
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Attempts to send a message into the channel.
+    ///
+    /// If the channel is full, the message is returned back as an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::channel;
+    ///
+    /// let (tx, _rx) = channel::bounded(1);
+    /// assert_eq!(tx.try_send(1), Ok(()));
+    /// assert_eq!(tx.try_send(2), Err(2));
+    /// ```
+    pub fn try_send(&self, msg: T) -> Result<(), T> {
+        self.channel.try_send(msg)
+    }
+
+    /// Returns the capacity of the channel.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::channel;
+    ///
+    /// let (tx, _rx) = channel::bounded::<i32>(10);
+    /// assert_eq!(tx.capacity(), 10);
+    /// ```
+    pub fn capacity(&self) -> usize {
+        self.channel.capacity()
+    }
+
+    /// Changes the capacity of the channel to `cap`.
+    ///
+    /// The messages in the channel are moved into a new buffer, and the old buffer is destroyed by
+    /// the epoch GC. No message is ever lost: if the channel holds more than `cap` messages, the
+    /// capacity is set to the number of messages instead. While the channel is being resized,
+    /// other senders and receivers wait for the new buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cap` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::channel;
+    ///
+    /// let (tx, rx) = channel::bounded(1);
+    /// tx.try_send(1).unwrap();
+    /// assert_eq!(tx.try_send(2), Err(2));
+    ///
+    /// tx.resize(2);
+    /// assert_eq!(tx.try_send(2), Ok(()));
+    /// assert_eq!(rx.try_recv(), Some(1));
+    /// assert_eq!(rx.try_recv(), Some(2));
+    /// ```
+    pub fn resize(&self, cap: usize) {
+        self.channel.resize(cap)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender { channel: self.channel.clone() }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sender {{ ... }}")
+    }
+}
+
+/// The receiving side of a channel.
+///
+/// Receivers may be cloned in order to receive from multiple threads.
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Attempts to receive a message from the channel.
+    ///
+    /// Returns `None` if the channel is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::channel;
+    ///
+    /// let (tx, rx) = channel::bounded(1);
+    /// assert_eq!(rx.try_recv(), None);
+    /// tx.try_send(1).unwrap();
+    /// assert_eq!(rx.try_recv(), Some(1));
+    /// ```
+    pub fn try_recv(&self) -> Option<T> {
+        self.channel.try_recv()
+    }
+
+    /// Returns the capacity of the channel.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::channel;
+    ///
+    /// let (_tx, rx) = channel::bounded::<i32>(10);
+    /// assert_eq!(rx.capacity(), 10);
+    /// ```
+    pub fn capacity(&self) -> usize {
+        self.channel.capacity()
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        Receiver { channel: self.channel.clone() }
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Receiver {{ ... }}")
+    }
+}
+
+/// Creates a channel that can hold at most `cap` messages at a time.
+///
+/// Returns the sending and the receiving side, which can both be cloned.
+///
+/// # Panics
+///
+/// Panics if `cap` is zero.
+///
+/// # Examples
+///
+/// ```
+/// use coco::channel;
+///
+/// let (tx, rx) = channel::bounded(2);
+/// tx.try_send("hello").unwrap();
+/// assert_eq!(rx.try_recv(), Some("hello"));
+/// ```
+pub fn bounded<T>(cap: usize) -> (Sender<T>, Receiver<T>) {
+    let channel = Arc::new(Channel::new(cap));
+    let tx = Sender { channel: channel.clone() };
+    let rx = Receiver { channel };
+    (tx, rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize};
+    use std::sync::atomic::Ordering::SeqCst;
+    use std::thread;
+
+    use epoch;
+    use super::{Receiver, bounded};
+
+    #[test]
+    fn smoke() {
+        let (tx, rx) = bounded(3);
+        assert_eq!(rx.try_recv(), None);
+
+        for lap in 0..10 {
+            for i in 0..3 {
+                assert_eq!(tx.try_send(lap * 3 + i), Ok(()));
+            }
+            assert_eq!(tx.try_send(100), Err(100));
+
+            for i in 0..3 {
+                assert_eq!(rx.try_recv(), Some(lap * 3 + i));
+            }
+            assert_eq!(rx.try_recv(), None);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be positive")]
+    fn zero_capacity() {
+        bounded::<i32>(0);
+    }
+
+    #[test]
+    fn resize() {
+        let (tx, rx) = bounded(2);
+        tx.try_send(1).unwrap();
+        assert_eq!(rx.try_recv(), Some(1));
+        tx.try_send(2).unwrap();
+        tx.try_send(3).unwrap();
+
+        // Grow, keeping the order of messages.
+        tx.resize(4);
+        assert_eq!(rx.capacity(), 4);
+        tx.try_send(4).unwrap();
+        tx.try_send(5).unwrap();
+        assert_eq!(tx.try_send(6), Err(6));
+
+        // Shrinking below the number of messages keeps all of them.
+        tx.resize(1);
+        assert_eq!(tx.capacity(), 4);
+        for i in 2..6 {
+            assert_eq!(rx.try_recv(), Some(i));
+        }
+        assert_eq!(rx.try_recv(), None);
+
+        tx.resize(1);
+        assert_eq!(tx.capacity(), 1);
+        tx.try_send(7).unwrap();
+        assert_eq!(tx.try_send(8), Err(8));
+        assert_eq!(rx.try_recv(), Some(7));
+    }
+
+    #[test]
+    fn drop_messages() {
+        struct Elem(Arc<AtomicUsize>);
+
+        impl Drop for Elem {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, SeqCst);
+            }
+        }
+
+        let drops = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = bounded(10);
+        for _ in 0..7 {
+            assert!(tx.try_send(Elem(drops.clone())).is_ok());
+        }
+        drop(rx.try_recv());
+        assert_eq!(drops.load(SeqCst), 1);
+
+        // Moving messages into a new buffer doesn't drop them.
+        tx.resize(20);
+        assert_eq!(drops.load(SeqCst), 1);
+        epoch::collect_all();
+        assert_eq!(drops.load(SeqCst), 1);
+
+        drop(tx);
+        drop(rx);
+        assert_eq!(drops.load(SeqCst), 7);
+    }
+
+    #[test]
+    fn drop_in_tls_destructor() {
+        thread_local! {
+            static RX: RefCell<Option<Receiver<Box<i32>>>> = const { RefCell::new(None) };
+        }
+
+        // The channel gets dropped while thread-locals are being destroyed, when the thread might
+        // not be able to pin anymore.
+        thread::spawn(|| {
+            let (tx, rx) = bounded(4);
+            tx.try_send(Box::new(1)).unwrap();
+            RX.with(|r| *r.borrow_mut() = Some(rx));
+        }).join().unwrap();
+    }
+
+    #[test]
+    fn mpmc() {
+        const THREADS: usize = 4;
+        const COUNT: usize = 25_000;
+
+        let (tx, rx) = bounded::<usize>(8);
+        let counts = Arc::new((0..COUNT).map(|_| AtomicUsize::new(0)).collect::<Vec<_>>());
+        let done = Arc::new(AtomicBool::new(false));
+
+        let receivers = (0..THREADS).map(|_| {
+            let rx = rx.clone();
+            let counts = counts.clone();
+            thread::spawn(move || {
+                for _ in 0..COUNT {
+                    loop {
+                        if let Some(n) = rx.try_recv() {
+                            counts[n].fetch_add(1, SeqCst);
+                            break;
+                        }
+                        thread::yield_now();
+                    }
+                }
+            })
+        }).collect::<Vec<_>>();
+
+        let senders = (0..THREADS).map(|_| {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                for i in 0..COUNT {
+                    let mut msg = i;
+                    while let Err(m) = tx.try_send(msg) {
+                        msg = m;
+                        thread::yield_now();
+                    }
+                }
+            })
+        }).collect::<Vec<_>>();
+
+        // Keep replacing the buffer while messages are in flight.
+        let resizer = {
+            let tx = tx.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                let mut cap = 1;
+                while !done.load(SeqCst) {
+                    tx.resize(cap);
+                    cap = cap % 16 + 1;
+                    thread::yield_now();
+                }
+            })
+        };
+
+        for t in senders.into_iter().chain(receivers) {
+            t.join().unwrap();
+        }
+        done.store(true, SeqCst);
+        resizer.join().unwrap();
+
+        // Every message was received exactly once.
+        for c in counts.iter() {
+            assert_eq!(c.load(SeqCst), THREADS);
+        }
+        assert_eq!(rx.try_recv(), None);
+    }
+}
@@ -0,0 +1,391 @@
+//! A global allocator for tests that catches memory-safety violations.
+//!
+//! Concurrent data structures reclaiming memory through the epoch GC are prone to subtle bugs that
+//! don't necessarily crash a test: an object might be freed twice, or written to after it was
+//! freed. This allocator wraps the system allocator and checks every deallocation:
+//!
+//! * Every live allocation is recorded along with its size. Freeing an address that isn't live,
+//!   e.g. because it was already freed, aborts the process. So does freeing with a wrong size.
+//! * Freed memory is filled with a poison pattern, so that code reading it after it was freed
+//!   sees obviously bogus values. It is then kept in quarantine for a while instead of being
+//!   returned to the system immediately.
+//! * When memory leaves the quarantine, it is checked that the poison pattern is still intact. If
+//!   it isn't, somebody has written into it after it was freed, and the process aborts.
+//!
+//! The number of live allocations can be queried by [`live_allocations`], and [`poison_intact`]
+//! inspects a freed allocation while it's in quarantine. To detect leaks, a test can run its body
+//! through [`check_leaks`], which fails with a report of the blocks the body allocated but didn't
+//! free by the end of the test.
+//!
+//! Unit tests of this crate always run with this allocator. Other test crates can install it
+//! themselves when the `internals` feature is enabled.
+//!
+//! All bookkeeping is done under a single global lock and in fixed-size tables, so that the
+//! allocator itself never allocates. If the table of live allocations overflows, double frees can
+//! no longer be detected reliably, so the check is turned off.
+//!
+//! [`live_allocations`]: fn.live_allocations.html
+//! [`poison_intact`]: fn.poison_intact.html
+//! [`check_leaks`]: fn.check_leaks.html
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::{Cell, UnsafeCell};
+use std::io::{self, Write};
+use std::process;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::thread;
+
+use epoch;
+
+/// Base-2 logarithm of the number of slots in the table of live allocations.
+const TABLE_BITS: usize = 20;
+/// Number of slots in the table of live allocations.
+const TABLE_SIZE: usize = 1 << TABLE_BITS;
+/// Number of freed allocations kept in quarantine.
+const QUARANTINE_SIZE: usize = 1 << 14;
+/// The byte freed memory is filled with.
+const POISON: u8 = 0xdd;
+/// Maximum number of leaked blocks listed in a leak report.
+const MAX_REPORTED: usize = 16;
+
+/// Bookkeeping of the allocator.
+struct State {
+    /// Live allocations as `(address, size, owner)`, in an open-addressing hash table. Empty
+    /// slots hold address zero. The owner is the leak check the allocation was made under, or zero.
+    table: [(usize, usize, usize); TABLE_SIZE],
+    /// Number of live allocations in the table.
+    len: usize,
+    /// Whether some allocations couldn't be recorded because the table was too full.
+    overflowed: bool,
+    /// Freed allocations as `(address, size, align)`, in a ring buffer.
+    quarantine: [(usize, usize, usize); QUARANTINE_SIZE],
+    /// Index of the next slot in the quarantine to be used.
+    next: usize,
+}
+
+/// The bookkeeping, protected by a spinlock.
+struct Locked {
+    lock: AtomicBool,
+    state: UnsafeCell<State>,
+}
+
+unsafe impl Sync for Locked {}
+
+static STATE: Locked = Locked {
+    lock: AtomicBool::new(false),
+    state: UnsafeCell::new(State {
+        table: [(0, 0, 0); TABLE_SIZE],
+        len: 0,
+        overflowed: false,
+        quarantine: [(0, 0, 0); QUARANTINE_SIZE],
+        next: 0,
+    }),
+};
+
+thread_local! {
+    /// The leak check allocations made by this thread are attributed to, or zero if there is none.
+    ///
+    /// It has no destructor, so the allocator can access it even while the thread is exiting.
+    static OWNER: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Source of unique identifiers of leak checks.
+static NEXT_OWNER: AtomicUsize = AtomicUsize::new(1);
+
+/// Runs `f` with exclusive access to the bookkeeping.
+fn with_state<R, F: FnOnce(&mut State) -> R>(f: F) -> R {
+    while STATE.lock.compare_exchange_weak(false, true, Acquire, Acquire).is_err() {
+        // The holder might be preempted, so don't burn the whole time slice.
+        thread::yield_now();
+    }
+    let r = f(unsafe { &mut *STATE.state.get() });
+    STATE.lock.store(false, Release);
+    r
+}
+
+/// Reports a memory-safety violation and aborts the process.
+///
+/// Unwinding out of an allocator is not allowed, so panicking is not an option.
+fn fail(msg: &str, addr: usize) -> ! {
+    let _ = writeln!(io::stderr(), "checked_alloc: {} at address {:#x}", msg, addr);
+    process::abort();
+}
+
+/// Returns the preferred slot of `addr` in the table.
+fn slot(addr: usize) -> usize {
+    ((addr as u64 >> 4).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (64 - TABLE_BITS)) as usize
+}
+
+impl State {
+    /// Records a live allocation.
+    fn insert(&mut self, addr: usize, size: usize, owner: usize) {
+        // Keep the table sparse so that probe sequences stay short.
+        if self.len >= TABLE_SIZE / 4 * 3 {
+            self.overflowed = true;
+            return;
+        }
+
+        let mut i = slot(addr);
+        while self.table[i].0 != 0 {
+            i = (i + 1) % TABLE_SIZE;
+        }
+        self.table[i] = (addr, size, owner);
+        self.len += 1;
+    }
+
+    /// Removes a live allocation and returns its size, or `None` if it isn't recorded.
+    fn remove(&mut self, addr: usize) -> Option<usize> {
+        let mut i = slot(addr);
+        loop {
+            match self.table[i] {
+                (0, _, _) => return None,
+                (a, size, _) if a == addr => {
+                    self.vacate(i);
+                    self.len -= 1;
+                    return Some(size);
+                }
+                _ => i = (i + 1) % TABLE_SIZE,
+            }
+        }
+    }
+
+    /// Empties slot `i`, shifting following entries back so that no probe sequence gets broken.
+    fn vacate(&mut self, mut i: usize) {
+        let mut j = i;
+        loop {
+            j = (j + 1) % TABLE_SIZE;
+            let (addr, _, _) = self.table[j];
+            if addr == 0 {
+                self.table[i] = (0, 0, 0);
+                return;
+            }
+
+            // The entry can stay if its preferred slot lies cyclically within `(i, j]`.
+            let k = slot(addr);
+            let stays = if i <= j { i < k && k <= j } else { i < k || k <= j };
+            if !stays {
+                self.table[i] = self.table[j];
+                i = j;
+            }
+        }
+    }
+}
+
+/// Returns the number of live allocations.
+///
+/// The number is only approximate if the table of live allocations has overflowed.
+pub fn live_allocations() -> usize {
+    with_state(|s| s.len)
+}
+
+/// Returns `true` if `ptr` is the address of a live allocation.
+pub fn is_live(ptr: *const u8) -> bool {
+    with_state(|s| {
+        let mut i = slot(ptr as usize);
+        loop {
+            match s.table[i].0 {
+                0 => return false,
+                a if a == ptr as usize => return true,
+                _ => i = (i + 1) % TABLE_SIZE,
+            }
+        }
+    })
+}
+
+/// Returns whether the freed allocation at `ptr` still holds the poison pattern, or `None` if it
+/// isn't in quarantine.
+///
+/// The memory is inspected under the lock, so it can't leave the quarantine in the meantime.
+pub fn poison_intact(ptr: *const u8) -> Option<bool> {
+    with_state(|s| {
+        // Search from the most recently freed allocation backwards.
+        for k in 1..=QUARANTINE_SIZE {
+            let (addr, size, _) = s.quarantine[(s.next + QUARANTINE_SIZE - k) % QUARANTINE_SIZE];
+            if addr == ptr as usize {
+                let block = addr as *const u8;
+                return Some((0..size).all(|i| unsafe { *block.add(i) } == POISON));
+            }
+        }
+        None
+    })
+}
+
+/// Returns the number of live allocations made under leak check `owner`, and the first few of
+/// them as `(address, size)`.
+fn leaked(owner: usize) -> (usize, [(usize, usize); MAX_REPORTED]) {
+    with_state(|s| {
+        let mut count = 0;
+        let mut blocks = [(0, 0); MAX_REPORTED];
+        for &(addr, size, o) in s.table.iter() {
+            if addr != 0 && o == owner {
+                if count < MAX_REPORTED {
+                    blocks[count] = (addr, size);
+                }
+                count += 1;
+            }
+        }
+        (count, blocks)
+    })
+}
+
+/// Runs `f`, and panics with a report of the blocks it has allocated but not freed.
+///
+/// Only allocations made by the current thread are checked. Objects deferred for destruction
+/// aren't considered leaked: after `f` returns, garbage is collected until either all its
+/// allocations are freed or no more progress can be made. Allocations the epoch GC makes for its
+/// own bookkeeping, like bags of garbage, are not checked either.
+///
+/// Blocks that are supposed to outlive `f`, e.g. in lazily initialized statics, are reported as
+/// leaked too, so they should be initialized before calling this. Allocations made while the table
+/// of live allocations is overflowed are not tracked, so their leaks go unnoticed.
+pub fn check_leaks<R, F: FnOnce() -> R>(f: F) -> R {
+    /// Number of attempts at collecting garbage before giving up.
+    const ATTEMPTS: usize = 100;
+
+    // Register the thread with the epoch GC, so that its bookkeeping isn't attributed to `f`.
+    epoch::pin(|scope| scope.flush());
+
+    let owner = NEXT_OWNER.fetch_add(1, Relaxed);
+    let previous = OWNER.with(|o| o.replace(owner));
+    let r = {
+        // This will stop tracking even if `f` panics.
+        defer! {
+            OWNER.with(|o| o.set(previous))
+        }
+        f()
+    };
+
+    let mut attempts = 0;
+    loop {
+        let (count, blocks) = leaked(owner);
+        if count == 0 {
+            return r;
+        }
+
+        if attempts == ATTEMPTS {
+            let mut report = format!("checked_alloc: leaked {} blocks", count);
+            for &(addr, size) in blocks.iter().take(count) {
+                report.push_str(&format!("\n    {} bytes at address {:#x}", size, addr));
+            }
+            if count > MAX_REPORTED {
+                report.push_str("\n    ...");
+            }
+            panic!("{}", report);
+        }
+
+        // Some of the blocks may be garbage waiting for the epoch to advance.
+        epoch::collect_all();
+        thread::yield_now();
+        attempts += 1;
+    }
+}
+
+/// Runs `f` without attributing its allocations to the current leak check, if any.
+///
+/// This is meant for caches that legitimately outlive the code being checked.
+pub fn untracked<R, F: FnOnce() -> R>(f: F) -> R {
+    let previous = OWNER.with(|o| o.replace(0));
+    defer! {
+        OWNER.with(|o| o.set(previous))
+    }
+    f()
+}
+
+/// The checking allocator.
+pub struct CheckedAlloc;
+
+unsafe impl GlobalAlloc for CheckedAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() && layout.size() > 0 {
+            let owner = OWNER.try_with(|o| o.get()).unwrap_or(0);
+            with_state(|s| s.insert(ptr as usize, layout.size(), owner));
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let addr = ptr as usize;
+        let size = layout.size();
+        if size == 0 {
+            System.dealloc(ptr, layout);
+            return;
+        }
+
+        let evicted = with_state(|s| {
+            match s.remove(addr) {
+                Some(n) if n != size => return Err("deallocation with a wrong size"),
+                Some(_) => {}
+                None if !s.overflowed => return Err("double free or free of an unknown pointer"),
+                None => {}
+            }
+
+            ptr::write_bytes(ptr, POISON, size);
+
+            let evicted = s.quarantine[s.next];
+            s.quarantine[s.next] = (addr, size, layout.align());
+            s.next = (s.next + 1) % QUARANTINE_SIZE;
+            Ok(evicted)
+        });
+
+        match evicted {
+            Err(msg) => fail(msg, addr),
+            Ok((0, _, _)) => {}
+            Ok((addr, size, align)) => {
+                let ptr = addr as *mut u8;
+                for i in 0..size {
+                    if *ptr.add(i) != POISON {
+                        fail("write after free", addr + i);
+                    }
+                }
+                System.dealloc(ptr, Layout::from_size_align_unchecked(size, align));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+
+    use epoch::{self, Owned};
+    use super::{check_leaks, is_live, live_allocations, poison_intact};
+
+    #[test]
+    fn tracks_allocations() {
+        let b = Box::new([7u8; 64]);
+        let p = &*b as *const [u8; 64] as *const u8;
+        assert!(is_live(p));
+        assert!(live_allocations() > 0);
+
+        drop(b);
+        assert!(!is_live(p));
+    }
+
+    #[test]
+    fn poisons_freed_memory() {
+        let b = Box::new([7u8; 64]);
+        let p = &*b as *const [u8; 64] as *const u8;
+        assert_eq!(poison_intact(p), None);
+
+        drop(b);
+        assert_eq!(poison_intact(p), Some(true));
+    }
+
+    #[test]
+    #[should_panic(expected = "leaked 1 blocks")]
+    fn reports_leaks() {
+        check_leaks(|| mem::forget(Box::new([7u8; 64])));
+    }
+
+    #[test]
+    fn deferred_garbage_is_not_leaked() {
+        check_leaks(|| {
+            epoch::pin(|scope| unsafe {
+                scope.defer_drop(Owned::new([7u8; 64]).into_ptr(scope));
+            })
+        });
+    }
+}
@@ -104,6 +104,10 @@ impl<T> Buffer<T> {
     }
 }
 
+// Dropping a buffer only deallocates the memory without touching the elements, so it may happen
+// on any thread, e.g. the one collecting the buffer as garbage.
+unsafe impl<T> Send for Buffer<T> {}
+
 impl<T> Drop for Buffer<T> {
     fn drop(&mut self) {
         unsafe {
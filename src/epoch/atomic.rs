@@ -1,8 +1,9 @@
 use std::marker::PhantomData;
 use std::mem;
 use std::ops::{Deref, DerefMut};
+use std::ptr;
 use std::sync::atomic::AtomicUsize;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::Ordering::{self, AcqRel, Acquire, Release};
 
 use epoch::Scope;
 
@@ -21,12 +22,41 @@ fn strongest_failure_ordering(ord: Ordering) -> Ordering {
     }
 }
 
+/// In debug builds, panics if `ord` is too weak for installing a newly allocated object.
+///
+/// Other threads may read the object as soon as they load the pointer to it, so the operation
+/// installing it must have at least `Release` semantics. Otherwise the reads could race with the
+/// initialization of the object.
+#[inline]
+fn debug_assert_publishing(ord: Ordering) {
+    debug_assert!(
+        ord != Ordering::Relaxed && ord != Acquire,
+        "installing an `Owned` requires `Release`, `AcqRel`, or `SeqCst` ordering, got `{:?}`",
+        ord
+    );
+}
+
 /// Panics if the pointer is not properly unaligned.
 #[inline]
 fn ensure_aligned<T>(raw: *const T) {
     assert!(raw as usize & low_bits::<T>() == 0, "unaligned pointer");
 }
 
+/// A static check that `T` is not a zero-sized type.
+///
+/// Zero-sized objects are never really allocated, so they cannot be stored behind atomic pointers
+/// and reclaimed by the epoch GC.
+struct NotZeroSized<T>(PhantomData<T>);
+
+impl<T> NotZeroSized<T> {
+    /// Evaluating this constant fails to compile if `T` is zero-sized.
+    const ASSERT: () = assert!(
+        mem::size_of::<T>() != 0,
+        "zero-sized types cannot be allocated behind atomic pointers; use `AtomicUsize` or the \
+         tag of an `Atomic` instead"
+    );
+}
+
 /// Returns a bitmask containing the unused least significant bits of an aligned pointer to `T`.
 #[inline]
 fn low_bits<T>() -> usize {
@@ -52,6 +82,10 @@ fn data_with_tag<T>(data: usize, tag: usize) -> usize {
 /// [`Scope`]: struct.Scope.html
 #[derive(Debug)]
 pub struct Atomic<T> {
+    /// The tagged pointer.
+    ///
+    /// This is an `AtomicUsize` rather than an `AtomicPtr<T>` because any pointer may carry a tag,
+    /// so there is no untagged case to specialize.
     data: AtomicUsize,
     _marker: PhantomData<*mut T>,
 }
@@ -98,6 +132,8 @@ impl<T> Atomic<T> {
 
     /// Allocates `value` on the heap and returns a new atomic pointer pointing to it.
     ///
+    /// Zero-sized types are rejected at compile time, since they cannot be allocated.
+    ///
     /// # Examples
     ///
     /// ```
@@ -105,10 +141,39 @@ impl<T> Atomic<T> {
     ///
     /// let a = Atomic::new(1234);
     /// ```
+    ///
+    /// ```compile_fail
+    /// use coco::epoch::Atomic;
+    ///
+    /// let a = Atomic::new(());
+    /// ```
     pub fn new(value: T) -> Self {
         Self::from_owned(Owned::new(value))
     }
 
+    /// Returns a new atomic pointer taking over the allocation owned by `b`.
+    ///
+    /// Unlike [`new`], this doesn't allocate or move the value, which is useful for moving large
+    /// heap nodes from one data structure to another.
+    ///
+    /// [`new`]: struct.Atomic.html#method.new
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let b = Box::new([0u8; 4096]);
+    /// let raw = &*b as *const [u8; 4096];
+    ///
+    /// let a = Atomic::new_boxed(b);
+    /// epoch::pin(|scope| assert_eq!(a.load(SeqCst, scope).as_raw(), raw));
+    /// ```
+    pub fn new_boxed(b: Box<T>) -> Self {
+        Self::from_owned(Owned::from_box(b))
+    }
+
     /// Returns a new atomic pointer pointing to `owned`.
     ///
     /// # Examples
@@ -137,6 +202,52 @@ impl<T> Atomic<T> {
         Self::from_data(ptr.data)
     }
 
+    /// Consumes the atomic pointer and returns its raw encoded word.
+    ///
+    /// The word contains both the pointer and the tag. The pointee is neither dropped nor
+    /// reclaimed; ownership of the whole cell is transferred to the caller, who can rebuild it
+    /// later using [`Atomic::from_raw_atomic`].
+    ///
+    /// [`Atomic::from_raw_atomic`]: struct.Atomic.html#method.from_raw_atomic
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{Atomic, Owned};
+    ///
+    /// let a = Atomic::from_owned(Owned::new(1234).with_tag(1));
+    /// let word = a.into_raw();
+    /// assert_eq!(word & 1, 1);
+    ///
+    /// let a = unsafe { Atomic::<i32>::from_raw_atomic(word) };
+    /// # drop(a);
+    /// ```
+    pub fn into_raw(self) -> usize {
+        self.data.into_inner()
+    }
+
+    /// Rebuilds an atomic pointer from a raw encoded word.
+    ///
+    /// # Safety
+    ///
+    /// The word must have been returned by [`Atomic::into_raw`] on an `Atomic<T>` of the same
+    /// type. Exactly one `Atomic` may own the word at a time: rebuilding it twice lets both cells
+    /// unlink and reclaim the same pointee, which is a double free.
+    ///
+    /// [`Atomic::into_raw`]: struct.Atomic.html#method.into_raw
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::Atomic;
+    ///
+    /// let word = Atomic::<i32>::null().into_raw();
+    /// let a = unsafe { Atomic::<i32>::from_raw_atomic(word) };
+    /// ```
+    pub unsafe fn from_raw_atomic(word: usize) -> Self {
+        Self::from_data(word)
+    }
+
     /// Loads a `Ptr` from the atomic pointer.
     ///
     /// This method takes an [`Ordering`] argument which describes the memory ordering of this
@@ -159,6 +270,269 @@ impl<T> Atomic<T> {
         Ptr::from_data(self.data.load(ord))
     }
 
+    /// Loads the pointer and dereferences it without checking for null.
+    ///
+    /// The tag is ignored. This is meant for the hottest loads in the internals of a data
+    /// structure, where the pointer is known to be non-null by construction, e.g. the head of a
+    /// list that always starts with a sentinel node. Unlike `load(ord, scope).as_ref()`, there is
+    /// no branch on null. In debug builds a null pointer is still caught with a panic.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// # Safety
+    ///
+    /// The atomic pointer must not be null, and the object it points to must stay alive for the
+    /// lifetime of `scope`, i.e. replaced objects may only be destroyed through the epoch GC.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic};
+    /// use std::sync::atomic::Ordering::Acquire;
+    ///
+    /// let a = Atomic::new(1234);
+    /// epoch::pin(|scope| {
+    ///     assert_eq!(unsafe { *a.load_unchecked(Acquire, scope) }, 1234);
+    /// });
+    /// ```
+    #[inline]
+    pub unsafe fn load_unchecked<'scope>(&self, ord: Ordering, _: &'scope Scope) -> &'scope T {
+        let raw = (self.data.load(ord) & !low_bits::<T>()) as *const T;
+        debug_assert!(!raw.is_null(), "`load_unchecked` on a null pointer");
+        &*raw
+    }
+
+    /// Loads a `Ptr` from the atomic pointer using `Acquire` ordering.
+    ///
+    /// This is the right ordering for most loads: it synchronizes with the store that published
+    /// the pointed-to object, so the object can be safely read through the returned pointer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic};
+    ///
+    /// let a = Atomic::new(1234);
+    /// epoch::pin(|scope| {
+    ///     let p = a.load_acquire(scope);
+    ///     assert_eq!(unsafe { p.as_ref() }, Some(&1234));
+    /// });
+    /// ```
+    pub fn load_acquire<'scope>(&self, scope: &'scope Scope) -> Ptr<'scope, T> {
+        self.load(Acquire, scope)
+    }
+
+    /// Loads a `Ptr` from the atomic pointer and checks whether bit `bit` of its tag is set.
+    ///
+    /// Linked structures commonly mark a node as logically deleted by setting a bit in the tag of
+    /// its next-pointer. This loads the next-pointer and tells whether the node is marked in a
+    /// single call.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bit` doesn't fit into the unused bits of an aligned pointer to `T`.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::<u64>::from_ptr(Ptr::null().with_tag(2));
+    /// epoch::pin(|scope| {
+    ///     assert!(!a.load_marked(0, SeqCst, scope).1);
+    ///     assert!(a.load_marked(1, SeqCst, scope).1);
+    /// });
+    /// ```
+    pub fn load_marked<'scope>(
+        &self,
+        bit: u32,
+        ord: Ordering,
+        scope: &'scope Scope,
+    ) -> (Ptr<'scope, T>, bool) {
+        let mask = 1usize.checked_shl(bit).unwrap_or(0);
+        assert!(mask != 0 && mask & low_bits::<T>() == mask, "tag bit {} doesn't fit", bit);
+        let ptr = self.load(ord, scope);
+        (ptr, ptr.tag() & mask != 0)
+    }
+
+    /// Loads a `Ptr` from the atomic pointer and splits it into the untagged pointer and the tag.
+    ///
+    /// Traversals of marked lists typically need both right away: the pointer for following the
+    /// link, and the tag for checking the mark.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic, Owned};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::null();
+    /// epoch::pin(|scope| {
+    ///     let p = Owned::new(1234u64).into_ptr(scope);
+    ///     a.store(p.with_tag(1), SeqCst);
+    ///
+    ///     let (ptr, tag) = a.load_split(SeqCst, scope);
+    ///     assert_eq!((ptr, tag), (p, 1));
+    ///     unsafe { drop(ptr.into_box()) }
+    /// });
+    /// ```
+    pub fn load_split<'scope>(&self, ord: Ordering, scope: &'scope Scope)
+                              -> (Ptr<'scope, T>, usize) {
+        let ptr = self.load(ord, scope);
+        (ptr.with_tag(0), ptr.tag())
+    }
+
+    /// Returns `true` if the atomic pointer currently points to `raw`, ignoring the tag.
+    ///
+    /// Only the address is compared and nothing is dereferenced, so the thread doesn't need to be
+    /// pinned. This is handy in assertions, e.g. checking that a compare-and-swap has installed the
+    /// expected node.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of the load.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::Atomic;
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let b = Box::new(1234);
+    /// let raw = &*b as *const i32;
+    ///
+    /// let a = Atomic::from(b);
+    /// assert!(a.points_to(raw, SeqCst));
+    /// ```
+    pub fn points_to(&self, raw: *const T, ord: Ordering) -> bool {
+        self.data.load(ord) & !low_bits::<T>() == raw as usize
+    }
+
+    /// Returns an iterator over the nodes of a linked list starting at this atomic pointer.
+    ///
+    /// The iterator yields the pointer loaded from this atomic, then the pointer loaded from the
+    /// link returned by `next` for that node, and so on until a null pointer is reached. All links
+    /// are loaded with `Acquire` ordering. Yielded pointers keep their tags, which linked
+    /// structures usually use to mark the node containing the link.
+    ///
+    /// # Safety
+    ///
+    /// Every non-null pointer reachable this way must point to a node that stays valid for the
+    /// lifetime of `scope`, i.e. nodes may only be destroyed through the epoch GC after being
+    /// unlinked.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic};
+    ///
+    /// struct Node {
+    ///     value: i32,
+    ///     next: Atomic<Node>,
+    /// }
+    ///
+    /// let head = Atomic::new(Node {
+    ///     value: 1,
+    ///     next: Atomic::new(Node { value: 2, next: Atomic::null() }),
+    /// });
+    ///
+    /// epoch::pin(|scope| unsafe {
+    ///     let values = head.iter(|n| &n.next, scope).map(|p| p.deref().value).collect::<Vec<_>>();
+    ///     assert_eq!(values, [1, 2]);
+    /// });
+    /// # epoch::pin(|scope| unsafe {
+    /// #     let first = head.load_acquire(scope);
+    /// #     drop(first.deref().next.load_acquire(scope).into_box());
+    /// #     drop(first.into_box());
+    /// # });
+    /// ```
+    pub unsafe fn iter<'scope, F>(&self, next: F, scope: &'scope Scope) -> Iter<'scope, T, F>
+    where
+        F: Fn(&'scope T) -> &'scope Atomic<T>,
+    {
+        Iter {
+            curr: self.load(Acquire, scope),
+            next,
+            scope,
+        }
+    }
+
+    /// Loads a raw pointer (without the tag) from the atomic pointer without pinning.
+    ///
+    /// Pinning protects the pointee from being destroyed while it is in use. If the data behind
+    /// this atomic is published once and never reclaimed, e.g. committed segments of an
+    /// append-only log, that protection is wasted work and this method can be used instead.
+    ///
+    /// The load uses `Acquire` ordering, so the pointee can be safely read through the returned
+    /// pointer.
+    ///
+    /// Dereferencing the returned pointer is safe only if no object ever stored into this atomic
+    /// is destroyed or deferred for destruction (e.g. using [`defer_free`]) while the program is
+    /// running.
+    ///
+    /// [`defer_free`]: struct.Scope.html#method.defer_free
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::Atomic;
+    ///
+    /// let a = Atomic::new(1234);
+    /// let p = a.load_immortal();
+    /// assert_eq!(unsafe { *p }, 1234);
+    /// ```
+    pub fn load_immortal(&self) -> *const T {
+        (self.data.load(Acquire) & !low_bits::<T>()) as *const T
+    }
+
+    /// Returns a pointer to the underlying atomic word.
+    ///
+    /// This is an escape hatch for interoperating with foreign code, e.g. when the atomic pointer
+    /// is shared with C or lives in a memory-mapped region.
+    ///
+    /// The word holds the address of the object, with the tag stored in the unused least
+    /// significant bits, of which there are `log2(align_of::<T>())`. Any value written through the
+    /// returned pointer must follow the same encoding and point to an object allocated by this
+    /// crate (or be null). Objects must still be reclaimed through this crate, e.g. with
+    /// [`defer_free`].
+    ///
+    /// [`defer_free`]: struct.Scope.html#method.defer_free
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234u64);
+    /// let word = unsafe { &*a.as_ptr() };
+    /// word.fetch_or(1, SeqCst);
+    ///
+    /// epoch::pin(|scope| {
+    ///     let p = a.load(SeqCst, scope);
+    ///     assert_eq!(p.tag(), 1);
+    ///     assert_eq!(unsafe { p.as_ref() }, Some(&1234));
+    /// #   unsafe { scope.defer_free(p) }
+    /// });
+    /// ```
+    pub fn as_ptr(&self) -> *const AtomicUsize {
+        &self.data
+    }
+
     /// Stores a `Ptr` into the atomic pointer.
     ///
     /// This method takes an [`Ordering`] argument which describes the memory ordering of this
@@ -184,6 +558,9 @@ impl<T> Atomic<T> {
     /// This method takes an [`Ordering`] argument which describes the memory ordering of this
     /// operation.
     ///
+    /// In debug builds, `Relaxed` and `Acquire` orderings are rejected with a panic, since they
+    /// would publish the new object without synchronizing with its initialization.
+    ///
     /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
     ///
     /// # Examples
@@ -196,13 +573,37 @@ impl<T> Atomic<T> {
     /// a.store_owned(Owned::new(1234), SeqCst);
     /// ```
     pub fn store_owned(&self, new: Owned<T>, ord: Ordering) {
+        debug_assert_publishing(ord);
         let data = new.data;
         mem::forget(new);
         self.data.store(data, ord);
     }
 
+    /// Publishes an `Owned` by storing it into the atomic pointer with `Release` ordering.
+    ///
+    /// This is the ordering almost every store of a freshly allocated object needs: any thread
+    /// that loads the pointer with `Acquire` is guaranteed to see the initialized object.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic, Owned};
+    /// use std::sync::atomic::Ordering::Acquire;
+    ///
+    /// let a = Atomic::null();
+    /// a.publish(Owned::new(1234));
+    /// epoch::pin(|scope| assert_eq!(unsafe { *a.load(Acquire, scope).deref() }, 1234));
+    /// ```
+    pub fn publish(&self, new: Owned<T>) {
+        self.store_owned(new, Release);
+    }
+
     /// Stores a `Ptr` into the atomic pointer, returning the previous `Ptr`.
     ///
+    /// The previous pointer is usually unlinked and can be deferred for destruction. However, if it
+    /// is the same as `new`, the object is still reachable through the atomic pointer and must not
+    /// be deferred.
+    ///
     /// This method takes an [`Ordering`] argument which describes the memory ordering of this
     /// operation.
     ///
@@ -219,10 +620,84 @@ impl<T> Atomic<T> {
     ///     let p = a.swap(Ptr::null(), SeqCst, scope);
     /// });
     /// ```
+    #[must_use = "the returned pointer may need to be unlinked and reclaimed"]
     pub fn swap<'scope>(&self, new: Ptr<T>, ord: Ordering, _: &'scope Scope) -> Ptr<'scope, T> {
         Ptr::from_data(self.data.swap(new.data, ord))
     }
 
+    /// Stores an `Owned` tagged with `tag` into the atomic pointer, returning the previous `Ptr`.
+    ///
+    /// The new pointer and the tag are installed in a single atomic exchange, so no other thread
+    /// can observe the new pointer without the tag. This is useful for logically deleting a cell
+    /// while replacing its contents.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// In debug builds, `Relaxed` and `Acquire` orderings are rejected with a panic, since they
+    /// would publish the new object without synchronizing with its initialization.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tag doesn't fit into the unused bits of the pointer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic, Owned};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234u64);
+    /// epoch::pin(|scope| {
+    ///     let old = a.swap_tagged(Owned::new(5678), 1, SeqCst, scope);
+    ///     assert_eq!(old.tag(), 0);
+    ///     assert_eq!(a.load(SeqCst, scope).tag(), 1);
+    ///     unsafe { scope.defer_free(old) }
+    /// });
+    /// ```
+    #[must_use = "the returned pointer may need to be unlinked and reclaimed"]
+    pub fn swap_tagged<'scope>(
+        &self,
+        new: Owned<T>,
+        tag: usize,
+        ord: Ordering,
+        scope: &'scope Scope,
+    ) -> Ptr<'scope, T> {
+        debug_assert_publishing(ord);
+        self.swap(new.with_tag(tag).into_ptr(scope), ord, scope)
+    }
+
+    /// Stores a null pointer into the atomic pointer, returning the previous `Ptr`.
+    ///
+    /// This is the usual way of removing an object from a single-slot cell. The caller becomes
+    /// responsible for reclaiming the returned pointer, typically using [`defer_free`].
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`defer_free`]: struct.Scope.html#method.defer_free
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    /// epoch::pin(|scope| {
+    ///     let p = a.swap_null(SeqCst, scope);
+    ///     assert!(a.load(SeqCst, scope).is_null());
+    ///     unsafe { scope.defer_free(p) }
+    /// });
+    /// ```
+    #[must_use = "the returned pointer may need to be unlinked and reclaimed"]
+    pub fn swap_null<'scope>(&self, ord: Ordering, _: &'scope Scope) -> Ptr<'scope, T> {
+        Ptr::from_data(self.data.swap(0, ord))
+    }
+
     /// Stores `new` into the atomic pointer if the current value is the same as `current`.
     ///
     /// The return value is a result indicating whether the new pointer was written. On failure the
@@ -305,15 +780,65 @@ impl<T> Atomic<T> {
         }
     }
 
-    /// Stores `new` into the atomic pointer if the current value is the same as `current`.
+    /// Unlinks the object `expected` points to by replacing it with null, and defers its
+    /// destruction.
     ///
-    /// The return value is a result indicating whether the new pointer was written. On success the
-    /// pointer that was written is returned. On failure `new` and the actual current value are
+    /// The object is deferred only if the compare-and-swap succeeds, and only after it, so it can't
+    /// be scheduled for destruction while it is still reachable through this atomic pointer. On
+    /// failure the actual current value is returned and nothing is deferred.
+    ///
+    /// The object is destroyed as if by [`defer_drop`].
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of the
+    /// compare-and-swap.
+    ///
+    /// # Safety
+    ///
+    /// The same rules apply as for [`defer_drop`]. In particular, the object must not be reachable
+    /// through any other atomic pointer.
+    ///
+    /// [`defer_drop`]: struct.Scope.html#method.defer_drop
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic};
+    /// use std::sync::atomic::Ordering::{AcqRel, Acquire};
+    ///
+    /// let a = Atomic::new(1234);
+    /// epoch::pin(|scope| unsafe {
+    ///     let p = a.load(Acquire, scope);
+    ///     assert!(a.unlink_and_defer(p, AcqRel, scope).is_ok());
+    ///     assert!(a.load(Acquire, scope).is_null());
+    /// });
+    /// ```
+    pub unsafe fn unlink_and_defer<'scope>(
+        &self,
+        expected: Ptr<'scope, T>,
+        ord: Ordering,
+        scope: &'scope Scope,
+    ) -> Result<(), Ptr<'scope, T>>
+    where
+        T: Send,
+    {
+        self.compare_and_swap(expected, Ptr::null(), ord, scope)?;
+        scope.defer_drop(expected);
+        Ok(())
+    }
+
+    /// Stores `new` into the atomic pointer if the current value is the same as `current`.
+    ///
+    /// The return value is a result indicating whether the new pointer was written. On success the
+    /// pointer that was written is returned. On failure `new` and the actual current value are
     /// returned.
     ///
     /// This method takes an [`Ordering`] argument which describes the memory ordering of this
     /// operation.
     ///
+    /// In debug builds, `Relaxed` and `Acquire` orderings are rejected with a panic, since they
+    /// would publish the new object without synchronizing with its initialization.
+    ///
     /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
     ///
     /// # Examples
@@ -334,15 +859,12 @@ impl<T> Atomic<T> {
         current: Ptr<T>,
         new: Owned<T>,
         ord: Ordering,
-        _: &'scope Scope,
+        scope: &'scope Scope,
     ) -> Result<Ptr<'scope, T>, (Ptr<'scope, T>, Owned<T>)> {
+        debug_assert_publishing(ord);
         let fail_ord = strongest_failure_ordering(ord);
         match self.data.compare_exchange(current.data, new.data, ord, fail_ord) {
-            Ok(_) => {
-                let data = new.data;
-                mem::forget(new);
-                Ok(Ptr::from_data(data))
-            }
+            Ok(_) => Ok(new.into_ptr(scope)),
             Err(previous) => Err((Ptr::from_data(previous), new)),
         }
     }
@@ -358,6 +880,9 @@ impl<T> Atomic<T> {
     /// This method takes an [`Ordering`] argument which describes the memory ordering of this
     /// operation.
     ///
+    /// In debug builds, `Relaxed` and `Acquire` orderings are rejected with a panic, since they
+    /// would publish the new object without synchronizing with its initialization.
+    ///
     /// [`compare_and_swap_owned`]: struct.Atomic.html#method.compare_and_swap_owned
     /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
     ///
@@ -391,27 +916,29 @@ impl<T> Atomic<T> {
         current: Ptr<T>,
         new: Owned<T>,
         ord: Ordering,
-        _: &'scope Scope,
+        scope: &'scope Scope,
     ) -> Result<Ptr<'scope, T>, (Ptr<'scope, T>, Owned<T>)> {
+        debug_assert_publishing(ord);
         let fail_ord = strongest_failure_ordering(ord);
         match self.data.compare_exchange_weak(current.data, new.data, ord, fail_ord) {
-            Ok(_) => {
-                let data = new.data;
-                mem::forget(new);
-                Ok(Ptr::from_data(data))
-            }
+            Ok(_) => Ok(new.into_ptr(scope)),
             Err(previous) => Err((Ptr::from_data(previous), new)),
         }
     }
 
-    /// Bitwise "and" with the current tag.
+    /// Stores a box into the atomic pointer if the current value is the same as `current`.
     ///
-    /// Performs a bitwise "and" operation on the current tag and the argument `val`, and sets the
-    /// new tag to the result. Returns the previous pointer.
+    /// The return value is a result indicating whether the new pointer was written. On success the
+    /// pointer that was written is returned. On failure a [`CasError`] is returned, holding the
+    /// actual current value and the box, which can be reused for the next attempt.
     ///
     /// This method takes an [`Ordering`] argument which describes the memory ordering of this
     /// operation.
     ///
+    /// In debug builds, `Relaxed` and `Acquire` orderings are rejected with a panic, since they
+    /// would publish the new object without synchronizing with its initialization.
+    ///
+    /// [`CasError`]: struct.CasError.html
     /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
     ///
     /// # Examples
@@ -420,188 +947,738 @@ impl<T> Atomic<T> {
     /// use coco::epoch::{self, Atomic, Ptr};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
-    /// let a = Atomic::<i32>::from_ptr(Ptr::null().with_tag(3));
+    /// let a = Atomic::new(1234);
+    ///
     /// epoch::pin(|scope| {
-    ///     assert_eq!(a.fetch_and(2, SeqCst, scope).tag(), 3);
-    ///     assert_eq!(a.load(SeqCst, scope).tag(), 2);
+    ///     let err = a.compare_and_set(Ptr::null(), Box::new(5678), SeqCst, scope).unwrap_err();
+    ///     assert_eq!(unsafe { err.observed.as_ref() }, Some(&1234));
+    ///     assert_eq!(*err.into_box(), 5678);
     /// });
     /// ```
-    pub fn fetch_and<'scope>(&self, val: usize, ord: Ordering, _: &'scope Scope) -> Ptr<'scope, T> {
-        Ptr::from_data(self.data.fetch_and(val | !low_bits::<T>(), ord))
+    pub fn compare_and_set<'scope>(
+        &self,
+        current: Ptr<T>,
+        new: Box<T>,
+        ord: Ordering,
+        scope: &'scope Scope,
+    ) -> Result<Ptr<'scope, T>, CasError<'scope, T>> {
+        self.compare_and_swap_owned(current, Owned::from_box(new), ord, scope)
+            .map_err(|(observed, new)| CasError { observed, returned: new.into_box() })
     }
 
-    /// Bitwise "or" with the current tag.
+    /// Stores a box into the atomic pointer if the current value is the same as `current`.
     ///
-    /// Performs a bitwise "or" operation on the current tag and the argument `val`, and sets the
-    /// new tag to the result. Returns the previous pointer.
+    /// Unlike [`compare_and_set`], this method is allowed to spuriously fail even when comparison
+    /// succeeds, which can result in more efficient code on some platforms.
+    /// The return value is a result indicating whether the new pointer was written. On success the
+    /// pointer that was written is returned. On failure a [`CasError`] is returned, holding the
+    /// actual current value and the box, which can be reused for the next attempt.
     ///
     /// This method takes an [`Ordering`] argument which describes the memory ordering of this
     /// operation.
     ///
+    /// In debug builds, `Relaxed` and `Acquire` orderings are rejected with a panic, since they
+    /// would publish the new object without synchronizing with its initialization.
+    ///
+    /// [`compare_and_set`]: struct.Atomic.html#method.compare_and_set
+    /// [`CasError`]: struct.CasError.html
     /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
     ///
     /// # Examples
     ///
     /// ```
-    /// use coco::epoch::{self, Atomic, Ptr};
+    /// use coco::epoch::{self, Atomic};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
-    /// let a = Atomic::<i32>::from_ptr(Ptr::null().with_tag(1));
+    /// let a = Atomic::new(1234);
+    ///
     /// epoch::pin(|scope| {
-    ///     assert_eq!(a.fetch_or(2, SeqCst, scope).tag(), 1);
-    ///     assert_eq!(a.load(SeqCst, scope).tag(), 3);
+    ///     let mut new = Box::new(5678);
+    ///     let mut ptr = a.load(SeqCst, scope);
+    ///     loop {
+    ///         match a.compare_and_set_weak(ptr, new, SeqCst, scope) {
+    ///             Ok(_) => break,
+    ///             Err(err) => {
+    ///                 ptr = err.observed;
+    ///                 new = err.returned;
+    ///             }
+    ///         }
+    ///     }
+    ///     unsafe { scope.defer_free(ptr) }
     /// });
     /// ```
-    pub fn fetch_or<'scope>(&self, val: usize, ord: Ordering, _: &'scope Scope) -> Ptr<'scope, T> {
-        Ptr::from_data(self.data.fetch_or(val & low_bits::<T>(), ord))
+    pub fn compare_and_set_weak<'scope>(
+        &self,
+        current: Ptr<T>,
+        new: Box<T>,
+        ord: Ordering,
+        scope: &'scope Scope,
+    ) -> Result<Ptr<'scope, T>, CasError<'scope, T>> {
+        self.compare_and_swap_weak_owned(current, Owned::from_box(new), ord, scope)
+            .map_err(|(observed, new)| CasError { observed, returned: new.into_box() })
     }
 
-    /// Bitwise "xor" with the current tag.
+    /// Stores a box tagged with `new_tag` into the atomic pointer if the current value is the same
+    /// as `current`.
     ///
-    /// Performs a bitwise "xor" operation on the current tag and the argument `val`, and sets the
-    /// new tag to the result. Returns the previous pointer.
+    /// The comparison takes the tag of `current` into account, so this method can atomically move
+    /// a pointer from one state to another while replacing the object, e.g. from "active" to
+    /// "frozen". Like [`compare_and_set_weak`], it is allowed to spuriously fail even when
+    /// comparison succeeds.
+    ///
+    /// The return value is a result indicating whether the new pointer was written. On success the
+    /// pointer that was written is returned. On failure a [`CasError`] is returned, holding the
+    /// actual current value and the box.
     ///
     /// This method takes an [`Ordering`] argument which describes the memory ordering of this
     /// operation.
     ///
+    /// In debug builds, `Relaxed` and `Acquire` orderings are rejected with a panic, since they
+    /// would publish the new object without synchronizing with its initialization.
+    ///
+    /// [`compare_and_set_weak`]: struct.Atomic.html#method.compare_and_set_weak
+    /// [`CasError`]: struct.CasError.html
     /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
     ///
+    /// # Panics
+    ///
+    /// Panics if the tag doesn't fit into the unused bits of the pointer.
+    ///
     /// # Examples
     ///
     /// ```
-    /// use coco::epoch::{self, Atomic, Ptr};
+    /// use coco::epoch::{self, Atomic};
     /// use std::sync::atomic::Ordering::SeqCst;
     ///
-    /// let a = Atomic::<i32>::from_ptr(Ptr::null().with_tag(1));
+    /// const FROZEN: usize = 1;
+    /// let a = Atomic::new(1234u64);
+    ///
     /// epoch::pin(|scope| {
-    ///     assert_eq!(a.fetch_xor(3, SeqCst, scope).tag(), 1);
-    ///     assert_eq!(a.load(SeqCst, scope).tag(), 2);
+    ///     let mut new = Box::new(5678);
+    ///     let mut curr = a.load(SeqCst, scope);
+    ///     loop {
+    ///         match a.compare_and_set_weak_tagged(curr, new, FROZEN, SeqCst, scope) {
+    ///             Ok(p) => {
+    ///                 assert_eq!(p.tag(), FROZEN);
+    ///                 break;
+    ///             }
+    ///             Err(err) => {
+    ///                 curr = err.observed;
+    ///                 new = err.returned;
+    ///             }
+    ///         }
+    ///     }
+    ///     unsafe { scope.defer_free(curr) }
     /// });
     /// ```
-    pub fn fetch_xor<'scope>(&self, val: usize, ord: Ordering, _: &'scope Scope) -> Ptr<'scope, T> {
-        Ptr::from_data(self.data.fetch_xor(val & low_bits::<T>(), ord))
-    }
-}
-
-impl<T> Default for Atomic<T> {
-    fn default() -> Self {
-        Atomic::null()
+    pub fn compare_and_set_weak_tagged<'scope>(
+        &self,
+        current: Ptr<T>,
+        new: Box<T>,
+        new_tag: usize,
+        ord: Ordering,
+        scope: &'scope Scope,
+    ) -> Result<Ptr<'scope, T>, CasError<'scope, T>> {
+        let new = Owned::from_box(new).with_tag(new_tag);
+        self.compare_and_swap_weak_owned(current, new, ord, scope)
+            .map_err(|(observed, new)| CasError { observed, returned: new.into_box() })
     }
-}
 
-/// An owned heap-allocated object.
-///
-/// This type is very similar to `Box<T>`.
-///
-/// The pointer must be properly aligned. Since it is aligned, a tag can be stored into the unused
-/// least significant bits of the address.
-#[derive(Debug)]
-pub struct Owned<T> {
-    data: usize,
-    _marker: PhantomData<Box<T>>,
-}
+    /// Stores a box into the atomic pointer if the current value satisfies `predicate`.
+    ///
+    /// The current value is loaded with `Acquire` ordering and passed to `predicate`. If it
+    /// returns `true`, the box is installed with a compare-and-set, and the whole procedure is
+    /// retried if another thread changes the atomic in the meantime. If the atomic pointer is
+    /// null, there is nothing to check and the box is installed unconditionally.
+    ///
+    /// On success the previous pointer is returned, so that it can be deferred for destruction.
+    /// If the predicate doesn't hold, the box is handed back.
+    ///
+    /// In debug builds, `Relaxed` and `Acquire` orderings are rejected with a panic, since they
+    /// would publish the new object without synchronizing with its initialization.
+    ///
+    /// # Safety
+    ///
+    /// The current value gets dereferenced, so the object the atomic points to must be valid for
+    /// the lifetime of `scope`, i.e. replaced objects may only be destroyed through the epoch GC.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// // Only ever replace the value with a higher version.
+    /// let a = Atomic::new(3);
+    /// epoch::pin(|scope| unsafe {
+    ///     assert!(a.store_if(Box::new(2), |&v| v < 2, SeqCst, scope).is_err());
+    ///
+    ///     let old = a.store_if(Box::new(5), |&v| v < 5, SeqCst, scope).unwrap();
+    ///     assert_eq!(*old.deref(), 3);
+    ///     scope.defer_free(old);
+    /// });
+    /// ```
+    pub unsafe fn store_if<'scope, P>(
+        &self,
+        new: Box<T>,
+        predicate: P,
+        ord: Ordering,
+        scope: &'scope Scope,
+    ) -> Result<Ptr<'scope, T>, Box<T>>
+    where
+        P: Fn(&T) -> bool,
+    {
+        let mut new = new;
+        loop {
+            let current = self.load(Acquire, scope);
+            if let Some(c) = current.as_ref() {
+                if !predicate(c) {
+                    return Err(new);
+                }
+            }
 
-impl<T> Owned<T> {
-    /// Returns a new owned pointer initialized with the tagged pointer `data`.
-    fn from_data(data: usize) -> Self {
-        Owned {
-            data: data,
-            _marker: PhantomData,
+            // On failure the observed value might have been loaded with a weaker ordering than
+            // `Acquire`, so it gets loaded again at the beginning of the next iteration.
+            match self.compare_and_set_weak(current, new, ord, scope) {
+                Ok(_) => return Ok(current),
+                Err(err) => new = err.into_box(),
+            }
         }
     }
 
-    /// Allocates `value` on the heap and returns a new owned pointer pointing to it.
+    /// Replaces the current value with one computed from it, and defers destruction of the old one.
+    ///
+    /// The current value is loaded with `Acquire` ordering and passed to `f`, or `None` if the
+    /// atomic pointer is null. If `f`
+    /// returns `None`, nothing changes. If it returns a new box, the box is installed with a
+    /// compare-and-set, and the old value gets deferred for destruction as if by [`defer_drop`].
+    /// If another thread changes the atomic in the meantime, the box is dropped and the procedure
+    /// is retried with the fresh value, so `f` may be called several times.
+    ///
+    /// If the returned box owns the current object itself (i.e. it was rebuilt from the reference
+    /// passed to `f`), this is treated like returning `None`: the object is still reachable, so it
+    /// is neither deferred nor dropped. Only the address is compared, so the tag of the current
+    /// pointer doesn't matter.
+    ///
+    /// Returns `true` if a new value was installed.
+    ///
+    /// This is the recommended way of updating a single-object cell: the whole load, compute,
+    /// compare-and-set, and reclaim sequence is done without any `unsafe` on the caller's side.
+    /// It relies on objects stored in the atomic pointer being destroyed only through the epoch
+    /// GC, which is why `T` must be `Send + 'static`.
+    ///
+    /// In debug builds, `Relaxed` and `Acquire` orderings are rejected with a panic, since they
+    /// would publish the new object without synchronizing with its initialization.
+    ///
+    /// [`defer_drop`]: struct.Scope.html#method.defer_drop
     ///
     /// # Examples
     ///
     /// ```
-    /// use coco::epoch::Owned;
+    /// use coco::epoch::{self, Atomic};
+    /// use std::sync::atomic::Ordering::{AcqRel, Acquire};
     ///
-    /// let o = Owned::new(1234);
+    /// let a = Atomic::new(vec![1, 2]);
+    /// epoch::pin(|scope| {
+    ///     a.update(
+    ///         |v| {
+    ///             let mut v = v.cloned().unwrap_or_default();
+    ///             v.push(3);
+    ///             Some(Box::new(v))
+    ///         },
+    ///         AcqRel,
+    ///         scope,
+    ///     );
+    ///     assert_eq!(unsafe { a.load(Acquire, scope).deref() }, &[1, 2, 3]);
+    /// });
     /// ```
-    pub fn new(value: T) -> Self {
-        Self::from_box(Box::new(value))
+    pub fn update<F>(&self, mut f: F, ord: Ordering, scope: &Scope) -> bool
+    where
+        T: Send + 'static,
+        F: FnMut(Option<&T>) -> Option<Box<T>>,
+    {
+        debug_assert_publishing(ord);
+
+        loop {
+            let current = self.load(Acquire, scope);
+            let new = match f(unsafe { current.as_ref() }) {
+                None => return false,
+                Some(new) => new,
+            };
+
+            // If `f` handed back the current object itself, installing it would be a no-op.
+            // The object is still reachable, so it must be neither deferred nor dropped.
+            if ptr::eq(&*new, current.as_raw()) {
+                let _ = Box::into_raw(new);
+                return false;
+            }
+
+            // On failure the new box is simply dropped, since `f` computes another one from the
+            // fresh value.
+            if self.compare_and_set_weak(current, new, ord, scope).is_ok() {
+                unsafe { scope.defer_drop(current) }
+                return true;
+            }
+        }
     }
 
-    /// Returns a new owned pointer initialized with `b`.
+    /// Installs a new object that links to the current one, as when pushing onto a stack.
     ///
-    /// # Panics
+    /// The current pointer is loaded with `Acquire` ordering and passed to `build`, which
+    /// allocates the new object pointing to it. Then the object is installed with a
+    /// compare-and-swap. If another thread changes the atomic in the meantime, the already
+    /// allocated object is reused: `link` is called to point it to the current pointer observed by
+    /// the failed compare-and-swap, and the compare-and-swap is retried. That way `build` is
+    /// called exactly once and the object is never reallocated. The pointer passed to `link` is
+    /// observed with the failure ordering derived from `ord`, which is `Relaxed` for `Release`.
     ///
-    /// Panics if the pointer (the `Box`) is not properly aligned.
+    /// Returns the installed pointer.
+    ///
+    /// In debug builds, `Relaxed` and `Acquire` orderings are rejected with a panic, since they
+    /// would publish the new object without synchronizing with its initialization.
     ///
     /// # Examples
     ///
     /// ```
-    /// use coco::epoch::Owned;
+    /// use coco::epoch::{self, Atomic};
+    /// use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed};
     ///
-    /// let o = unsafe { Owned::from_raw(Box::into_raw(Box::new(1234))) };
+    /// struct Node {
+    ///     value: i32,
+    ///     next: Atomic<Node>,
+    /// }
+    ///
+    /// let head = Atomic::null();
+    /// epoch::pin(|scope| {
+    ///     for value in 0..3 {
+    ///         head.push_like(
+    ///             |h| Box::new(Node { value, next: Atomic::from_ptr(h) }),
+    ///             |node, h| node.next.store(h, Relaxed),
+    ///             AcqRel,
+    ///             scope,
+    ///         );
+    ///     }
+    ///
+    ///     let top = unsafe { head.load(Acquire, scope).deref() };
+    ///     assert_eq!(top.value, 2);
+    /// #   let mut p = head.swap_null(Acquire, scope);
+    /// #   while let Some(node) = unsafe { p.as_ref() } {
+    /// #       let next = node.next.load(Relaxed, scope);
+    /// #       unsafe { drop(p.into_box()) }
+    /// #       p = next;
+    /// #   }
+    /// });
     /// ```
-    pub fn from_box(b: Box<T>) -> Self {
-        unsafe { Self::from_raw(Box::into_raw(b)) }
+    pub fn push_like<'scope, B, L>(
+        &self,
+        build: B,
+        mut link: L,
+        ord: Ordering,
+        scope: &'scope Scope,
+    ) -> Ptr<'scope, T>
+    where
+        B: FnOnce(Ptr<'scope, T>) -> Box<T>,
+        L: FnMut(&mut T, Ptr<'scope, T>),
+    {
+        let mut current = self.load(Acquire, scope);
+        let mut new = Owned::from_box(build(current));
+        loop {
+            match self.compare_and_swap_weak_owned(current, new, ord, scope) {
+                Ok(p) => return p,
+                Err((c, mut n)) => {
+                    // No need to load again: the failed compare-and-swap returns the fresh value.
+                    link(&mut n, c);
+                    current = c;
+                    new = n;
+                }
+            }
+        }
     }
 
-    /// Returns a new owned pointer initialized with `raw`.
+    /// Publishes a box into the atomic pointer if it is null.
     ///
-    /// # Panics
+    /// A common pattern is to build a new object privately, initializing its fields (including
+    /// any `Atomic`s inside it) with `Relaxed` stores, and then publish it so that other threads
+    /// can see it. For that to be correct, the publishing operation must use at least `Release`
+    /// ordering, so that the initialization happens-before any read through the published
+    /// pointer. This method always installs the box using `AcqRel` ordering.
     ///
-    /// Panics if `raw` is not properly aligned.
+    /// On success the published pointer is returned. If the atomic pointer is not null (including
+    /// a null pointer with a non-zero tag), the box is returned back.
     ///
     /// # Examples
     ///
     /// ```
-    /// use coco::epoch::Owned;
+    /// use coco::epoch::{self, Atomic};
     ///
-    /// let o = unsafe { Owned::from_raw(Box::into_raw(Box::new(1234))) };
+    /// let a = Atomic::null();
+    ///
+    /// epoch::pin(|scope| {
+    ///     assert!(a.publish_box(Box::new(1234), scope).is_ok());
+    ///     assert_eq!(a.publish_box(Box::new(5678), scope).err(), Some(Box::new(5678)));
+    /// #   unsafe { scope.defer_free(a.load_acquire(scope)) }
+    /// });
     /// ```
-    pub unsafe fn from_raw(raw: *mut T) -> Self {
-        ensure_aligned(raw);
-        Self::from_data(raw as usize)
+    pub fn publish_box<'scope>(
+        &self,
+        new: Box<T>,
+        scope: &'scope Scope,
+    ) -> Result<Ptr<'scope, T>, Box<T>> {
+        self.compare_and_swap_owned(Ptr::null(), Owned::from_box(new), AcqRel, scope)
+            .map_err(|(_, o)| o.into_box())
     }
 
-    /// Converts the owned pointer to a [`Ptr`].
+    /// Returns the current pointer, initializing it with the result of `init` if it is null.
+    ///
+    /// If the atomic pointer is null, `init` is called, its result is allocated on the heap, and
+    /// the allocation is installed using a compare-and-swap. If another thread installs a pointer
+    /// first, the allocation is destroyed and that thread's pointer is returned instead. Either
+    /// way, the returned pointer is never null and `init` is called at most once.
+    ///
+    /// The tag of the null pointer is carried over to the installed pointer.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of the
+    /// compare-and-swap. Loads always use `Acquire` ordering, so the returned object can be read
+    /// even if it was installed by another thread.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
     ///
     /// # Examples
     ///
     /// ```
-    /// use coco::epoch::{self, Owned};
+    /// use coco::epoch::{self, Atomic};
+    /// use std::sync::atomic::Ordering::AcqRel;
+    ///
+    /// let a = Atomic::null();
     ///
-    /// let o = Owned::new(1234);
     /// epoch::pin(|scope| {
-    ///     let p = o.into_ptr(scope);
+    ///     let p = a.get_or_init(AcqRel, scope, || 1234);
+    ///     assert_eq!(unsafe { p.as_ref() }, Some(&1234));
+    ///
+    ///     let p = a.get_or_init(AcqRel, scope, || 5678);
+    ///     assert_eq!(unsafe { p.as_ref() }, Some(&1234));
     /// });
     /// ```
-    ///
-    /// [`Ptr`]: struct.Ptr.html
-    pub fn into_ptr<'scope>(self, _: &'scope Scope) -> Ptr<'scope, T> {
-        let data = self.data;
-        mem::forget(self);
-        Ptr::from_data(data)
+    pub fn get_or_init<'scope, F>(
+        &self,
+        ord: Ordering,
+        scope: &'scope Scope,
+        init: F,
+    ) -> Ptr<'scope, T>
+    where
+        F: FnOnce() -> T,
+    {
+        let mut current = self.load(Acquire, scope);
+        if !current.is_null() {
+            return current;
+        }
+
+        let mut new = Owned::new(init());
+        loop {
+            // The null pointer might carry a tag, which must be kept.
+            match self.compare_and_swap_owned(current, new.with_tag(current.tag()), ord, scope) {
+                Ok(p) => return p,
+                Err((_, n)) => {
+                    // The observed value might have been loaded with a weaker ordering than
+                    // `Acquire`, so load it again.
+                    current = self.load(Acquire, scope);
+                    if !current.is_null() {
+                        // Another thread won the race.
+                        drop(n.into_box());
+                        return current;
+                    }
+                    // The pointer is still null, but its tag has changed.
+                    new = n;
+                }
+            }
+        }
     }
 
-    /// Returns the tag stored within the pointer.
+    /// Bitwise "and" with the current tag.
+    ///
+    /// Performs a bitwise "and" operation on the current tag and the argument `val`, and sets the
+    /// new tag to the result. Returns the previous pointer.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
     ///
     /// # Examples
     ///
     /// ```
-    /// use coco::epoch::Owned;
+    /// use coco::epoch::{self, Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
     ///
-    /// assert_eq!(Owned::new(1234).tag(), 0);
+    /// let a = Atomic::<i32>::from_ptr(Ptr::null().with_tag(3));
+    /// epoch::pin(|scope| {
+    ///     assert_eq!(a.fetch_and(2, SeqCst, scope).tag(), 3);
+    ///     assert_eq!(a.load(SeqCst, scope).tag(), 2);
+    /// });
     /// ```
-    pub fn tag(&self) -> usize {
-        self.data & low_bits::<T>()
+    pub fn fetch_and<'scope>(&self, val: usize, ord: Ordering, _: &'scope Scope) -> Ptr<'scope, T> {
+        Ptr::from_data(self.data.fetch_and(val | !low_bits::<T>(), ord))
     }
 
-    /// Returns the same pointer, but tagged with `tag`.
+    /// Bitwise "or" with the current tag.
+    ///
+    /// Performs a bitwise "or" operation on the current tag and the argument `val`, and sets the
+    /// new tag to the result. Returns the previous pointer.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
     ///
     /// # Examples
     ///
     /// ```
-    /// use coco::epoch::Owned;
+    /// use coco::epoch::{self, Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
     ///
-    /// let o = Owned::new(0u64);
-    /// assert_eq!(o.tag(), 0);
-    /// let o = o.with_tag(5);
-    /// assert_eq!(o.tag(), 5);
+    /// let a = Atomic::<i32>::from_ptr(Ptr::null().with_tag(1));
+    /// epoch::pin(|scope| {
+    ///     assert_eq!(a.fetch_or(2, SeqCst, scope).tag(), 1);
+    ///     assert_eq!(a.load(SeqCst, scope).tag(), 3);
+    /// });
+    /// ```
+    pub fn fetch_or<'scope>(&self, val: usize, ord: Ordering, _: &'scope Scope) -> Ptr<'scope, T> {
+        Ptr::from_data(self.data.fetch_or(val & low_bits::<T>(), ord))
+    }
+
+    /// Bitwise "xor" with the current tag.
+    ///
+    /// Performs a bitwise "xor" operation on the current tag and the argument `val`, and sets the
+    /// new tag to the result. Returns the previous pointer.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::<i32>::from_ptr(Ptr::null().with_tag(1));
+    /// epoch::pin(|scope| {
+    ///     assert_eq!(a.fetch_xor(3, SeqCst, scope).tag(), 1);
+    ///     assert_eq!(a.load(SeqCst, scope).tag(), 2);
+    /// });
+    /// ```
+    pub fn fetch_xor<'scope>(&self, val: usize, ord: Ordering, _: &'scope Scope) -> Ptr<'scope, T> {
+        Ptr::from_data(self.data.fetch_xor(val & low_bits::<T>(), ord))
+    }
+}
+
+impl<T> Default for Atomic<T> {
+    fn default() -> Self {
+        Atomic::null()
+    }
+}
+
+impl<T> From<T> for Atomic<T> {
+    /// Allocates `value` on the heap and returns a new atomic pointer pointing to it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::Atomic;
+    ///
+    /// let a: Atomic<i32> = 1234.into();
+    /// ```
+    fn from(value: T) -> Self {
+        Atomic::new(value)
+    }
+}
+
+impl<T> From<Box<T>> for Atomic<T> {
+    /// Returns a new atomic pointer pointing to the allocation owned by `b`.
+    ///
+    /// No new allocation is made - the box is simply taken over by the atomic pointer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::Atomic;
+    ///
+    /// let a = Atomic::<i32>::from(Box::new(1234));
+    /// ```
+    fn from(b: Box<T>) -> Self {
+        Atomic::new_boxed(b)
+    }
+}
+
+impl<T> From<Owned<T>> for Atomic<T> {
+    /// Returns a new atomic pointer pointing to `owned`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{Atomic, Owned};
+    ///
+    /// let a = Atomic::<i32>::from(Owned::new(1234));
+    /// ```
+    fn from(owned: Owned<T>) -> Self {
+        Atomic::from_owned(owned)
+    }
+}
+
+/// The error returned by a failed [`compare_and_set`] or [`compare_and_set_weak`].
+///
+/// The box that couldn't be installed is handed back, so that it can be reused for the next
+/// attempt instead of being leaked or reallocated.
+///
+/// [`compare_and_set`]: struct.Atomic.html#method.compare_and_set
+/// [`compare_and_set_weak`]: struct.Atomic.html#method.compare_and_set_weak
+#[derive(Debug)]
+pub struct CasError<'scope, T: 'scope> {
+    /// The actual value of the atomic pointer.
+    pub observed: Ptr<'scope, T>,
+    /// The box that was not installed.
+    pub returned: Box<T>,
+}
+
+impl<'scope, T> CasError<'scope, T> {
+    /// Returns the box that was not installed.
+    pub fn into_box(self) -> Box<T> {
+        self.returned
+    }
+}
+
+/// An owned heap-allocated object.
+///
+/// This type is very similar to `Box<T>`.
+///
+/// The pointer must be properly aligned. Since it is aligned, a tag can be stored into the unused
+/// least significant bits of the address.
+#[derive(Debug)]
+pub struct Owned<T> {
+    data: usize,
+    _marker: PhantomData<Box<T>>,
+}
+
+impl<T> Owned<T> {
+    /// Returns a new owned pointer initialized with the tagged pointer `data`.
+    fn from_data(data: usize) -> Self {
+        Owned {
+            data: data,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Allocates `value` on the heap and returns a new owned pointer pointing to it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::Owned;
+    ///
+    /// let o = Owned::new(1234);
+    /// ```
+    pub fn new(value: T) -> Self {
+        Self::from_box(Box::new(value))
+    }
+
+    /// Returns a new owned pointer initialized with `b`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pointer (the `Box`) is not properly aligned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::Owned;
+    ///
+    /// let o = unsafe { Owned::from_raw(Box::into_raw(Box::new(1234))) };
+    /// ```
+    pub fn from_box(b: Box<T>) -> Self {
+        unsafe { Self::from_raw(Box::into_raw(b)) }
+    }
+
+    /// Returns a new owned pointer initialized with `raw`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `raw` is not properly aligned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::Owned;
+    ///
+    /// let o = unsafe { Owned::from_raw(Box::into_raw(Box::new(1234))) };
+    /// ```
+    pub unsafe fn from_raw(raw: *mut T) -> Self {
+        let () = NotZeroSized::<T>::ASSERT;
+        ensure_aligned(raw);
+        Self::from_data(raw as usize)
+    }
+
+    /// Converts the owned pointer to a [`Ptr`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Owned};
+    ///
+    /// let o = Owned::new(1234);
+    /// epoch::pin(|scope| {
+    ///     let p = o.into_ptr(scope);
+    /// });
+    /// ```
+    ///
+    /// [`Ptr`]: struct.Ptr.html
+    pub fn into_ptr<'scope>(self, _: &'scope Scope) -> Ptr<'scope, T> {
+        let data = self.data;
+        mem::forget(self);
+        Ptr::from_data(data)
+    }
+
+    /// Converts the owned pointer into a `Box` (without the tag).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::Owned;
+    ///
+    /// let o = Owned::new(1234);
+    /// let b: Box<i32> = o.into_box();
+    /// assert_eq!(*b, 1234);
+    /// ```
+    pub fn into_box(self) -> Box<T> {
+        let raw = (self.data & !low_bits::<T>()) as *mut T;
+        mem::forget(self);
+        unsafe { Box::from_raw(raw) }
+    }
+
+    /// Returns the tag stored within the pointer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::Owned;
+    ///
+    /// assert_eq!(Owned::new(1234).tag(), 0);
+    /// ```
+    pub fn tag(&self) -> usize {
+        self.data & low_bits::<T>()
+    }
+
+    /// Returns the same pointer, but tagged with `tag`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::Owned;
+    ///
+    /// let o = Owned::new(0u64);
+    /// assert_eq!(o.tag(), 0);
+    /// let o = o.with_tag(5);
+    /// assert_eq!(o.tag(), 5);
     /// ```
     pub fn with_tag(self, tag: usize) -> Self {
         let data = self.data;
@@ -647,6 +1724,19 @@ impl<'scope, T> Clone for Ptr<'scope, T> {
 
 impl<'scope, T> Copy for Ptr<'scope, T> {}
 
+/// Compares both the addresses and the tags of two pointers.
+///
+/// To compare only the addresses, use [`eq_ignoring_tag`].
+///
+/// [`eq_ignoring_tag`]: struct.Ptr.html#method.eq_ignoring_tag
+impl<'scope, T> PartialEq for Ptr<'scope, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data
+    }
+}
+
+impl<'scope, T> Eq for Ptr<'scope, T> {}
+
 impl<'scope, T> Ptr<'scope, T> {
     /// Returns a new pointer initialized with the tagged pointer `data`.
     fn from_data(data: usize) -> Self {
@@ -735,6 +1825,67 @@ impl<'scope, T> Ptr<'scope, T> {
         (self.data & !low_bits::<T>()) as *const T
     }
 
+    /// Returns the pointer as an integer, including the tag in its unused least significant bits.
+    ///
+    /// This is the same representation as used by [`Atomic`]. It can be stored in an atomic word
+    /// managed by the user, e.g. next to other bits, and turned back into a pointer with
+    /// [`from_usize`].
+    ///
+    /// [`Atomic`]: struct.Atomic.html
+    /// [`from_usize`]: struct.Ptr.html#method.from_usize
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Ptr};
+    ///
+    /// epoch::pin(|scope| {
+    ///     let p = unsafe { Ptr::from_raw(Box::into_raw(Box::new(1234u64))) }.with_tag(3);
+    ///     assert_eq!(p.as_usize(), p.as_raw() as usize | 3);
+    ///     unsafe { drop(p.into_box()) }
+    /// });
+    /// ```
+    pub fn as_usize(&self) -> usize {
+        self.data
+    }
+
+    /// Returns a pointer from an integer previously returned by [`as_usize`].
+    ///
+    /// The lifetime of the returned pointer is bound to `scope`.
+    ///
+    /// # Safety
+    ///
+    /// `data` must have been produced by [`as_usize`] on a pointer to a `T`, or be zero with an
+    /// optional tag. The integer doesn't carry the provenance of the original pointer, so the
+    /// object may be dereferenced only if it is protected by the epoch GC as usual: it must have
+    /// been reachable when the current thread got pinned, or not have been deferred for
+    /// destruction at all. The round trip through an integer is also opaque to tools like Miri
+    /// that track pointer provenance strictly.
+    ///
+    /// [`as_usize`]: struct.Ptr.html#method.as_usize
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Owned, Ptr};
+    /// use std::sync::atomic::AtomicUsize;
+    /// use std::sync::atomic::Ordering::{Acquire, Release};
+    ///
+    /// let word = AtomicUsize::new(0);
+    /// epoch::pin(|scope| unsafe {
+    ///     let p = Owned::new(1234).into_ptr(scope).with_tag(1);
+    ///     word.store(p.as_usize(), Release);
+    ///
+    ///     let q = Ptr::<i32>::from_usize(word.load(Acquire), scope);
+    ///     assert_eq!(q, p);
+    ///     assert_eq!(*q.deref(), 1234);
+    ///     drop(q.into_box());
+    /// });
+    /// ```
+    pub unsafe fn from_usize(data: usize, _: &'scope Scope) -> Self {
+        Ptr::from_data(data)
+    }
+
     /// Dereferences the pointer.
     ///
     /// Returns a reference to the pointee that is valid in `'scope`.
@@ -779,6 +1930,14 @@ impl<'scope, T> Ptr<'scope, T> {
     ///
     /// Returns `None` if the pointer is null, or else a reference to the object wrapped in `Some`.
     ///
+    /// This is the canonical way of accessing an object through a pointer that might be null, and
+    /// should be preferred over [`deref`], which doesn't check for null. The returned reference
+    /// is bound to the lifetime of the scope, so it cannot outlive the pin that keeps the object
+    /// from being reclaimed. See also [`deref_or`].
+    ///
+    /// [`deref`]: struct.Ptr.html#method.deref
+    /// [`deref_or`]: struct.Ptr.html#method.deref_or
+    ///
     /// # Safety
     ///
     /// This method checks whether the pointer is null, and if not, assumes that it's pointing to a
@@ -813,6 +1972,149 @@ impl<'scope, T> Ptr<'scope, T> {
         self.as_raw().as_ref()
     }
 
+    /// Dereferences the pointer, or returns `default` if it is null.
+    ///
+    /// This is a shorthand for `p.as_ref().unwrap_or(default)`, useful e.g. with a static sentinel
+    /// object standing in for a missing one.
+    ///
+    /// # Safety
+    ///
+    /// The same rules apply as for [`as_ref`].
+    ///
+    /// [`as_ref`]: struct.Ptr.html#method.as_ref
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::null();
+    /// epoch::pin(|scope| {
+    ///     let p = a.load(SeqCst, scope);
+    ///     assert_eq!(unsafe { p.deref_or(&0) }, &0);
+    /// });
+    /// ```
+    pub unsafe fn deref_or(&self, default: &'scope T) -> &'scope T {
+        self.as_ref().unwrap_or(default)
+    }
+
+    /// Defers destruction of the object this pointer points to, after it was unlinked.
+    ///
+    /// This is a shorthand for [`defer_drop`], convenient for pointers just loaded or swapped out
+    /// of an [`Atomic`]. The tag is ignored, so a logically deleted (tagged) pointer can be
+    /// reclaimed directly. If the pointer is null, regardless of the tag, this method does nothing.
+    ///
+    /// # Safety
+    ///
+    /// The same rules apply as for [`defer_drop`]: the object must be unreachable for threads that
+    /// pin from now on, and it must not be reclaimed more than once.
+    ///
+    /// [`defer_drop`]: struct.Scope.html#method.defer_drop
+    /// [`Atomic`]: struct.Atomic.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    /// epoch::pin(|scope| {
+    ///     let p = a.swap(Ptr::null(), SeqCst, scope);
+    ///     unsafe { p.with_tag(1).unlinked(scope) }
+    /// });
+    /// ```
+    pub unsafe fn unlinked(self, scope: &Scope)
+    where
+        T: Send,
+    {
+        scope.defer_drop(self)
+    }
+
+    /// Converts the pointer into a `Box` (without the tag), taking ownership of the object.
+    ///
+    /// This is useful for synchronous cleanup of data structures that are exclusively owned, e.g.
+    /// in destructors, where deferring destruction through the epoch GC would be wasted work.
+    ///
+    /// # Safety
+    ///
+    /// The object must have been allocated as a `Box<T>` or an [`Owned<T>`], and no other thread
+    /// may be accessing it or be able to access it afterwards. It must not be reclaimed by any
+    /// other means, e.g. [`defer_free`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pointer is null.
+    ///
+    /// [`Owned<T>`]: struct.Owned.html
+    /// [`defer_free`]: struct.Scope.html#method.defer_free
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic};
+    /// use std::sync::atomic::Ordering::Relaxed;
+    ///
+    /// let a = Atomic::new(1234);
+    /// unsafe {
+    ///     epoch::unprotected(|scope| {
+    ///         let b = a.load(Relaxed, scope).into_box();
+    ///         assert_eq!(*b, 1234);
+    ///     })
+    /// }
+    /// ```
+    pub unsafe fn into_box(self) -> Box<T> {
+        assert!(!self.is_null(), "converting a null pointer into a box");
+        Box::from_raw(self.as_raw() as *mut T)
+    }
+
+    /// Returns a pointer to a part of the object, as computed by `f`.
+    ///
+    /// This is useful for projecting a pointer to a node onto one of its fields, e.g. the `Atomic`
+    /// pointing to the next node, while keeping the lifetime of the scope. The tag is not
+    /// preserved.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must be valid for dereferencing, as in [`deref`]. The reference returned by `f`
+    /// must point into the object itself, e.g. to one of its fields, so that it stays valid as
+    /// long as the object does. The returned pointer must not be reclaimed on its own, e.g. with
+    /// [`defer_free`], since it doesn't point to a separate allocation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pointer is null.
+    ///
+    /// [`deref`]: struct.Ptr.html#method.deref
+    /// [`defer_free`]: struct.Scope.html#method.defer_free
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// struct Node {
+    ///     value: i32,
+    ///     next: Atomic<Node>,
+    /// }
+    ///
+    /// let a = Atomic::new(Node { value: 1234, next: Atomic::null() });
+    /// epoch::pin(|scope| {
+    ///     let node = a.load(SeqCst, scope);
+    ///     let next = unsafe { node.map(|n| &n.next) };
+    ///     assert!(unsafe { next.deref() }.load(SeqCst, scope).is_null());
+    /// });
+    /// ```
+    pub unsafe fn map<U, F>(self, f: F) -> Ptr<'scope, U>
+    where
+        F: FnOnce(&T) -> &U
+    {
+        assert!(!self.is_null(), "mapping a null pointer");
+        Ptr::from_raw(f(&*self.as_raw()))
+    }
+
     /// Returns the tag stored within the pointer.
     ///
     /// # Examples
@@ -852,6 +2154,80 @@ impl<'scope, T> Ptr<'scope, T> {
     pub fn with_tag(&self, tag: usize) -> Self {
         Self::from_data(data_with_tag::<T>(self.data, tag))
     }
+
+    /// Returns `true` if both pointers point to the same address, regardless of their tags.
+    ///
+    /// Use this method to check whether two pointers refer to the same object, e.g. whether a node
+    /// is still in the list even if it got marked in the meantime. Use `==` to check whether two
+    /// pointers are identical including tags, e.g. whether a node got marked.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(0u64);
+    /// epoch::pin(|scope| {
+    ///     let p1 = a.load(SeqCst, scope);
+    ///     let p2 = p1.with_tag(1);
+    ///
+    ///     assert!(p1 != p2);
+    ///     assert!(p1.eq_ignoring_tag(&p2));
+    /// });
+    /// ```
+    pub fn eq_ignoring_tag(&self, other: &Ptr<T>) -> bool {
+        self.as_raw() == other.as_raw()
+    }
+
+    /// Casts the pointer to a pointer to an object of type `U`, preserving the tag.
+    ///
+    /// This is the equivalent of casting `*const T` to `*const U`, except that the returned
+    /// pointer is still only valid within `'scope`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the address is not properly aligned for `U`, or if the tag doesn't fit into the
+    /// unused bits of a pointer to `U`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must make sure that the object at this address can be validly interpreted as an
+    /// object of type `U`, e.g. because `U` is a `#[repr(C)]` prefix of `T`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic, Owned};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// #[repr(C)]
+    /// struct Header {
+    ///     len: u64,
+    /// }
+    ///
+    /// #[repr(C)]
+    /// struct Node {
+    ///     header: Header,
+    ///     data: [u64; 4],
+    /// }
+    ///
+    /// let a = Atomic::from_owned(Owned::new(Node {
+    ///     header: Header { len: 4 },
+    ///     data: [1, 2, 3, 4],
+    /// }).with_tag(1));
+    ///
+    /// epoch::pin(|scope| unsafe {
+    ///     let p = a.load(SeqCst, scope).cast::<Header>();
+    ///     assert_eq!(p.tag(), 1);
+    ///     assert_eq!(p.deref().len, 4);
+    /// });
+    /// ```
+    pub unsafe fn cast<U>(self) -> Ptr<'scope, U> {
+        let raw = self.as_raw() as *const U;
+        ensure_aligned(raw);
+        Ptr::from_data(data_with_tag::<U>(raw as usize, self.tag()))
+    }
 }
 
 impl<'scope, T> Default for Ptr<'scope, T> {
@@ -859,3 +2235,674 @@ impl<'scope, T> Default for Ptr<'scope, T> {
         Ptr::null()
     }
 }
+
+/// An iterator over the nodes of a linked list, created by [`Atomic::iter`].
+///
+/// [`Atomic::iter`]: struct.Atomic.html#method.iter
+pub struct Iter<'scope, T: 'scope, F> {
+    curr: Ptr<'scope, T>,
+    next: F,
+    scope: &'scope Scope,
+}
+
+impl<'scope, T, F> Iterator for Iter<'scope, T, F>
+where
+    F: Fn(&'scope T) -> &'scope Atomic<T>,
+{
+    type Item = Ptr<'scope, T>;
+
+    fn next(&mut self) -> Option<Ptr<'scope, T>> {
+        // The caller of `Atomic::iter` guaranteed that all reachable nodes are valid.
+        let node = unsafe { self.curr.as_ref() }?;
+        let curr = self.curr;
+        self.curr = (self.next)(node).load(Acquire, self.scope);
+        Some(curr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Barrier};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release, SeqCst};
+    use std::thread;
+
+    use epoch::{self, Atomic, Owned, Ptr};
+
+    #[test]
+    fn cas_owned_returns_owned_on_failure() {
+        let a = Atomic::new(1u64);
+
+        epoch::pin(|scope| {
+            let curr = a.load(SeqCst, scope);
+
+            match a.compare_and_swap_owned(Ptr::null(), Owned::new(2).with_tag(3), SeqCst, scope) {
+                Ok(_) => panic!(),
+                Err((p, o)) => {
+                    assert_eq!(p.as_raw(), curr.as_raw());
+                    assert_eq!(o.tag(), 3);
+                    assert_eq!(*o, 2);
+                    drop(o.into_box());
+                }
+            }
+
+            let new = a.compare_and_swap_owned(curr, Owned::new(4).with_tag(5), SeqCst, scope);
+            let new = new.ok().unwrap();
+            assert_eq!(new.tag(), 5);
+            assert_eq!(a.load(SeqCst, scope).tag(), 5);
+
+            unsafe {
+                scope.defer_free(curr);
+                scope.defer_free(new);
+            }
+        });
+    }
+
+    #[test]
+    fn cas_weak_owned_returns_owned_on_failure() {
+        let a = Atomic::new(1u64);
+
+        epoch::pin(|scope| {
+            let curr = a.load(SeqCst, scope);
+            let mut new = Owned::new(2).with_tag(3);
+
+            // The comparison never succeeds, so the same allocation must keep coming back.
+            let raw = &*new as *const u64;
+            for _ in 0..100 {
+                match a.compare_and_swap_weak_owned(Ptr::null(), new, SeqCst, scope) {
+                    Ok(_) => panic!(),
+                    Err((p, o)) => {
+                        assert_eq!(p.as_raw(), curr.as_raw());
+                        assert_eq!(&*o as *const u64, raw);
+                        assert_eq!(o.tag(), 3);
+                        new = o;
+                    }
+                }
+            }
+
+            let mut curr = curr;
+            loop {
+                match a.compare_and_swap_weak_owned(curr, new, SeqCst, scope) {
+                    Ok(p) => {
+                        assert_eq!(p.as_raw(), raw);
+                        assert_eq!(p.tag(), 3);
+                        break;
+                    }
+                    Err((c, o)) => {
+                        curr = c;
+                        new = o;
+                    }
+                }
+            }
+
+            unsafe {
+                scope.defer_free(curr);
+                scope.defer_free(a.load(SeqCst, scope));
+            }
+        });
+    }
+
+    #[test]
+    fn get_or_init_tag() {
+        let a = Atomic::null();
+        epoch::pin(|scope| unsafe {
+            a.store(Ptr::null().with_tag(3), Release);
+            let p = a.get_or_init(Release, scope, || 7u64);
+            assert_eq!(p.tag(), 3);
+            assert_eq!(*p.deref(), 7);
+            assert!(a.load(Acquire, scope) == p);
+            drop(p.into_box());
+        });
+    }
+
+    #[test]
+    fn get_or_init_race() {
+        const THREADS: usize = 8;
+
+        struct Elem(usize, Arc<AtomicUsize>);
+
+        impl Drop for Elem {
+            fn drop(&mut self) {
+                self.1.fetch_add(1, SeqCst);
+            }
+        }
+
+        let a = Arc::new(Atomic::<Elem>::null());
+        let drops = Arc::new(AtomicUsize::new(0));
+        let inits = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(THREADS));
+
+        let threads = (0..THREADS).map(|i| {
+            let a = a.clone();
+            let drops = drops.clone();
+            let inits = inits.clone();
+            let barrier = barrier.clone();
+
+            thread::spawn(move || {
+                barrier.wait();
+                epoch::pin(|scope| {
+                    let p = a.get_or_init(AcqRel, scope, || {
+                        inits.fetch_add(1, SeqCst);
+                        Elem(i, drops.clone())
+                    });
+                    (p.as_raw() as usize, unsafe { p.deref().0 })
+                })
+            })
+        }).collect::<Vec<_>>();
+
+        let results = threads.into_iter().map(|t| t.join().unwrap()).collect::<Vec<_>>();
+        assert!(results.iter().all(|r| *r == results[0]));
+
+        // Every allocation that lost the race must have been destroyed.
+        assert_eq!(drops.load(SeqCst), inits.load(SeqCst) - 1);
+
+        epoch::pin(|scope| unsafe {
+            drop(Box::from_raw(a.load(SeqCst, scope).as_raw() as *mut Elem));
+        });
+        assert_eq!(drops.load(SeqCst), inits.load(SeqCst));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "requires `Release`")]
+    fn store_owned_relaxed() {
+        let a = Atomic::null();
+        a.store_owned(Owned::new(1u64), Relaxed);
+    }
+
+    #[test]
+    fn iter_list() {
+        struct Node {
+            value: usize,
+            next: Atomic<Node>,
+        }
+
+        let head = Atomic::null();
+        for i in 0..10 {
+            let next = Atomic::null();
+            epoch::pin(|scope| next.store(head.load(Relaxed, scope), Relaxed));
+            head.publish(Owned::new(Node { value: i, next }));
+        }
+
+        epoch::pin(|scope| unsafe {
+            let values = head.iter(|n| &n.next, scope).map(|p| p.deref().value);
+            assert!(values.eq((0..10).rev()));
+
+            for p in head.iter(|n| &n.next, scope).collect::<Vec<_>>() {
+                drop(p.into_box());
+            }
+        });
+    }
+
+    #[test]
+    fn store_if() {
+        let a = Atomic::new(1u64);
+        epoch::pin(|scope| unsafe {
+            // The predicate doesn't hold, so the box is handed back.
+            let new = a.store_if(Box::new(0), |&v| v == 0, SeqCst, scope).unwrap_err();
+            assert_eq!(*new, 0);
+            assert_eq!(*a.load(SeqCst, scope).deref(), 1);
+
+            let old = a.store_if(Box::new(2), |&v| v < 2, SeqCst, scope).unwrap();
+            assert_eq!(*old.deref(), 1);
+            scope.defer_free(old);
+        });
+
+        // Many threads race to raise the value, so compare-and-set attempts get lost.
+        let a = Arc::new(a);
+        let threads = (0..4u64).map(|t| {
+            let a = a.clone();
+            thread::spawn(move || {
+                for i in 0..1000 {
+                    let v = 2 + i * 4 + t;
+                    epoch::pin(|scope| unsafe {
+                        if let Ok(old) = a.store_if(Box::new(v), |&c| c < v, SeqCst, scope) {
+                            assert!(*old.deref() < v);
+                            scope.defer_free(old);
+                        }
+                    });
+                }
+            })
+        }).collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        epoch::pin(|scope| unsafe {
+            let p = a.load(SeqCst, scope);
+            assert_eq!(*p.deref(), 2 + 999 * 4 + 3);
+            drop(p.into_box());
+        });
+    }
+
+    #[test]
+    fn unlinked_tagged() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Node {
+            _data: u64,
+        }
+
+        impl Drop for Node {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, SeqCst);
+            }
+        }
+
+        let a = Atomic::new(Node { _data: 7 });
+        epoch::pin(|scope| unsafe {
+            // Mark the node as logically deleted, then unlink and reclaim it.
+            let p = a.load(Acquire, scope);
+            assert!(a.compare_and_swap(p, p.with_tag(1), AcqRel, scope).is_ok());
+            let p = a.swap(Ptr::null(), AcqRel, scope);
+            assert_eq!(p.tag(), 1);
+            p.unlinked(scope);
+
+            // Null pointers are ignored, tagged or not.
+            Ptr::<Node>::null().unlinked(scope);
+            Ptr::<Node>::null().with_tag(1).unlinked(scope);
+            scope.flush();
+        });
+
+        while DROPS.load(SeqCst) == 0 {
+            epoch::collect_all();
+        }
+        assert_eq!(DROPS.load(SeqCst), 1);
+    }
+
+    #[test]
+    fn unlink_and_defer() {
+        let a = Atomic::new(1u64);
+        epoch::pin(|scope| unsafe {
+            let p = a.load(Acquire, scope);
+
+            // A stale expected value unlinks nothing.
+            let stale = p.with_tag(1);
+            assert_eq!(a.unlink_and_defer(stale, AcqRel, scope), Err(p));
+            assert_eq!(a.load(Acquire, scope), p);
+
+            assert_eq!(a.unlink_and_defer(p, AcqRel, scope), Ok(()));
+            assert!(a.load(Acquire, scope).is_null());
+        });
+    }
+
+    #[test]
+    fn load_marked() {
+        let a = Atomic::new(7u64);
+        epoch::pin(|scope| unsafe {
+            let p = a.load(Acquire, scope);
+            assert_eq!(a.load_marked(0, Acquire, scope), (p, false));
+
+            a.store(p.with_tag(1), Release);
+            assert_eq!(a.load_marked(0, Acquire, scope), (p.with_tag(1), true));
+            assert!(!a.load_marked(2, Acquire, scope).1);
+
+            drop(p.into_box());
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't fit")]
+    fn load_marked_bit_too_large() {
+        let a = Atomic::<u64>::null();
+        epoch::pin(|scope| {
+            a.load_marked(3, Acquire, scope);
+        });
+    }
+
+    #[test]
+    fn points_to() {
+        let a = Atomic::new(7u64);
+        epoch::pin(|scope| unsafe {
+            let p = a.load(Acquire, scope);
+            assert!(a.points_to(p.as_raw(), Relaxed));
+
+            a.store(p.with_tag(1), Release);
+            assert!(a.points_to(p.as_raw(), Relaxed));
+            assert!(!a.points_to(::std::ptr::null(), Relaxed));
+
+            drop(p.into_box());
+        });
+    }
+
+    #[test]
+    fn update() {
+        let a = Arc::new(Atomic::null());
+
+        let threads = (0..4).map(|_| {
+            let a = a.clone();
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    let f = |v: Option<&u64>| Some(Box::new(v.map_or(1, |&v| v + 1)));
+                    epoch::pin(|scope| a.update(f, AcqRel, scope));
+                }
+            })
+        }).collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        // Returning `None` leaves the value unchanged.
+        assert!(!epoch::pin(|scope| a.update(|_| None, AcqRel, scope)));
+
+        epoch::pin(|scope| unsafe {
+            let p = a.load(Acquire, scope);
+            assert_eq!(*p.deref(), 4000);
+            drop(p.into_box());
+        });
+    }
+
+    #[test]
+    fn update_retries() {
+        let a = Atomic::new(1u64);
+        let mut calls = 0;
+
+        // The first attempt races with a store, so `f` gets called again with the fresh value.
+        let updated = epoch::pin(|scope| {
+            let f = |v: Option<&u64>| {
+                calls += 1;
+                if calls == 1 {
+                    unsafe {
+                        let old = a.swap(Owned::new(10).into_ptr(scope), AcqRel, scope);
+                        scope.defer_free(old);
+                    }
+                }
+                v.map(|&v| Box::new(v + 1))
+            };
+            a.update(f, AcqRel, scope)
+        });
+        assert!(updated);
+        assert!(calls >= 2);
+
+        epoch::pin(|scope| unsafe {
+            let p = a.load(Acquire, scope);
+            assert_eq!(*p.deref(), 11);
+            drop(p.into_box());
+        });
+    }
+
+    #[test]
+    fn update_same_object() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Elem(u64);
+
+        impl Drop for Elem {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, SeqCst);
+            }
+        }
+
+        let a = Atomic::new(Elem(7));
+        epoch::pin(|scope| {
+            // The tag must not hide the fact that the same object is returned.
+            let p = a.load(Acquire, scope);
+            a.store(p.with_tag(1), Release);
+        });
+
+        let same = |v: Option<&Elem>| {
+            v.map(|v| unsafe { Box::from_raw(v as *const Elem as *mut Elem) })
+        };
+        assert!(!epoch::pin(|scope| a.update(same, AcqRel, scope)));
+        epoch::pin(|scope| scope.flush());
+        epoch::collect_all();
+        assert_eq!(DROPS.load(SeqCst), 0);
+
+        epoch::pin(|scope| unsafe {
+            let p = a.load(Acquire, scope);
+            assert_eq!(p.tag(), 1);
+            assert_eq!(p.deref().0, 7);
+            drop(p.into_box());
+        });
+        assert_eq!(DROPS.load(SeqCst), 1);
+    }
+
+    #[test]
+    fn usize_round_trip() {
+        let word = AtomicUsize::new(0);
+
+        epoch::pin(|scope| unsafe {
+            assert!(Ptr::<u64>::from_usize(word.load(Acquire), scope).is_null());
+
+            let p = Owned::new(7u64).into_ptr(scope).with_tag(5);
+            word.store(p.as_usize(), Release);
+
+            let q = Ptr::<u64>::from_usize(word.load(Acquire), scope);
+            assert_eq!(q, p);
+            assert_eq!(q.tag(), 5);
+            assert_eq!(q.as_raw(), p.as_raw());
+            drop(q.into_box());
+        });
+    }
+
+    #[test]
+    fn load_unchecked() {
+        let a = Atomic::new(7u64);
+        epoch::pin(|scope| unsafe {
+            assert_eq!(*a.load_unchecked(Acquire, scope), 7);
+
+            // The tag is ignored.
+            let p = a.load(Acquire, scope);
+            a.store(p.with_tag(3), Release);
+            assert_eq!(*a.load_unchecked(Acquire, scope), 7);
+            drop(p.into_box());
+        });
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "on a null pointer")]
+    fn load_unchecked_null() {
+        let a = Atomic::<u64>::null();
+        epoch::pin(|scope| unsafe {
+            a.load_unchecked(Acquire, scope);
+        });
+    }
+
+    #[test]
+    fn load_split() {
+        let a = Atomic::null();
+        epoch::pin(|scope| {
+            assert_eq!(a.load_split(Acquire, scope), (Ptr::null(), 0));
+
+            let p = Owned::new(7u64).into_ptr(scope);
+            for tag in 0..8 {
+                a.store(p.with_tag(tag), Release);
+                let (ptr, t) = a.load_split(Acquire, scope);
+                assert_eq!(ptr, p);
+                assert_eq!(ptr.tag(), 0);
+                assert_eq!(t, tag);
+            }
+            unsafe { drop(p.into_box()) }
+        });
+    }
+
+    #[test]
+    fn push_like() {
+        struct Node {
+            value: u64,
+            next: Atomic<Node>,
+        }
+
+        let head = Atomic::null();
+        epoch::pin(|scope| unsafe {
+            let first = Owned::new(Node { value: 1, next: Atomic::null() }).into_ptr(scope);
+            let mut links = 0;
+
+            // Change the head while the new node is being built, so that the first attempt fails.
+            let pushed = head.push_like(
+                |h| {
+                    assert!(h.is_null());
+                    head.store(first, Release);
+                    Box::new(Node { value: 2, next: Atomic::from_ptr(h) })
+                },
+                |node, h| {
+                    links += 1;
+                    node.next.store(h, Relaxed);
+                },
+                AcqRel,
+                scope,
+            );
+            assert_eq!(links, 1);
+            assert!(head.load(Acquire, scope) == pushed);
+            assert_eq!(pushed.deref().value, 2);
+            assert!(pushed.deref().next.load(Relaxed, scope) == first);
+
+            drop(pushed.into_box());
+            drop(first.into_box());
+        });
+    }
+
+    #[test]
+    fn raw_atomic_round_trip() {
+        let a = Atomic::from_owned(Owned::new(7u64).with_tag(3));
+        let word = a.into_raw();
+        assert_eq!(word & 3, 3);
+
+        let b = unsafe { Atomic::<u64>::from_raw_atomic(word) };
+        epoch::pin(|scope| unsafe {
+            let p = b.load(Acquire, scope);
+            assert_eq!(p.tag(), 3);
+            assert_eq!(*p.deref(), 7);
+            drop(p.into_box());
+        });
+    }
+
+    #[test]
+    fn publish() {
+        let a = Atomic::null();
+        a.publish(Owned::new(7u64));
+        epoch::pin(|scope| unsafe {
+            assert_eq!(*a.load(Acquire, scope).deref(), 7);
+            drop(a.load(Relaxed, scope).into_box());
+        });
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "requires `Release`")]
+    fn cas_owned_acquire() {
+        let a = Atomic::null();
+        epoch::pin(|scope| {
+            let _ = a.compare_and_swap_owned(Ptr::null(), Owned::new(1u64), Acquire, scope);
+        });
+    }
+
+    #[test]
+    fn map_to_field() {
+        struct Node {
+            _value: u64,
+            next: Atomic<Node>,
+        }
+
+        let a = Atomic::new(Node { _value: 1, next: Atomic::null() });
+        epoch::pin(|scope| unsafe {
+            let node = a.load(SeqCst, scope).with_tag(1);
+            let next = node.map(|n| &n.next);
+            assert_eq!(next.tag(), 0);
+            assert_eq!(next.as_raw(), &node.deref().next as *const _);
+
+            next.deref().store_owned(Owned::new(Node { _value: 2, next: Atomic::null() }), SeqCst);
+            assert!(!node.deref().next.load(SeqCst, scope).is_null());
+
+            scope.defer_free(node.deref().next.swap_null(SeqCst, scope));
+            scope.defer_free(a.swap_null(SeqCst, scope));
+        });
+    }
+
+    #[test]
+    fn cas_weak_tag_transition() {
+        let a = Atomic::new(1u64);
+
+        epoch::pin(|scope| {
+            let active = a.load(SeqCst, scope);
+
+            // The expected tag must match.
+            let wrong = active.with_tag(1);
+            let err = loop {
+                match a.compare_and_set_weak_tagged(wrong, Box::new(2), 1, SeqCst, scope) {
+                    Ok(_) => panic!("compare-and-swap with a wrong tag succeeded"),
+                    Err(err) => if err.observed == active { break err },
+                }
+            };
+            assert_eq!(*err.returned, 2);
+
+            // Move from tag 0 to tag 1 and then from tag 1 to tag 2.
+            let mut curr = active;
+            for (tag, value) in [(1, 3), (2, 4)].iter().cloned() {
+                let mut new = Box::new(value);
+                loop {
+                    match a.compare_and_set_weak_tagged(curr, new, tag, SeqCst, scope) {
+                        Ok(p) => {
+                            assert_eq!(p.tag(), tag);
+                            unsafe {
+                                assert_eq!(*p.deref(), value);
+                                scope.defer_free(curr);
+                            }
+                            curr = p;
+                            break;
+                        }
+                        Err(err) => new = err.returned,
+                    }
+                }
+            }
+            assert_eq!(a.load(SeqCst, scope), curr);
+            unsafe { scope.defer_free(a.swap_null(SeqCst, scope)) }
+        });
+    }
+
+    #[test]
+    fn tag_round_trip() {
+        epoch::pin(|scope| unsafe {
+            // Tags survive conversions between `Owned`, `Ptr`, and `Atomic`.
+            let a = Atomic::from_owned(Owned::new(1u64).with_tag(3));
+            let p = a.load(SeqCst, scope);
+            assert_eq!(p.tag(), 3);
+
+            let b = Atomic::from_ptr(p.with_tag(5));
+            assert_eq!(b.load(SeqCst, scope).tag(), 5);
+            assert_eq!(b.swap(p, SeqCst, scope).tag(), 5);
+            assert_eq!(b.load(SeqCst, scope).tag(), 3);
+
+            // Raw pointers don't carry tags.
+            assert_eq!(p.as_raw(), p.with_tag(0).as_raw());
+            assert_eq!(a.load_immortal(), p.as_raw());
+
+            // Converting into a box clears the tag.
+            let b = p.into_box();
+            assert_eq!(*b, 1);
+        });
+    }
+
+    #[test]
+    fn swap_tagged_is_atomic() {
+        const COUNT: usize = 10_000;
+
+        let a = Arc::new(Atomic::new(0u64));
+        let original = epoch::pin(|scope| a.load(SeqCst, scope).as_raw() as usize);
+
+        let reader = {
+            let a = a.clone();
+            thread::spawn(move || {
+                for _ in 0..COUNT {
+                    epoch::pin(|scope| {
+                        let p = a.load(SeqCst, scope);
+                        if p.as_raw() as usize != original {
+                            assert_eq!(p.tag(), 1);
+                        }
+                    });
+                }
+            })
+        };
+
+        for i in 0..COUNT {
+            epoch::pin(|scope| unsafe {
+                let old = a.swap_tagged(Owned::new(i as u64), 1, SeqCst, scope);
+                scope.defer_free(old);
+            });
+        }
+        reader.join().unwrap();
+
+        epoch::pin(|scope| unsafe { scope.defer_free(a.load(SeqCst, scope)) });
+    }
+}
@@ -0,0 +1,336 @@
+//! Atomic pointers to unsized objects.
+//!
+//! A pointer to an unsized object (e.g. a trait object or a slice) is a fat pointer consisting of
+//! two words: the address and the metadata (a vtable or a length). Two words cannot be
+//! atomically loaded, stored, or compared-and-swapped on most platforms, so fat pointers can't be
+//! stored into an `AtomicUsize` directly.
+//!
+//! Instead, `AtomicBox<T>` keeps the fat pointer inside a `Box<T>` that is itself allocated on the
+//! heap, and atomically manipulates a thin pointer to that allocation. This costs an additional
+//! allocation and indirection per object, but works on all platforms.
+
+use std::fmt;
+use std::sync::atomic::Ordering;
+
+use epoch::{Atomic, Owned, Ptr, Scope};
+
+/// The result of a compare-and-swap of a box: the written pointer on success, or the current
+/// pointer and the rejected box on failure.
+type BoxCasResult<'scope, T> = Result<Ptr<'scope, Box<T>>, (Ptr<'scope, Box<T>>, Box<T>)>;
+
+/// An atomic pointer to a boxed, possibly unsized object.
+///
+/// Loading an `AtomicBox<T>` yields a `Ptr<Box<T>>`, which points to the heap-allocated box that
+/// holds the fat pointer. Such pointers can be compared and reclaimed just like any other `Ptr`.
+/// Reclaiming a pointer with [`defer_drop`] drops the inner `Box<T>`, which runs the destructor of
+/// the object through the vtable.
+///
+/// [`defer_drop`]: struct.Scope.html#method.defer_drop
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch::{self, AtomicBox};
+/// use std::fmt::Display;
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// let a: AtomicBox<dyn Display + Send + Sync> = AtomicBox::new(Box::new(1234));
+///
+/// epoch::pin(|scope| unsafe {
+///     let old = a.swap_box(Box::new("hello"), SeqCst, scope);
+///     assert_eq!(old.deref().to_string(), "1234");
+///     scope.defer_drop(old);
+///
+///     assert_eq!(a.load(SeqCst, scope).deref().to_string(), "hello");
+/// });
+/// ```
+pub struct AtomicBox<T: ?Sized> {
+    inner: Atomic<Box<T>>,
+}
+
+impl<T: ?Sized> AtomicBox<T> {
+    /// Returns a new null atomic pointer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::AtomicBox;
+    ///
+    /// let a = AtomicBox::<[i32]>::null();
+    /// ```
+    pub fn null() -> Self {
+        AtomicBox { inner: Atomic::null() }
+    }
+
+    /// Returns a new atomic pointer pointing to `b`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::AtomicBox;
+    ///
+    /// let a = AtomicBox::<[i32]>::new(vec![1, 2, 3].into_boxed_slice());
+    /// ```
+    pub fn new(b: Box<T>) -> Self {
+        AtomicBox { inner: Atomic::new(b) }
+    }
+
+    /// Loads a `Ptr` to the box from the atomic pointer.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, AtomicBox};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = AtomicBox::<[i32]>::new(vec![1, 2, 3].into_boxed_slice());
+    /// epoch::pin(|scope| {
+    ///     let p = a.load(SeqCst, scope);
+    ///     assert_eq!(unsafe { p.deref().len() }, 3);
+    /// });
+    /// ```
+    pub fn load<'scope>(&self, ord: Ordering, scope: &'scope Scope) -> Ptr<'scope, Box<T>> {
+        self.inner.load(ord, scope)
+    }
+
+    /// Stores a `Ptr` into the atomic pointer.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{AtomicBox, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = AtomicBox::<[i32]>::new(vec![1, 2, 3].into_boxed_slice());
+    /// a.store(Ptr::null(), SeqCst);
+    /// ```
+    pub fn store(&self, new: Ptr<Box<T>>, ord: Ordering) {
+        self.inner.store(new, ord);
+    }
+
+    /// Stores a box into the atomic pointer.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::AtomicBox;
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = AtomicBox::<[i32]>::null();
+    /// a.store_box(vec![1, 2, 3].into_boxed_slice(), SeqCst);
+    /// ```
+    pub fn store_box(&self, new: Box<T>, ord: Ordering) {
+        self.inner.store_owned(Owned::new(new), ord);
+    }
+
+    /// Stores a box into the atomic pointer, returning the previous `Ptr`.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, AtomicBox};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = AtomicBox::<[i32]>::new(vec![1, 2, 3].into_boxed_slice());
+    /// epoch::pin(|scope| {
+    ///     let p = a.swap_box(vec![4, 5].into_boxed_slice(), SeqCst, scope);
+    ///     unsafe { scope.defer_drop(p) }
+    /// });
+    /// ```
+    #[must_use = "the returned pointer may need to be unlinked and reclaimed"]
+    pub fn swap_box<'scope>(
+        &self,
+        new: Box<T>,
+        ord: Ordering,
+        scope: &'scope Scope,
+    ) -> Ptr<'scope, Box<T>> {
+        self.inner.swap(Owned::new(new).into_ptr(scope), ord, scope)
+    }
+
+    /// Stores a box into the atomic pointer if the current value is the same as `current`.
+    ///
+    /// The return value is a result indicating whether the new box was written. On success the
+    /// pointer that was written is returned. On failure `new` and the actual current value are
+    /// returned.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, AtomicBox};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = AtomicBox::<[i32]>::new(vec![1, 2, 3].into_boxed_slice());
+    ///
+    /// epoch::pin(|scope| {
+    ///     let curr = a.load(SeqCst, scope);
+    ///     let res = a.compare_and_swap_box(curr, vec![4, 5].into_boxed_slice(), SeqCst, scope);
+    ///     assert!(res.is_ok());
+    /// });
+    /// ```
+    pub fn compare_and_swap_box<'scope>(
+        &self,
+        current: Ptr<Box<T>>,
+        new: Box<T>,
+        ord: Ordering,
+        scope: &'scope Scope,
+    ) -> BoxCasResult<'scope, T> {
+        self.inner
+            .compare_and_swap_owned(current, Owned::new(new), ord, scope)
+            .map_err(|(p, o)| (p, *o.into_box()))
+    }
+
+    /// Stores a box into the atomic pointer if the current value is the same as `current`.
+    ///
+    /// Unlike [`compare_and_swap_box`], this method is allowed to spuriously fail even when
+    /// comparison succeeds, which can result in more efficient code on some platforms.
+    /// The return value is a result indicating whether the new box was written. On success the
+    /// pointer that was written is returned. On failure `new` and the actual current value are
+    /// returned.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`compare_and_swap_box`]: struct.AtomicBox.html#method.compare_and_swap_box
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, AtomicBox};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = AtomicBox::<[i32]>::new(vec![1, 2, 3].into_boxed_slice());
+    ///
+    /// epoch::pin(|scope| {
+    ///     let mut new = vec![4, 5].into_boxed_slice();
+    ///     let mut curr = a.load(SeqCst, scope);
+    ///     loop {
+    ///         match a.compare_and_swap_weak_box(curr, new, SeqCst, scope) {
+    ///             Ok(_) => break,
+    ///             Err((c, n)) => {
+    ///                 curr = c;
+    ///                 new = n;
+    ///             }
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn compare_and_swap_weak_box<'scope>(
+        &self,
+        current: Ptr<Box<T>>,
+        new: Box<T>,
+        ord: Ordering,
+        scope: &'scope Scope,
+    ) -> BoxCasResult<'scope, T> {
+        self.inner
+            .compare_and_swap_weak_owned(current, Owned::new(new), ord, scope)
+            .map_err(|(p, o)| (p, *o.into_box()))
+    }
+}
+
+impl<T: ?Sized> Default for AtomicBox<T> {
+    fn default() -> Self {
+        AtomicBox::null()
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for AtomicBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AtomicBox {{ ... }}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use epoch::{self, AtomicBox};
+
+    trait Shape {
+        fn area(&self) -> usize;
+    }
+
+    struct Square(usize, Arc<AtomicUsize>);
+
+    impl Shape for Square {
+        fn area(&self) -> usize {
+            self.0 * self.0
+        }
+    }
+
+    impl Drop for Square {
+        fn drop(&mut self) {
+            self.1.fetch_add(1, SeqCst);
+        }
+    }
+
+    #[test]
+    fn drop_trait_object() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let a: AtomicBox<dyn Shape + Send> = AtomicBox::new(Box::new(Square(3, drops.clone())));
+
+        epoch::pin(|scope| unsafe {
+            assert_eq!(a.load(SeqCst, scope).deref().area(), 9);
+
+            let old = a.swap_box(Box::new(Square(4, drops.clone())), SeqCst, scope);
+            assert_eq!(old.deref().area(), 9);
+            scope.defer_drop(old);
+            scope.flush();
+
+            assert_eq!(a.load(SeqCst, scope).deref().area(), 16);
+        });
+
+        while drops.load(SeqCst) == 0 {
+            epoch::pin(|scope| scope.flush());
+        }
+        assert_eq!(drops.load(SeqCst), 1);
+    }
+
+    #[test]
+    fn cas_returns_box() {
+        let a = AtomicBox::<[i32]>::new(vec![1, 2, 3].into_boxed_slice());
+
+        epoch::pin(|scope| unsafe {
+            let curr = a.load(SeqCst, scope);
+            let new = a.compare_and_swap_box(curr, vec![4].into_boxed_slice(), SeqCst, scope);
+            assert_eq!(&**new.unwrap().deref(), &[4]);
+
+            match a.compare_and_swap_box(curr, vec![5, 6].into_boxed_slice(), SeqCst, scope) {
+                Ok(_) => panic!(),
+                Err((c, b)) => {
+                    assert_eq!(&**c.deref(), &[4]);
+                    assert_eq!(&*b, &[5, 6]);
+                }
+            }
+
+            scope.defer_drop(curr);
+        });
+    }
+}
@@ -0,0 +1,175 @@
+//! Garbage collection on a dedicated background thread.
+//!
+//! By default, garbage is collected cooperatively: threads collect some while pinning and when
+//! their local bags get full. Applications that would rather keep collection off the pinning path
+//! of their worker threads can spawn a collector thread with [`spawn_collector`], and make the
+//! workers collect less often with [`set_collect_policy`].
+//!
+//! [`spawn_collector`]: ../fn.spawn_collector.html
+//! [`set_collect_policy`]: ../fn.set_collect_policy.html
+
+use std::fmt;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use epoch::garbage;
+use epoch::thread::{pin, try_advance};
+
+/// Advances the epoch and collects garbage until no more progress can be made.
+fn collect() {
+    /// Maximum number of rounds in a single wake-up, so that the thread stays responsive to being
+    /// stopped even if other threads keep producing garbage.
+    const MAX_ROUNDS: usize = 64;
+
+    // Collection itself produces garbage, which shouldn't linger in the local bag.
+    pin(|scope| scope.flush());
+
+    // Unpin between rounds so as not to hold the epoch back.
+    for _ in 0..MAX_ROUNDS {
+        let (advanced, destroyed, is_empty) = pin(|scope| {
+            let advanced = try_advance(scope);
+            let destroyed = garbage::collect(scope);
+            (advanced, destroyed, garbage::is_empty(scope))
+        });
+
+        if is_empty || (!advanced && destroyed == 0) {
+            break;
+        }
+    }
+}
+
+/// A handle to a background collector thread, returned by [`spawn_collector`].
+///
+/// Dropping the handle stops the thread and waits for it to exit.
+///
+/// [`spawn_collector`]: fn.spawn_collector.html
+pub struct CollectorThread {
+    /// Dropping it wakes up the thread and tells it to exit.
+    stop: Option<Sender<()>>,
+    /// The collector thread.
+    handle: Option<JoinHandle<()>>,
+}
+
+impl CollectorThread {
+    /// Stops the collector thread and waits for it to exit.
+    ///
+    /// Garbage that hasn't been collected by then is left for other threads to collect.
+    pub fn stop(mut self) {
+        self.join();
+    }
+
+    /// Tells the thread to exit and joins it.
+    fn join(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            // The thread catches panics from destructors of garbage, so it can't panic itself.
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for CollectorThread {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+impl fmt::Debug for CollectorThread {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CollectorThread {{ ... }}")
+    }
+}
+
+/// Spawns a thread that collects garbage every `interval`.
+///
+/// Every time it wakes up, the thread advances the epoch and destroys garbage in the global queue
+/// for as long as it can make progress, much like [`collect_all`]. It is pinned only for short
+/// moments at a time, so it never holds the epoch back for long. The thread registers as a
+/// participant when it starts and unregisters when it is stopped.
+///
+/// Worker threads still collect on their own as usual. To leave most of the work to the collector
+/// thread, set a collect policy that makes them collect rarely or never. Note that workers still
+/// collect when their local bags get full.
+///
+/// [`collect_all`]: fn.collect_all.html
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+/// use std::time::Duration;
+///
+/// let collector = epoch::spawn_collector(Duration::from_millis(10));
+///
+/// // Worker threads never collect while pinning.
+/// fn never(_thread_count: usize, _pin_count: usize) -> bool {
+///     false
+/// }
+/// epoch::set_collect_policy(never);
+///
+/// // ...
+///
+/// # epoch::set_collect_policy(epoch::default_collect_policy);
+/// collector.stop();
+/// ```
+pub fn spawn_collector(interval: Duration) -> CollectorThread {
+    let (stop, stopped) = mpsc::channel::<()>();
+
+    let handle = thread::Builder::new()
+        .name("coco-collector".to_string())
+        .spawn(move || {
+            // Keep collecting until the handle gets dropped.
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                collect();
+            }
+        })
+        .expect("failed to spawn the collector thread");
+
+    CollectorThread {
+        stop: Some(stop),
+        handle: Some(handle),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::SeqCst;
+    use std::thread;
+    use std::time::Duration;
+
+    use epoch::{self, Owned};
+
+    #[test]
+    fn collects_in_background() {
+        const COUNT: usize = 1000;
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Elem(usize);
+
+        impl Drop for Elem {
+            fn drop(&mut self) {
+                DROPS.fetch_add(self.0, SeqCst);
+            }
+        }
+
+        let collector = epoch::spawn_collector(Duration::from_millis(1));
+
+        thread::spawn(|| {
+            epoch::pin(|scope| unsafe {
+                for _ in 0..COUNT {
+                    scope.defer_drop(Owned::new(Elem(1)).into_ptr(scope));
+                }
+                scope.flush();
+            });
+        }).join().unwrap();
+
+        // Only the collector thread is collecting, unless other tests are running.
+        while DROPS.load(SeqCst) < COUNT {
+            thread::sleep(Duration::from_millis(1));
+        }
+        collector.stop();
+        assert_eq!(DROPS.load(SeqCst), COUNT);
+    }
+}
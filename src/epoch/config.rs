@@ -0,0 +1,318 @@
+//! Runtime configuration of the garbage collector.
+//!
+//! All settings are global and kept in atomic variables, so they can be changed at any time and
+//! from any thread. Threads pick up new values gradually, as they go through their pinning and
+//! collection routines.
+
+use std::cmp;
+use std::mem;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+#[cfg(test)]
+use std::sync::{Mutex, MutexGuard};
+
+use epoch::garbage::ReadyBag;
+
+/// Number of pinnings between consultations of the collection policy. Must be a power of two.
+pub const PINS_BETWEEN_CHECKS: usize = 16;
+
+/// Number of consecutive fruitless attempts at reclaiming garbage after which a thread backs off.
+static STALL_BACKOFF: AtomicUsize = AtomicUsize::new(8);
+
+/// The function called when a thread's local bag becomes full, or zero if there is none.
+static ON_BAG_FULL: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of objects in the global queue above which threads try harder to reclaim garbage.
+static MAX_PENDING_GARBAGE: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// The function called when pending garbage can't be reclaimed, or zero if there is none.
+static ON_GARBAGE_STALL: AtomicUsize = AtomicUsize::new(0);
+
+/// The function called with every bag of garbage ready for destruction, or zero if there is none.
+static COLLECT_HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// The function deciding when a pinning thread collects garbage, or zero for the default one.
+static COLLECT_POLICY: AtomicUsize = AtomicUsize::new(0);
+
+/// Configures the garbage collection backoff for stalled epochs.
+///
+/// Threads try advancing the global epoch and collecting some garbage whenever their local bag
+/// becomes full, and periodically while pinning. But if another thread stays pinned for a long
+/// time, the epoch cannot advance, nothing new can be destroyed, and those attempts are wasted
+/// work.
+///
+/// After `attempts` consecutive attempts that neither advanced the epoch nor destroyed anything,
+/// the thread backs off: for the next 128 pinnings it doesn't collect garbage and simply pushes
+/// full bags into the global queue, and it calls `std::thread::yield_now()` as soon as it gets
+/// unpinned, giving the stalling thread a chance to make progress. Every further run of `attempts`
+/// fruitless attempts doubles the number of skipped pinnings, up to 8192. Any progress resets the
+/// backoff. The thread never yields while pinned.
+///
+/// Setting `attempts` to zero disables the backoff. The default is 8 attempts.
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+///
+/// // Back off after 4 fruitless attempts in a row.
+/// epoch::set_stall_backoff(4);
+/// # epoch::set_stall_backoff(8);
+/// ```
+pub fn set_stall_backoff(attempts: usize) {
+    STALL_BACKOFF.store(attempts, Relaxed);
+}
+
+/// Returns the number of attempts configured by `set_stall_backoff`.
+pub fn stall_backoff() -> usize {
+    STALL_BACKOFF.load(Relaxed)
+}
+
+/// Sets a limit on the number of objects waiting for destruction in the global garbage queue.
+///
+/// If a thread stays pinned for too long, the epoch cannot advance and garbage keeps piling up,
+/// possibly until the process runs out of memory. Whenever a thread pushes a full bag into the
+/// global queue while the queue holds more than `n` objects, it makes an extra effort: it tries
+/// advancing the epoch and collecting garbage several times, ignoring any backoff, and yields to
+/// the scheduler after getting unpinned if that didn't help. Such a stall is also reported to the
+/// hook set by [`on_garbage_stall`], if any.
+///
+/// This cannot forcibly reclaim garbage that is still protected by a pinned thread, so it only
+/// mitigates the problem. The default is `usize::MAX`, i.e. no limit.
+///
+/// [`on_garbage_stall`]: fn.on_garbage_stall.html
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+///
+/// epoch::set_max_pending_garbage(1 << 20);
+/// # epoch::set_max_pending_garbage(usize::MAX);
+/// ```
+pub fn set_max_pending_garbage(n: usize) {
+    MAX_PENDING_GARBAGE.store(n, Relaxed);
+}
+
+/// Returns the limit configured by `set_max_pending_garbage`.
+pub fn max_pending_garbage() -> usize {
+    MAX_PENDING_GARBAGE.load(Relaxed)
+}
+
+/// Sets a function that gets called whenever pending garbage exceeds the limit and can't be
+/// reclaimed.
+///
+/// This happens when the limit set by [`set_max_pending_garbage`] is exceeded and the extra
+/// effort at reclaiming garbage doesn't bring the global queue back under it, usually because a
+/// thread has been pinned for too long. The function gets the number of pending objects and the
+/// number of threads that are pinned in an older epoch, i.e. that are holding the epoch back. It
+/// is called on the thread that pushed the garbage, while it is pinned, so it should be short and
+/// must not pin the thread itself.
+///
+/// Passing `None` removes the hook. There is none by default.
+///
+/// [`set_max_pending_garbage`]: fn.set_max_pending_garbage.html
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+/// use std::sync::atomic::AtomicUsize;
+/// use std::sync::atomic::Ordering::Relaxed;
+///
+/// static LAGGING: AtomicUsize = AtomicUsize::new(0);
+///
+/// fn report(_pending: usize, lagging: usize) {
+///     LAGGING.store(lagging, Relaxed);
+/// }
+///
+/// epoch::on_garbage_stall(Some(report));
+/// # epoch::on_garbage_stall(None);
+/// ```
+pub fn on_garbage_stall(f: Option<fn(usize, usize)>) {
+    ON_GARBAGE_STALL.store(f.map_or(0, |f| f as usize), Release);
+}
+
+/// Returns the function set by `on_garbage_stall`, if any.
+pub fn garbage_stall_hook() -> Option<fn(usize, usize)> {
+    let f = ON_GARBAGE_STALL.load(Acquire);
+    if f == 0 {
+        None
+    } else {
+        Some(unsafe { mem::transmute::<usize, fn(usize, usize)>(f) })
+    }
+}
+
+/// Sets a function that gets called whenever a thread's local bag becomes full.
+///
+/// A full bag means that the thread is about to push it into the global queue and start a fresh
+/// one. Frequent calls indicate high deferral pressure, so this hook is useful for monitoring or
+/// for throttling producers of garbage. The function is called on the thread that filled the bag,
+/// while it is pinned, so it should be short and must not pin the thread itself.
+///
+/// Passing `None` removes the hook. There is none by default.
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+/// use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT};
+/// use std::sync::atomic::Ordering::Relaxed;
+///
+/// static FULL_BAGS: AtomicUsize = ATOMIC_USIZE_INIT;
+///
+/// fn count() {
+///     FULL_BAGS.fetch_add(1, Relaxed);
+/// }
+///
+/// epoch::on_bag_full(Some(count));
+/// ```
+pub fn on_bag_full(f: Option<fn()>) {
+    ON_BAG_FULL.store(f.map_or(0, |f| f as usize), Release);
+}
+
+/// Calls the function set by `on_bag_full`, if any.
+pub fn bag_full() {
+    let f = ON_BAG_FULL.load(Acquire);
+    if f != 0 {
+        let f: fn() = unsafe { mem::transmute(f) };
+        f();
+    }
+}
+
+/// The collection policy used by default.
+///
+/// A thread collects on every 128th pinning while there are at most four registered threads.
+/// Every time the number of threads doubles beyond that, the interval is doubled as well (up to
+/// 8192 pinnings), so that the total rate of collections stays roughly the same no matter how many
+/// threads there are. Advancing the epoch traverses the list of all threads, so with many of them
+/// each collection is more expensive too.
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+///
+/// assert!(epoch::default_collect_policy(1, 128));
+/// assert!(!epoch::default_collect_policy(1, 129));
+/// assert!(!epoch::default_collect_policy(64, 128));
+/// ```
+pub fn default_collect_policy(thread_count: usize, pin_count: usize) -> bool {
+    /// Number of pinnings between collections with few threads.
+    const MIN_INTERVAL: usize = 128;
+    /// Maximum number of pinnings between collections.
+    const MAX_INTERVAL: usize = 8192;
+    /// Number of threads up to which the minimum interval is used.
+    const FEW_THREADS: usize = 4;
+
+    let scale = (thread_count / FEW_THREADS).next_power_of_two();
+    // The interval is a power of two.
+    let interval = cmp::min(MIN_INTERVAL.saturating_mul(scale), MAX_INTERVAL);
+    pin_count & (interval - 1) == 0
+}
+
+/// Sets the function deciding when a pinning thread collects garbage.
+///
+/// On every 16th pinning of a thread (not counting reentrant pinning), `f` is called with the
+/// number of currently registered threads and the number of times this thread has been pinned so
+/// far, which is therefore always a multiple of 16. If it returns `true`, the thread tries
+/// advancing the epoch and collects some garbage from the global queue. Collection also happens
+/// independently of this policy when a thread's local bag becomes full, or when garbage piles up
+/// in the global queue.
+///
+/// The function is called while pinning, so it should be very cheap. The default is
+/// [`default_collect_policy`], which can also be passed here to restore it.
+///
+/// [`default_collect_policy`]: fn.default_collect_policy.html
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+///
+/// // Collect on every 64th pinning regardless of the number of threads.
+/// fn every_64th(_thread_count: usize, pin_count: usize) -> bool {
+///     pin_count % 64 == 0
+/// }
+///
+/// epoch::set_collect_policy(every_64th);
+/// # epoch::set_collect_policy(epoch::default_collect_policy);
+/// ```
+pub fn set_collect_policy(f: fn(usize, usize) -> bool) {
+    COLLECT_POLICY.store(f as usize, Release);
+}
+
+/// Returns `true` if a thread pinned for the `pin_count`-th time should collect garbage.
+///
+/// This is only called when `pin_count` is a multiple of `PINS_BETWEEN_CHECKS`.
+#[inline]
+pub fn should_collect(thread_count: usize, pin_count: usize) -> bool {
+    let f = COLLECT_POLICY.load(Acquire);
+    if f == 0 {
+        default_collect_policy(thread_count, pin_count)
+    } else {
+        let f: fn(usize, usize) -> bool = unsafe { mem::transmute(f) };
+        f(thread_count, pin_count)
+    }
+}
+
+/// Sets a function that releases the objects in every bag of garbage that gets collected.
+///
+/// By default, collected objects are destroyed one by one in the order they were deferred, each
+/// the way it was deferred (see [`defer_free`] and [`defer_drop`]). A hook can take over: it gets
+/// a [`ReadyBag`] and must destroy every object in it, e.g. after sorting them by address for
+/// friendlier access patterns, or by batching frees to a custom allocator. Objects the hook
+/// doesn't destroy are leaked.
+///
+/// The hook is called on whichever thread collects the garbage, possibly while it is pinned or
+/// exiting, so it must not pin the thread itself. If it panics, the panic is caught and the
+/// remaining objects in the bag are leaked.
+///
+/// Passing `None` restores the default. There is no hook by default.
+///
+/// [`defer_free`]: struct.Scope.html#method.defer_free
+/// [`defer_drop`]: struct.Scope.html#method.defer_drop
+/// [`ReadyBag`]: struct.ReadyBag.html
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch::{self, ReadyBag};
+///
+/// // Destroy objects in the order of their addresses.
+/// fn sorted(bag: &ReadyBag) {
+///     let mut objects = bag.iter().collect::<Vec<_>>();
+///     objects.sort_by_key(|o| o.object() as usize);
+///     for o in objects {
+///         unsafe { o.destroy() }
+///     }
+/// }
+///
+/// epoch::set_collect_hook(Some(sorted));
+/// # epoch::set_collect_hook(None);
+/// ```
+pub fn set_collect_hook(f: Option<fn(&ReadyBag)>) {
+    COLLECT_HOOK.store(f.map_or(0, |f| f as usize), Release);
+}
+
+/// Returns the function set by `set_collect_hook`, if any.
+pub fn collect_hook() -> Option<fn(&ReadyBag)> {
+    let f = COLLECT_HOOK.load(Acquire);
+    if f == 0 {
+        None
+    } else {
+        Some(unsafe { mem::transmute::<usize, fn(&ReadyBag)>(f) })
+    }
+}
+
+/// Serializes unit tests that change the global configuration.
+///
+/// The settings affect every thread, so two such tests running in parallel would see each other's
+/// changes. Every test that changes a setting holds this lock until it has restored the default.
+#[cfg(test)]
+pub fn test_lock() -> MutexGuard<'static, ()> {
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    // A failed test poisons the lock, but the settings it changed are restored by then.
+    LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
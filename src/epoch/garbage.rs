@@ -24,15 +24,23 @@
 //! populated with garbage, and when it becomes full, it is finally pushed into queue. This design
 //! reduces contention on data structures. The global queue cannot be explicitly accessed - the
 //! only way to interact with it is by calling function `defer_free`.
+//!
+//! To further reduce contention, the global queue is split into shards, about one per CPU. Each
+//! thread pushes its bags into its own shard, while collection visits the shards in turn.
 
-use std::cell::UnsafeCell;
+#[cfg(feature = "debug_backtrace")]
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell, UnsafeCell};
 use std::cmp;
 use std::fmt;
 use std::mem;
-use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT};
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::sync::atomic::{AtomicBool, AtomicUsize, ATOMIC_USIZE_INIT};
 use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, SeqCst};
 
 use epoch::{self, Atomic, Owned, Scope, Ptr};
+use epoch::config;
 
 /// Maximum number of objects a bag can contain.
 #[cfg(not(feature = "strict_gc"))]
@@ -40,6 +48,58 @@ const MAX_OBJECTS: usize = 64;
 #[cfg(feature = "strict_gc")]
 const MAX_OBJECTS: usize = 4;
 
+/// Number of bags to destroy in a single collection.
+const COLLECT_STEPS: usize = 8;
+
+/// Maximum number of shards of the global garbage queue.
+const MAX_SHARDS: usize = 64;
+
+/// Maximum number of empty bags a thread keeps for reuse.
+const MAX_FREE_BAGS: usize = 4;
+
+/// Number of bags in the global queue above which it is considered to have a backlog.
+const BACKLOG_BAGS: usize = 16;
+
+/// Whether the global queue had a backlog when it was last pushed into or collected from.
+static BACKLOG: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Empty bags that can be reused instead of allocating new ones.
+    static FREE_BAGS: FreeBags = FreeBags::new();
+
+    /// The number from which the index of the shard this thread pushes bags into is derived.
+    static SHARD: usize = next_shard();
+
+    /// Whether this thread is currently destroying collected garbage.
+    static COLLECTING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Returns a new shard number, so that threads get spread evenly across the shards.
+fn next_shard() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    NEXT.fetch_add(1, Relaxed)
+}
+
+/// A thread-local list of empty bags, which are deallocated when the thread exits.
+struct FreeBags {
+    bags: RefCell<Vec<*mut Bag>>,
+}
+
+impl FreeBags {
+    /// Returns a new, empty free list.
+    fn new() -> Self {
+        FreeBags { bags: RefCell::new(Vec::with_capacity(MAX_FREE_BAGS)) }
+    }
+}
+
+impl Drop for FreeBags {
+    fn drop(&mut self) {
+        for &bag in self.bags.borrow().iter() {
+            unsafe { drop(Box::from_raw(bag)) }
+        }
+    }
+}
+
 /// The global epoch.
 ///
 /// The last bit in this number is unused and is always zero. Every so often the global epoch is
@@ -50,12 +110,18 @@ const MAX_OBJECTS: usize = 4;
 /// thread will hold a reference to it. That is the crux of safe memory reclamation.
 pub static EPOCH: AtomicUsize = ATOMIC_USIZE_INIT;
 
+/// A type-erased function that destroys an object given its address and count.
+type DestroyFn = unsafe fn(*mut u8, usize);
+
 /// Holds removed objects that will be eventually destroyed.
 pub struct Bag {
     /// Number of objects in the bag.
     len: AtomicUsize,
     /// Removed objects.
-    objects: [UnsafeCell<(unsafe fn(*mut u8, usize), *mut u8, usize)>; MAX_OBJECTS],
+    objects: [UnsafeCell<(DestroyFn, *mut u8, usize)>; MAX_OBJECTS],
+    /// Backtraces of the places where the objects were deferred, or null if not captured.
+    #[cfg(feature = "debug_backtrace")]
+    backtraces: [UnsafeCell<*mut Backtrace>; MAX_OBJECTS],
     /// The global epoch at the moment when this bag got pushed into the queue.
     epoch: usize,
     /// The next bag in the queue.
@@ -68,21 +134,52 @@ impl Bag {
         Bag {
             len: AtomicUsize::new(0),
             objects: unsafe { mem::zeroed() },
+            #[cfg(feature = "debug_backtrace")]
+            backtraces: unsafe { mem::zeroed() },
             epoch: unsafe { mem::uninitialized() },
             next: Atomic::null(),
         }
     }
 
+    /// Makes the bag empty again so that it can be reused.
+    fn reset(&mut self) {
+        self.len.store(0, Relaxed);
+        self.next.store(Ptr::null(), Relaxed);
+    }
+
+    /// Returns the number of objects in the bag.
+    pub fn len(&self) -> usize {
+        self.len.load(Relaxed)
+    }
+
+    /// Returns the number of objects that can still be inserted into the bag.
+    pub fn remaining(&self) -> usize {
+        MAX_OBJECTS - self.len()
+    }
+
     /// Returns `true` if the bag is empty.
     pub fn is_empty(&self) -> bool {
-        self.len.load(Relaxed) == 0
+        self.len() == 0
+    }
+
+    /// Returns `true` if the global epoch has advanced far enough past the bag's epoch for its
+    /// objects to be destroyed.
+    ///
+    /// A pinned thread can witness at most one epoch advancement. Therefore, any bag that is
+    /// within one epoch of the current one cannot be destroyed yet. The epoch wraps around, so
+    /// the distance is measured forward from the bag's epoch with wrapping arithmetic. A bag that
+    /// appears to be ahead of `epoch` was sealed in an epoch the caller hasn't observed yet, so it
+    /// is not expired either.
+    fn is_expired(&self, epoch: usize) -> bool {
+        let diff = epoch.wrapping_sub(self.epoch);
+        diff > 2 && diff <= usize::MAX / 2
     }
 
     /// Attempts to insert a garbage object into the bag and returns `true` if succeeded.
     pub fn try_insert<T>(&self, destroy: unsafe fn(*mut T, usize), object: *const T, count: usize)
                          -> bool {
         // Erase type `*mut T` and use `*mut u8` instead.
-        let destroy: unsafe fn(*mut u8, usize) = unsafe { mem::transmute(destroy) };
+        let destroy: DestroyFn = unsafe { mem::transmute(destroy) };
         let object = object as *const u8 as *mut u8;
 
         let mut len = self.len.load(Acquire);
@@ -98,6 +195,12 @@ impl Bag {
                     // Success! Now store the garbage object into the array. The current thread
                     // will synchronize with the thread that destroys it through epoch advancement.
                     unsafe { *self.objects[len].get() = (destroy, object, count) }
+
+                    #[cfg(feature = "debug_backtrace")]
+                    unsafe {
+                        let bt = Box::new(Backtrace::force_capture());
+                        *self.backtraces[len].get() = Box::into_raw(bt);
+                    }
                     return true;
                 }
                 Err(l) => len = l,
@@ -107,12 +210,120 @@ impl Bag {
 
     /// Destroys all objects in the bag.
     ///
+    /// If destruction of an object panics, the panic is caught and the remaining objects are still
+    /// destroyed. The panic message gets reported by the panic hook as usual, but the panic is not
+    /// propagated further, since it would otherwise unwind through the garbage collector (which
+    /// might even be running within a thread-local destructor).
+    ///
+    /// Returns the number of destroyed objects.
+    ///
     /// Note: can be called only once!
-    unsafe fn destroy_all_objects(&self) {
-        for cell in self.objects.iter().take(self.len.load(Relaxed)) {
-            let (destroy, object, count) = *cell.get();
-            destroy(object, count);
+    ///
+    /// If a collect hook is set, the bag is handed to it instead (see [`set_collect_hook`]). A panic
+    /// in the hook is caught as well, and the objects it hasn't destroyed by then are leaked.
+    ///
+    /// [`set_collect_hook`]: ../fn.set_collect_hook.html
+    unsafe fn destroy_all_objects(&self) -> usize {
+        let len = self.len.load(Relaxed);
+        match config::collect_hook() {
+            None => {
+                for object in (ReadyBag { bag: self }).iter() {
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| object.destroy()));
+                }
+            }
+            Some(hook) => {
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| hook(&ReadyBag { bag: self })));
+            }
         }
+
+        #[cfg(feature = "debug_backtrace")]
+        for cell in self.backtraces.iter().take(len) {
+            let bt = mem::replace(&mut *cell.get(), ::std::ptr::null_mut());
+            if !bt.is_null() {
+                drop(Box::from_raw(bt));
+            }
+        }
+        len
+    }
+
+    /// Returns the backtrace captured when the `index`-th object was deferred, if any.
+    #[cfg(feature = "debug_backtrace")]
+    fn backtrace(&self, index: usize) -> Option<&Backtrace> {
+        unsafe { (*self.backtraces[index].get()).as_ref() }
+    }
+}
+
+/// An object whose destruction was deferred, as found in a [`ReadyBag`].
+///
+/// [`ReadyBag`]: struct.ReadyBag.html
+#[derive(Clone, Copy, Debug)]
+pub struct DeferredObject {
+    destroy: DestroyFn,
+    object: *mut u8,
+    count: usize,
+}
+
+impl DeferredObject {
+    /// Returns the address of the object.
+    pub fn object(&self) -> *mut u8 {
+        self.object
+    }
+
+    /// Returns the function that destroys the object when called with its address and count.
+    pub fn destroy_fn(&self) -> unsafe fn(*mut u8, usize) {
+        self.destroy
+    }
+
+    /// Returns the value passed to the destroying function along with the address, which usually
+    /// holds the number of elements in the object.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Destroys the object the way it was deferred, e.g. by dropping and freeing it.
+    ///
+    /// # Safety
+    ///
+    /// Every object in a bag must be destroyed at most once.
+    pub unsafe fn destroy(self) {
+        (self.destroy)(self.object, self.count)
+    }
+}
+
+/// A bag of objects that are ready to be destroyed.
+///
+/// No thread can hold a reference to any of these objects anymore. Ready bags are handed to the
+/// collect hook set by [`set_collect_hook`], which decides how their objects are released.
+///
+/// [`set_collect_hook`]: fn.set_collect_hook.html
+pub struct ReadyBag<'a> {
+    bag: &'a Bag,
+}
+
+impl<'a> ReadyBag<'a> {
+    /// Returns the number of objects in the bag.
+    pub fn len(&self) -> usize {
+        self.bag.len()
+    }
+
+    /// Returns `true` if the bag is empty.
+    pub fn is_empty(&self) -> bool {
+        self.bag.is_empty()
+    }
+
+    /// Returns an iterator over the objects in the bag, in the order they were deferred.
+    pub fn iter(&self) -> impl Iterator<Item = DeferredObject> + 'a {
+        let bag = self.bag;
+        bag.objects.iter().take(bag.len()).map(|cell| {
+            let (destroy, object, count) = unsafe { *cell.get() };
+            DeferredObject { destroy, object, count }
+        })
+    }
+}
+
+impl<'a> fmt::Debug for ReadyBag<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
     }
 }
 
@@ -136,6 +347,10 @@ pub struct Garbage {
     tail: Atomic<Bag>,
     /// The next bag that will be pushed into the queue as soon as it gets full.
     pending: Atomic<Bag>,
+    /// Number of bags in the queue.
+    bags: AtomicUsize,
+    /// Number of objects in the bags in the queue.
+    objects: AtomicUsize,
 }
 
 unsafe impl Send for Garbage {}
@@ -148,17 +363,21 @@ impl Garbage {
             head: Atomic::null(),
             tail: Atomic::null(),
             pending: Atomic::null(),
+            bags: AtomicUsize::new(0),
+            objects: AtomicUsize::new(0),
         };
 
         // This code may be executing while a thread harness is initializing, so normal pinning
         // would try to access it while it is being initialized. Such accesses fail with a panic.
-        // We must therefore cheat by creating a fake pin.
-        let pin = unsafe { &mem::zeroed::<Scope>() };
-
-        // The head of the queue is always a sentinel entry.
-        let sentinel = Owned::new(Bag::new()).into_ptr(pin);
-        garbage.head.store(sentinel, Relaxed);
-        garbage.tail.store(sentinel, Relaxed);
+        // We must therefore use an unprotected scope.
+        unsafe {
+            epoch::unprotected(|pin| {
+                // The head of the queue is always a sentinel entry.
+                let sentinel = Owned::new(Bag::new()).into_ptr(pin);
+                garbage.head.store(sentinel, Relaxed);
+                garbage.tail.store(sentinel, Relaxed);
+            })
+        }
 
         garbage
     }
@@ -170,7 +389,8 @@ impl Garbage {
     /// pending bag is returned.
     fn replace_pending<'p>(&self, old: Ptr<'p, Bag>, scope: &'p Scope)
                            -> Result<Ptr<'p, Bag>, Ptr<'p, Bag>> {
-        match self.pending.compare_and_swap_weak_owned(old, Owned::new(Bag::new()), AcqRel, scope) {
+        let new = Owned::from_box(alloc_bag());
+        match self.pending.compare_and_swap_weak_owned(old, new, AcqRel, scope) {
             Ok(new) => {
                 if !old.is_null() {
                     // Push the old bag into the queue.
@@ -212,8 +432,8 @@ impl Garbage {
     /// This method inserts the object into the garbage buffer. When the buffers becomes full, it's
     /// objects are flushed into the garbage queue.
     ///
-    /// Note: The object must be `Send + 'self`.
-    pub unsafe fn defer_drop<T>(&self, object: *const T, count: usize, scope: &Scope) {
+    /// Note: The object must outlive `'self`.
+    pub unsafe fn defer_drop<T: Send>(&self, object: *const T, count: usize, scope: &Scope) {
         unsafe fn destruct<T>(ptr: *mut T, count: usize) {
             // Run the destructors and free the memory.
             drop(Vec::from_raw_parts(ptr, count, count));
@@ -229,6 +449,8 @@ impl Garbage {
     /// This method inserts the object into the garbage buffer. When the buffers becomes full, it's
     /// objects are flushed into the garbage queue.
     ///
+    /// If `object` is null, this method does nothing.
+    ///
     /// Note: The object must be `Send + 'self`.
     pub unsafe fn defer_destroy<T>(
         &self,
@@ -237,6 +459,11 @@ impl Garbage {
         count: usize,
         scope: &Scope
     ) {
+        // There's nothing to destroy behind a null pointer.
+        if object.is_null() {
+            return;
+        }
+
         let mut pending = self.pending.load(Acquire, scope);
         loop {
             match pending.as_ref() {
@@ -283,31 +510,88 @@ impl Garbage {
     ///
     /// This method collects several buffers worth of garbage objects.
     pub fn collect(&self, scope: &Scope) {
-        /// Number of bags to destroy.
-        const COLLECT_STEPS: usize = 8;
-
-        let epoch = EPOCH.load(SeqCst);
-        let condition = |bag: &Bag| {
-            // A pinned thread can witness at most one epoch advancement. Therefore, any bag that
-            // is within one epoch of the current one cannot be destroyed yet.
-            let diff = epoch.wrapping_sub(bag.epoch);
-            cmp::min(diff, 0usize.wrapping_sub(diff)) > 2
-        };
+        self.collect_objects(scope);
+    }
+
+    /// Collects some garbage from the queue, destroys it, and returns the number of destroyed
+    /// objects.
+    fn collect_objects(&self, scope: &Scope) -> usize {
+        self.collect_bags(COLLECT_STEPS, scope).1
+    }
+
+    /// Collects at most `steps` bags from the queue and destroys their objects.
+    ///
+    /// Returns the number of collected bags and the number of destroyed objects.
+    ///
+    /// Destroying an object may run arbitrary code, e.g. a destructor that defers destruction of
+    /// other objects. Once its local bag gets full, such code would push it into the queue and
+    /// collect garbage again, recursing into the collector while it is in the middle of a
+    /// collection. To prevent that, the bags are first detached from the queue, and only then are
+    /// their objects destroyed. Collections started on this thread in the meantime don't do
+    /// anything, so garbage produced during destruction simply lands in the queue and gets
+    /// collected later.
+    fn collect_bags(&self, steps: usize, scope: &Scope) -> (usize, usize) {
+        self.collect_bags_at(EPOCH.load(SeqCst), steps, scope)
+    }
+
+    /// Collects at most `steps` bags that have expired by `epoch` and destroys their objects.
+    fn collect_bags_at(&self, epoch: usize, steps: usize, scope: &Scope) -> (usize, usize) {
+        let steps = cmp::min(steps, COLLECT_STEPS);
+
+        let is_nested = COLLECTING.try_with(|c| c.replace(true)).unwrap_or(false);
+        if is_nested {
+            return (0, 0);
+        }
+        defer! {{
+            let _ = COLLECTING.try_with(|c| c.set(false));
+        }}
 
-        for _ in 0..COLLECT_STEPS {
+        let condition = |bag: &Bag| bag.is_expired(epoch);
+
+        // Detach the bags from the queue.
+        let mut detached: [Option<&Bag>; COLLECT_STEPS] = [None; COLLECT_STEPS];
+        let mut bags = 0;
+        while bags < steps {
             match self.try_pop_if(&condition, scope) {
                 None => break,
-                Some(bag) => unsafe { bag.destroy_all_objects() },
+                Some(bag) => {
+                    detached[bags] = Some(bag);
+                    bags += 1;
+                }
             }
         }
+
+        // Now destroy their objects. The popped bags stay allocated while the thread is pinned.
+        let mut destroyed = 0;
+        for bag in detached.iter().take(bags).flat_map(|b| *b) {
+            destroyed += unsafe { bag.destroy_all_objects() };
+        }
+        (bags, destroyed)
+    }
+
+    /// Returns `true` if there are no bags in the queue.
+    fn is_empty(&self, scope: &Scope) -> bool {
+        let head = self.head.load(Acquire, scope);
+        unsafe { head.deref().next.load(Acquire, scope).is_null() }
     }
 
     /// Pushes a bag into the queue.
-    fn push(&self, mut bag: Box<Bag>, scope: &Scope) {
+    fn push(&self, bag: Box<Bag>, scope: &Scope) {
         // Mark the bag with the current epoch.
-        bag.epoch = EPOCH.load(SeqCst);
+        self.push_at(bag, EPOCH.load(SeqCst), scope);
+    }
+
+    /// Pushes a bag marked with `epoch` into the queue.
+    fn push_at(&self, mut bag: Box<Bag>, epoch: usize, scope: &Scope) {
+        bag.epoch = epoch;
+        let len = bag.len();
         let mut bag = Owned::from_box(bag);
 
+        // Count the bag before it gets linked, so that a concurrent pop can't decrement the
+        // counters before they were incremented.
+        self.bags.fetch_add(1, Relaxed);
+        self.objects.fetch_add(len, Relaxed);
+
         let mut tail = self.tail.load(Acquire, scope);
         loop {
             let next = unsafe { tail.deref().next.load(Acquire, scope) };
@@ -351,8 +635,11 @@ impl Garbage {
                     // Try moving the head forward.
                     match self.head.compare_and_swap_weak(head, next, AcqRel, scope) {
                         Ok(()) => {
-                            // The old head may be later freed.
-                            unsafe { scope.defer_free(head) }
+                            self.bags.fetch_sub(1, Relaxed);
+                            self.objects.fetch_sub(n.len(), Relaxed);
+
+                            // The old head may be later reused.
+                            unsafe { epoch::thread::defer_bag(scope, head) }
                             // The new head holds the popped value (heads are sentinels!).
                             return Some(n);
                         }
@@ -406,15 +693,49 @@ impl fmt::Debug for Garbage {
     }
 }
 
-/// Returns a reference to a global garbage, which is lazily initialized.
-fn global() -> &'static Garbage {
+/// The global garbage queue, split into shards.
+///
+/// With a single queue, all threads would contend on its head and tail. Instead, every thread
+/// pushes its bags into its own shard, while collection goes through the shards in a round-robin
+/// fashion. There are about as many shards as there are CPUs.
+struct Shards {
+    /// The shards.
+    queues: Vec<Garbage>,
+    /// The shard to start the next collection from.
+    cursor: AtomicUsize,
+}
+
+impl Shards {
+    /// Returns new, empty shards.
+    fn new() -> Self {
+        let count = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .next_power_of_two();
+
+        Shards {
+            queues: (0..cmp::min(count, MAX_SHARDS)).map(|_| Garbage::new()).collect(),
+            cursor: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the shard the current thread pushes bags into.
+    fn local(&self) -> &Garbage {
+        // If the thread is exiting, any shard will do.
+        let shard = SHARD.try_with(|s| *s).unwrap_or(0);
+        &self.queues[shard % self.queues.len()]
+    }
+}
+
+/// Returns a reference to the global garbage, which is lazily initialized.
+fn global() -> &'static Shards {
     static GLOBAL: AtomicUsize = ATOMIC_USIZE_INIT;
 
     let current = GLOBAL.load(Acquire);
 
-    let garbage = if current == 0 {
+    let shards = if current == 0 {
         // Initialize the singleton.
-        let raw = Box::into_raw(Box::new(Garbage::new()));
+        let raw = Box::into_raw(Box::new(Shards::new()));
         let new = raw as usize;
         let previous = GLOBAL.compare_and_swap(0, new, AcqRel);
 
@@ -430,17 +751,167 @@ fn global() -> &'static Garbage {
         current
     };
 
-    unsafe { &*(garbage as *const Garbage) }
+    unsafe { &*(shards as *const Shards) }
+}
+
+/// Returns an empty bag, taking it from the thread-local free list if possible.
+pub fn alloc_bag() -> Box<Bag> {
+    FREE_BAGS
+        .try_with(|free| free.bags.borrow_mut().pop())
+        .ok()
+        .and_then(|bag| bag)
+        .map(|bag| unsafe { Box::from_raw(bag) })
+        .unwrap_or_else(new_bag)
 }
 
-/// Pushes a bag into the global garbage.
+/// Allocates a new, empty bag.
+fn new_bag() -> Box<Bag> {
+    // Bags get cached in free lists and reused as queue sentinels, so they outlive the code that
+    // happened to allocate them and mustn't be reported as its leaks.
+    #[cfg(any(test, feature = "internals"))]
+    return ::checked_alloc::untracked(|| Box::new(Bag::new()));
+
+    #[cfg(not(any(test, feature = "internals")))]
+    Box::new(Bag::new())
+}
+
+/// Returns a bag whose objects have already been destroyed to the thread-local free list.
+///
+/// If the free list is full or the thread is exiting, the bag is deallocated instead.
+pub fn recycle_bag(mut bag: Box<Bag>) {
+    bag.reset();
+    let _ = FREE_BAGS.try_with(|free| {
+        let mut bags = free.bags.borrow_mut();
+        if bags.len() < MAX_FREE_BAGS {
+            bags.push(Box::into_raw(bag));
+        }
+    });
+}
+
+/// Pushes a bag into the current thread's shard of the global garbage.
 pub fn push(bag: Box<Bag>, scope: &Scope) {
-    global().push(bag, scope);
+    global().local().push(bag, scope);
+    update_backlog();
+}
+
+/// Collects several bags from the global queue, destroys their objects, and returns the number of
+/// destroyed objects.
+///
+/// Shards are visited in a round-robin fashion until enough bags have been collected.
+pub fn collect(scope: &Scope) -> usize {
+    #[cfg(feature = "metrics")]
+    epoch::metrics::record_collection();
+
+    let global = global();
+    let len = global.queues.len();
+    let start = global.cursor.fetch_add(1, Relaxed);
+
+    let mut steps = COLLECT_STEPS;
+    let mut destroyed = 0;
+    for i in 0..len {
+        let queue = &global.queues[start.wrapping_add(i) % len];
+        let (bags, objects) = queue.collect_bags(steps, scope);
+        steps -= bags;
+        destroyed += objects;
+
+        if steps == 0 {
+            break;
+        }
+    }
+
+    update_backlog();
+    destroyed
+}
+
+/// Returns `true` if the global garbage queue is empty.
+pub fn is_empty(scope: &Scope) -> bool {
+    global().queues.iter().all(|q| q.is_empty(scope))
+}
+
+/// Destroys all bags in the global queue regardless of their epochs, and returns the number of
+/// destroyed objects.
+///
+/// # Safety
+///
+/// No object in the queue may still be in use by any thread.
+#[cfg(feature = "test_util")]
+pub unsafe fn destroy_all(scope: &Scope) -> usize {
+    let mut destroyed = 0;
+    for queue in global().queues.iter() {
+        while let Some(bag) = queue.try_pop_if(|_| true, scope) {
+            destroyed += bag.destroy_all_objects();
+        }
+    }
+    destroyed
+}
+
+/// Adds up a per-shard counter.
+///
+/// The shards are read one by one while other threads push and pop, so the total is only a
+/// snapshot. The counters are added with wrapping arithmetic and treated as signed, so that a
+/// snapshot that would come out negative is clamped to zero instead of overflowing.
+fn sum_counters<F>(counter: F) -> usize
+    where F: Fn(&Garbage) -> &AtomicUsize
+{
+    let total = global()
+        .queues
+        .iter()
+        .fold(0usize, |sum, q| sum.wrapping_add(counter(q).load(Relaxed)));
+    cmp::max(total as isize, 0) as usize
+}
+
+/// Returns the number of bags in the global queue.
+pub fn bag_count() -> usize {
+    sum_counters(|q| &q.bags)
+}
+
+/// Prints where each object in the global queue was deferred to standard error.
+///
+/// This is used for reporting leaks, so it's expected that no other thread is accessing the queue.
+#[cfg(feature = "debug_backtrace")]
+pub fn print_backtraces(scope: &Scope) {
+    for queue in &global().queues {
+        let head = queue.head.load(Acquire, scope);
+        let mut curr = unsafe { head.deref().next.load(Acquire, scope) };
+
+        while let Some(bag) = unsafe { curr.as_ref() } {
+            for i in 0..bag.len() {
+                if let Some(bt) = bag.backtrace(i) {
+                    eprintln!("leaked object deferred at:\n{}", bt);
+                }
+            }
+            curr = bag.next.load(Acquire, scope);
+        }
+    }
+}
+
+/// Returns the number of objects in the bags in the global queue.
+pub fn object_count() -> usize {
+    sum_counters(|q| &q.objects)
 }
 
-/// Collects several bags from the global queue and destroys their objects.
-pub fn collect(scope: &Scope) {
-    global().collect(scope);
+/// Returns `true` if so many bags have piled up in the global queue that threads should help
+/// collecting them.
+///
+/// A single call to `collect` destroys a bounded number of bags in order to bound its latency. If
+/// only the threads producing garbage collected it, they could fall behind, so all pinning threads
+/// help as long as there is a backlog.
+///
+/// This is called while pinning, so instead of adding up the counters of all shards, it just reads
+/// a flag that gets updated whenever a bag is pushed or garbage is collected.
+#[inline]
+pub fn has_backlog() -> bool {
+    BACKLOG.load(Relaxed)
+}
+
+/// Recomputes whether the global queue has a backlog.
+fn update_backlog() {
+    let backlog = bag_count() > BACKLOG_BAGS;
+
+    // Avoid writing into the shared cache line unless the flag changes.
+    if BACKLOG.load(Relaxed) != backlog {
+        BACKLOG.store(backlog, Relaxed);
+    }
 }
 
 /// Destroys the global garbage.
@@ -451,7 +922,7 @@ pub fn collect(scope: &Scope) {
 /// thread has never been pinned.
 #[cfg(feature = "internals")]
 pub unsafe fn destroy_global() {
-    let global = global() as *const Garbage as *mut Garbage;
+    let global = global() as *const Shards as *mut Shards;
     drop(Box::from_raw(global));
 }
 
@@ -460,6 +931,8 @@ mod tests {
     extern crate rand;
 
     use std::mem;
+    use std::panic;
+    use std::ptr;
     use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT};
     use std::sync::atomic::Ordering::SeqCst;
     use std::sync::Arc;
@@ -467,7 +940,11 @@ mod tests {
 
     use self::rand::{Rng, thread_rng};
 
-    use super::Garbage;
+    use super::{BACKLOG_BAGS, Bag, Garbage, MAX_OBJECTS, ReadyBag, alloc_bag, has_backlog, push,
+                recycle_bag};
+    use epoch::{Owned, Ptr};
+    use epoch::config;
+    use epoch::thread::try_advance;
     use ::epoch;
 
     #[test]
@@ -480,6 +957,145 @@ mod tests {
         });
     }
 
+    #[test]
+    fn defer_null() {
+        let g = Garbage::new();
+        epoch::pin(|scope| unsafe {
+            g.defer_free(ptr::null::<i32>(), 1, scope);
+            g.defer_drop(ptr::null::<i32>(), 1, scope);
+            assert!(g.pending.load(SeqCst, scope).is_null());
+        });
+    }
+
+    #[test]
+    fn panicking_destroy() {
+        static DESTROYS: AtomicUsize = AtomicUsize::new(0);
+
+        unsafe fn destroy(ptr: *mut i32, _: usize) {
+            drop(Box::from_raw(ptr));
+            DESTROYS.fetch_add(1, SeqCst);
+        }
+
+        unsafe fn destroy_and_panic(ptr: *mut i32, _: usize) {
+            drop(Box::from_raw(ptr));
+            panic!("destroy_and_panic");
+        }
+
+        let bag = Bag::new();
+        for i in 0..MAX_OBJECTS {
+            let a = Box::into_raw(Box::new(7i32));
+            if i % 2 == 0 {
+                assert!(bag.try_insert(destroy_and_panic, a, 1));
+            } else {
+                assert!(bag.try_insert(destroy, a, 1));
+            }
+        }
+
+        unsafe { bag.destroy_all_objects(); }
+        assert_eq!(DESTROYS.load(SeqCst), MAX_OBJECTS / 2);
+    }
+
+    #[test]
+    fn count_bags() {
+        let g = Garbage::new();
+
+        epoch::pin(|scope| {
+            for _ in 0..5 {
+                g.push(Box::new(Bag::new()), scope);
+            }
+            assert_eq!(g.bags.load(SeqCst), 5);
+        });
+
+        while g.bags.load(SeqCst) > 0 {
+            epoch::pin(|scope| {
+                try_advance(scope);
+                g.collect_objects(scope);
+            });
+        }
+        epoch::pin(|scope| assert!(g.is_empty(scope)));
+    }
+
+    #[test]
+    fn expiry_wraps_around() {
+        let mut bag = Bag::new();
+        bag.epoch = usize::MAX - 1;
+
+        assert!(!bag.is_expired(usize::MAX - 1));
+        assert!(!bag.is_expired(0));
+        assert!(!bag.is_expired(usize::MAX - 3));
+        assert!(bag.is_expired(2));
+        assert!(bag.is_expired(4));
+        assert!(!bag.is_expired(usize::MAX - 5));
+    }
+
+    #[test]
+    fn collect_across_wraparound() {
+        static DESTROYS: AtomicUsize = AtomicUsize::new(0);
+
+        unsafe fn destroy(ptr: *mut i32, count: usize) {
+            drop(Box::from_raw(ptr));
+            DESTROYS.fetch_add(count, SeqCst);
+        }
+
+        let g = Garbage::new();
+        let start = usize::MAX - 1;
+
+        epoch::pin(|scope| {
+            for i in 0..3 {
+                let bag = Box::new(Bag::new());
+                for _ in 0..MAX_OBJECTS {
+                    assert!(bag.try_insert(destroy, Box::into_raw(Box::new(7i32)), 1));
+                }
+                g.push_at(bag, start.wrapping_add(2 * i), scope);
+            }
+
+            // The first bag was pushed just before the wrap, the others just after it.
+            assert_eq!(g.collect_bags_at(start, 8, scope), (0, 0));
+            assert_eq!(g.collect_bags_at(0, 8, scope), (0, 0));
+            assert_eq!(DESTROYS.load(SeqCst), 0);
+
+            assert_eq!(g.collect_bags_at(2, 8, scope), (1, MAX_OBJECTS));
+            assert_eq!(g.collect_bags_at(4, 8, scope), (1, MAX_OBJECTS));
+            assert_eq!(g.collect_bags_at(6, 8, scope), (1, MAX_OBJECTS));
+        });
+
+        assert_eq!(DESTROYS.load(SeqCst), 3 * MAX_OBJECTS);
+        epoch::pin(|scope| assert!(g.is_empty(scope)));
+    }
+
+    #[test]
+    #[cfg(feature = "debug_backtrace")]
+    fn capture_backtraces() {
+        unsafe fn free(ptr: *mut u64, _: usize) {
+            drop(Box::from_raw(ptr));
+        }
+
+        let bag = Bag::new();
+        assert!(bag.try_insert(free, Box::into_raw(Box::new(7u64)), 1));
+        assert!(bag.backtrace(0).is_some());
+
+        unsafe { bag.destroy_all_objects() };
+        assert!(bag.backtrace(0).is_none());
+    }
+
+    #[test]
+    fn recycle_bags() {
+        thread::spawn(|| {
+            let bag = Box::new(Bag::new());
+            let x = 7;
+            assert!(bag.try_insert(drop_nothing, &x, 1));
+
+            let raw = &*bag as *const Bag;
+            recycle_bag(bag);
+
+            let bag = alloc_bag();
+            assert_eq!(&*bag as *const Bag, raw);
+            assert!(bag.is_empty());
+
+            unsafe fn drop_nothing(_: *mut i32, _: usize) {}
+        }).join().unwrap();
+    }
+
     #[test]
     fn flush_pending() {
         let g = Garbage::new();
@@ -590,6 +1206,95 @@ mod tests {
         assert_eq!(DROPS.load(SeqCst), COUNT);
     }
 
+    #[test]
+    fn reentrant_defer() {
+        const COUNT: usize = 10_000;
+        static PARENTS: AtomicUsize = AtomicUsize::new(0);
+        static CHILDREN: AtomicUsize = AtomicUsize::new(0);
+
+        struct Child(usize);
+
+        impl Drop for Child {
+            fn drop(&mut self) {
+                CHILDREN.fetch_add(self.0, SeqCst);
+            }
+        }
+
+        // Dropping a parent defers destruction of its child while the collector is running.
+        struct Parent(*mut Child);
+
+        unsafe impl Send for Parent {}
+
+        impl Drop for Parent {
+            fn drop(&mut self) {
+                PARENTS.fetch_add(1, SeqCst);
+                epoch::pin(|scope| unsafe {
+                    scope.defer_drop(Ptr::from_raw(self.0));
+                });
+            }
+        }
+
+        epoch::pin(|scope| unsafe {
+            for _ in 0..COUNT {
+                let child = Box::into_raw(Box::new(Child(1)));
+                scope.defer_drop(Owned::new(Parent(child)).into_ptr(scope));
+            }
+        });
+
+        // Other tests might be pinning threads at the same time, so keep trying.
+        while CHILDREN.load(SeqCst) < COUNT {
+            epoch::collect_all();
+            thread::yield_now();
+        }
+        assert_eq!(PARENTS.load(SeqCst), COUNT);
+        assert_eq!(CHILDREN.load(SeqCst), COUNT);
+    }
+
+    #[test]
+    fn collect_hook() {
+        const COUNT: usize = 1000;
+        static HOOKED: AtomicUsize = AtomicUsize::new(0);
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Elem(usize);
+
+        impl Drop for Elem {
+            fn drop(&mut self) {
+                DROPS.fetch_add(self.0, SeqCst);
+            }
+        }
+
+        fn sorted(bag: &ReadyBag) {
+            let mut objects = bag.iter().collect::<Vec<_>>();
+            assert_eq!(objects.len(), bag.len());
+            objects.sort_by_key(|o| o.object() as usize);
+
+            // Other tests defer objects whose destruction panics, so keep going like the default.
+            HOOKED.fetch_add(objects.len(), SeqCst);
+            for o in objects {
+                let _ = panic::catch_unwind(|| unsafe { o.destroy() });
+            }
+        }
+
+        let _lock = config::test_lock();
+        epoch::set_collect_hook(Some(sorted));
+        epoch::pin(|scope| unsafe {
+            for _ in 0..COUNT {
+                scope.defer_drop(Owned::new(Elem(1)).into_ptr(scope));
+            }
+        });
+
+        // Other tests might be pinning threads at the same time, so keep trying.
+        while DROPS.load(SeqCst) < COUNT {
+            epoch::collect_all();
+            thread::yield_now();
+        }
+        epoch::set_collect_hook(None);
+
+        assert_eq!(DROPS.load(SeqCst), COUNT);
+        assert!(HOOKED.load(SeqCst) >= COUNT);
+    }
+
     #[test]
     fn count_destroy() {
         const COUNT: usize = 100_000;
@@ -678,6 +1383,17 @@ mod tests {
         assert_eq!(DESTROYS.load(SeqCst), COUNT);
     }
 
+    #[test]
+    fn backlog() {
+        epoch::pin(|scope| {
+            // The bags can't expire while this thread is pinned.
+            for _ in 0..BACKLOG_BAGS + 1 {
+                push(alloc_bag(), scope);
+            }
+            assert!(has_backlog());
+        });
+    }
+
     #[test]
     fn drop_garbage() {
         const COUNT: usize = 100_000;
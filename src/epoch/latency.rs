@@ -0,0 +1,151 @@
+//! Histogram of the time threads spend pinned.
+//!
+//! With the `latency_stats` feature, every outermost pin records how long the thread stayed
+//! pinned. A thread that stays pinned for a long time holds back the epoch and stalls reclamation
+//! for everyone, so occasional long pins matter more than the average. Percentiles of the
+//! recorded durations can be read with [`pin_latency_percentiles`].
+//!
+//! The durations are counted in a fixed set of buckets, in the spirit of HdrHistogram: values
+//! are grouped by their highest set bit, and every such group is split into a few linear
+//! sub-buckets. Recording is a single relaxed increment of a global counter.
+//!
+//! [`pin_latency_percentiles`]: ../fn.pin_latency_percentiles.html
+
+use std::cmp;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::Duration;
+
+/// Number of bits of precision below the highest set bit.
+const SUB_BITS: u32 = 2;
+
+/// Number of sub-buckets per power of two.
+const SUB_BUCKETS: usize = 1 << SUB_BITS;
+
+/// Total number of buckets, enough to cover every `u64` number of nanoseconds.
+const BUCKETS: usize = (64 - SUB_BITS as usize + 1) * SUB_BUCKETS;
+
+/// Number of pins whose duration falls into each bucket.
+static HISTOGRAM: [AtomicUsize; BUCKETS] = [const { AtomicUsize::new(0) }; BUCKETS];
+
+/// Returns the index of the bucket `nanos` falls into.
+fn bucket(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS as u64 {
+        nanos as usize
+    } else {
+        let exp = 63 - nanos.leading_zeros();
+        let sub = (nanos >> (exp - SUB_BITS)) as usize & (SUB_BUCKETS - 1);
+        (exp - SUB_BITS + 1) as usize * SUB_BUCKETS + sub
+    }
+}
+
+/// Returns the largest number of nanoseconds that falls into bucket `index`.
+fn upper_bound(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        index as u64
+    } else {
+        let group = index / SUB_BUCKETS;
+        let sub = (index % SUB_BUCKETS) as u64;
+        let width = 1u64 << (group - 1);
+        ((SUB_BUCKETS as u64 + sub) << (group - 1)) + (width - 1)
+    }
+}
+
+/// Records that a thread stayed pinned for `elapsed`.
+#[inline]
+pub fn record(elapsed: Duration) {
+    let nanos = elapsed.as_secs()
+        .saturating_mul(1_000_000_000)
+        .saturating_add(u64::from(elapsed.subsec_nanos()));
+    HISTOGRAM[bucket(nanos)].fetch_add(1, Relaxed);
+}
+
+/// Returns the 50th, 99th, and 99.9th percentile of the time threads have spent pinned.
+///
+/// Only outermost pins are measured, i.e. reentrant pinning doesn't count as a separate pin. The
+/// durations are approximated by the upper bound of the histogram bucket they fall into, which is
+/// within 25% of the actual value. If nothing was recorded yet, all three are zero.
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+///
+/// epoch::pin(|_| ());
+///
+/// let (p50, p99, p999) = epoch::pin_latency_percentiles();
+/// assert!(p50 <= p99 && p99 <= p999);
+/// ```
+pub fn pin_latency_percentiles() -> (Duration, Duration, Duration) {
+    let mut counts = [0; BUCKETS];
+    for (c, h) in counts.iter_mut().zip(HISTOGRAM.iter()) {
+        *c = h.load(Relaxed);
+    }
+    (percentile(&counts, 500), percentile(&counts, 990), percentile(&counts, 999))
+}
+
+/// Returns the smallest duration such that at least `permille` of the counted pins took no longer.
+fn percentile(counts: &[usize], permille: usize) -> Duration {
+    let total: usize = counts.iter().sum();
+    if total == 0 {
+        return Duration::from_secs(0);
+    }
+
+    let rank = cmp::max(1, (total * permille).div_ceil(1000));
+    let mut seen = 0;
+    for (i, &c) in counts.iter().enumerate() {
+        seen += c;
+        if seen >= rank {
+            return Duration::from_nanos(upper_bound(i));
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use epoch;
+    use super::{BUCKETS, bucket, percentile, upper_bound};
+
+    #[test]
+    fn buckets() {
+        for shift in 0..64 {
+            for &n in &[1u64 << shift, (1u64 << shift) + 1, (3u64 << shift) >> 1] {
+                let i = bucket(n);
+                assert!(i < BUCKETS);
+                assert!(n <= upper_bound(i));
+                assert!(i == 0 || upper_bound(i - 1) < n);
+            }
+        }
+        assert_eq!(bucket(u64::MAX), BUCKETS - 1);
+        assert_eq!(upper_bound(BUCKETS - 1), u64::MAX);
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut counts = [0; BUCKETS];
+        assert_eq!(percentile(&counts, 500), Duration::from_secs(0));
+
+        // 990 short pins, 9 longer ones, and a single very long one.
+        counts[bucket(100)] = 990;
+        counts[bucket(10_000)] = 9;
+        counts[bucket(1_000_000)] = 1;
+
+        let p50 = percentile(&counts, 500);
+        let p99 = percentile(&counts, 990);
+        let p999 = percentile(&counts, 999);
+        assert!(p50 >= Duration::from_nanos(100) && p50 < Duration::from_nanos(125));
+        assert_eq!(p99, p50);
+        assert!(p999 >= Duration::from_nanos(10_000) && p999 < Duration::from_nanos(12_500));
+        assert!(percentile(&counts, 1000) >= Duration::from_nanos(1_000_000));
+    }
+
+    #[test]
+    fn pin_latency_percentiles() {
+        epoch::pin(|_| ());
+        let (p50, p99, p999) = epoch::pin_latency_percentiles();
+        assert!(p50 <= p99 && p99 <= p999);
+    }
+}
@@ -0,0 +1,175 @@
+//! Export of garbage collector metrics in the Prometheus text format.
+//!
+//! With the `metrics` feature, pinnings and garbage collections are counted in global counters.
+//! Together with the state of the participants and the global queue, they can be rendered by
+//! [`metrics_text`] in the [Prometheus exposition format] and served to a metrics scraper.
+//!
+//! [`metrics_text`]: ../fn.metrics_text.html
+//! [Prometheus exposition format]: https://prometheus.io/docs/instrumenting/exposition_formats/
+
+use std::fmt::Write;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Relaxed, SeqCst};
+
+use epoch::garbage::{self, EPOCH};
+use epoch::thread;
+
+/// Number of times a thread got pinned (not counting reentrant pinning).
+static PINS: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of garbage collections.
+static COLLECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of failed attempts at installing a new participant in the list of participants.
+static REGISTRATION_RETRIES: AtomicUsize = AtomicUsize::new(0);
+
+/// Records that a thread got pinned.
+pub fn record_pin() {
+    PINS.fetch_add(1, Relaxed);
+}
+
+/// Records a garbage collection.
+pub fn record_collection() {
+    COLLECTIONS.fetch_add(1, Relaxed);
+}
+
+/// Records a failed attempt at registering a thread.
+pub fn record_registration_retry() {
+    REGISTRATION_RETRIES.fetch_add(1, Relaxed);
+}
+
+/// Appends a single metric with its `HELP` and `TYPE` lines to `out`.
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: usize) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Returns the garbage collector metrics in the Prometheus text exposition format.
+///
+/// The following metrics are exported:
+///
+/// * `coco_epoch_pins_total`: number of times a thread got pinned.
+/// * `coco_epoch_collections_total`: number of garbage collections.
+/// * `coco_epoch_pending_garbage_objects`: number of objects waiting in the global queue.
+/// * `coco_epoch_pending_garbage_bags`: number of bags waiting in the global queue.
+/// * `coco_epoch_registration_retries_total`: number of failed attempts at registering a thread
+///   because another one registered at the same time.
+/// * `coco_epoch_registered_threads`: number of registered participants.
+/// * `coco_epoch_pinned_threads`: number of currently pinned participants.
+/// * `coco_epoch_current`: the global epoch.
+///
+/// Garbage in thread-local bags is not counted as pending until it gets pushed into the global
+/// queue.
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+///
+/// let text = epoch::metrics_text();
+/// assert!(text.contains("# TYPE coco_epoch_pins_total counter"));
+/// ```
+pub fn metrics_text() -> String {
+    // Taking the snapshot pins the current thread, which shouldn't be counted as pinned.
+    let was_pinned = thread::is_pinned();
+    let snapshot = thread::debug_snapshot();
+    let mut pinned = snapshot.participants.iter().filter(|p| p.is_pinned).count();
+    if !was_pinned {
+        // The snapshot isn't atomic, so don't rely on it having seen this thread pinned.
+        pinned = pinned.saturating_sub(1);
+    }
+
+    let mut out = String::new();
+    metric(
+        &mut out,
+        "coco_epoch_pins_total",
+        "counter",
+        "Number of times a thread got pinned.",
+        PINS.load(Relaxed),
+    );
+    metric(
+        &mut out,
+        "coco_epoch_collections_total",
+        "counter",
+        "Number of garbage collections.",
+        COLLECTIONS.load(Relaxed),
+    );
+    metric(
+        &mut out,
+        "coco_epoch_pending_garbage_objects",
+        "gauge",
+        "Number of objects waiting for destruction in the global queue.",
+        garbage::object_count(),
+    );
+    metric(
+        &mut out,
+        "coco_epoch_pending_garbage_bags",
+        "gauge",
+        "Number of bags waiting for destruction in the global queue.",
+        garbage::bag_count(),
+    );
+    metric(
+        &mut out,
+        "coco_epoch_registration_retries_total",
+        "counter",
+        "Number of failed attempts at registering a thread.",
+        REGISTRATION_RETRIES.load(Relaxed),
+    );
+    metric(
+        &mut out,
+        "coco_epoch_registered_threads",
+        "gauge",
+        "Number of registered participants.",
+        snapshot.participants.len(),
+    );
+    metric(
+        &mut out,
+        "coco_epoch_pinned_threads",
+        "gauge",
+        "Number of currently pinned participants.",
+        pinned,
+    );
+    metric(
+        &mut out,
+        "coco_epoch_current",
+        "gauge",
+        "The global epoch.",
+        EPOCH.load(SeqCst),
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use epoch;
+
+    #[test]
+    fn metrics_text() {
+        epoch::pin(|_| ());
+        epoch::collect_all();
+
+        let text = epoch::metrics_text();
+        for name in &[
+            "coco_epoch_pins_total",
+            "coco_epoch_collections_total",
+            "coco_epoch_pending_garbage_objects",
+            "coco_epoch_pending_garbage_bags",
+            "coco_epoch_registration_retries_total",
+            "coco_epoch_registered_threads",
+            "coco_epoch_pinned_threads",
+            "coco_epoch_current",
+        ] {
+            assert!(text.contains(&format!("# HELP {} ", name)));
+            assert!(text.contains(&format!("# TYPE {} ", name)));
+        }
+
+        let value = |name: &str| -> usize {
+            let line = text.lines().find(|l| l.starts_with(&format!("{} ", name))).unwrap();
+            line.split(' ').nth(1).unwrap().parse().unwrap()
+        };
+        assert!(value("coco_epoch_pins_total") > 0);
+        assert!(value("coco_epoch_collections_total") > 0);
+        assert!(value("coco_epoch_registered_threads") > 0);
+    }
+}
@@ -23,19 +23,72 @@
 //! drop objects or run arbitrary destruction procedures. Removed objects can be stored into it by
 //! calling [`defer_free`] or [`defer_drop`].
 //!
+//! # Linking
+//!
+//! The global epoch, the list of participating threads, and the global garbage are statics, so
+//! there is exactly one collector per copy of this crate in the process. If the crate gets linked
+//! twice, e.g. into both a plugin loaded as a dynamic library and the main binary, each copy has a
+//! collector of its own. Garbage deferred through one copy is then never protected by threads
+//! pinned through the other one, so pointers loaded on one side must never be used on the other.
+//!
+//! Code that passes epoch-protected data across such a boundary should make sure both sides link
+//! the same copy of the crate, e.g. by comparing their [`collector_instance_id`]s.
+//!
 //! [`Atomic`]: struct.Atomic.html
 //! [`Garbage`]: struct.Garbage.html
 //! [`Ptr`]: struct.Ptr.html
+//! [`collector_instance_id`]: fn.collector_instance_id.html
 //! [`defer_free`]: struct.Scope.html#method.defer_free
 //! [`defer_drop`]: struct.Scope.html#method.defer_drop
 
 mod atomic;
+mod atomic_box;
+mod background;
+mod config;
 mod garbage;
+#[cfg(feature = "latency_stats")]
+mod latency;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod option_atomic;
+mod shared;
+mod slot;
+mod stamped;
+#[cfg(feature = "debug_stats")]
+mod stats;
+mod sync;
 mod thread;
+mod weak_atomic;
 
-pub use self::atomic::{Atomic, Owned, Ptr};
-pub use self::garbage::Garbage;
-pub use self::thread::{Scope, is_pinned, pin, unprotected};
+#[cfg(feature = "test_util")]
+pub mod test_util;
+
+pub use self::atomic::{Atomic, CasError, Iter, Owned, Ptr};
+pub use self::atomic_box::AtomicBox;
+pub use self::background::{CollectorThread, spawn_collector};
+pub use self::config::{default_collect_policy, on_bag_full, on_garbage_stall, set_collect_hook,
+                       set_collect_policy, set_max_pending_garbage, set_stall_backoff};
+pub use self::garbage::{DeferredObject, Garbage, ReadyBag};
+pub use self::option_atomic::OptionAtomic;
+pub use self::shared::{Shared, share};
+pub use self::slot::{Slot, SlotAtomic};
+pub use self::stamped::StampedAtomic;
+pub use self::thread::{BorrowedScope, DebugSnapshot, LocalHandle, ParticipantSnapshot, PinToken,
+                       Scope, ScopeGuard, TaskHarness, barrier, collect_all, collector_instance_id,
+                       compact_participants, deadline_overruns, debug_snapshot, is_pinned, pin,
+                       pin_borrowed, pin_flushing, pin_raw, pin_with_deadline, register, try_pin,
+                       try_reclaim_all_if_quiescent, unpin_raw, unprotected, with_current_pin};
+pub use self::weak_atomic::WeakAtomic;
 
 #[cfg(feature = "internals")]
 pub use self::garbage::destroy_global;
+#[cfg(feature = "internals")]
+pub use self::sync::FenceStrategy;
+#[cfg(feature = "debug_stats")]
+pub use self::stats::garbage_by_type;
+#[cfg(feature = "metrics")]
+pub use self::metrics::metrics_text;
+#[cfg(feature = "latency_stats")]
+pub use self::latency::pin_latency_percentiles;
+#[cfg(feature = "leak_check")]
+pub use self::thread::leaked_bags;
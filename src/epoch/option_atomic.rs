@@ -0,0 +1,228 @@
+//! Nullable atomic pointers with an `Option`-based interface.
+//!
+//! An `Atomic<T>` represents the absence of an object as a null pointer, and it's up to the user
+//! to remember checking for it. `OptionAtomic<T>` wraps an `Atomic<T>` and exposes null pointers
+//! as `None`, so that null checks are enforced by the type system. It has the same size and
+//! representation as `Atomic<T>`.
+
+use std::sync::atomic::Ordering;
+
+use epoch::{Atomic, Owned, Ptr, Scope};
+
+/// An atomic pointer that may be empty.
+///
+/// Loading yields an `Option<Ptr<T>>`, where `None` stands for a null pointer. Tags are ignored
+/// when checking for null, i.e. a null pointer with a non-zero tag is still `None`.
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch::{self, OptionAtomic};
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// let a = OptionAtomic::new(Some(1234));
+///
+/// epoch::pin(|scope| {
+///     if let Some(p) = a.take(SeqCst, scope) {
+///         assert_eq!(unsafe { *p.deref() }, 1234);
+///         unsafe { scope.defer_free(p) }
+///     }
+///     assert!(a.load(SeqCst, scope).is_none());
+/// });
+/// ```
+#[derive(Debug)]
+pub struct OptionAtomic<T> {
+    inner: Atomic<T>,
+}
+
+/// Converts a `Ptr` into an `Option<Ptr>`.
+fn to_option<T>(ptr: Ptr<T>) -> Option<Ptr<T>> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(ptr)
+    }
+}
+
+impl<T> OptionAtomic<T> {
+    /// Returns a new empty atomic pointer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::OptionAtomic;
+    ///
+    /// let a = OptionAtomic::<i32>::none();
+    /// ```
+    pub fn none() -> Self {
+        OptionAtomic { inner: Atomic::null() }
+    }
+
+    /// Returns a new atomic pointer, allocating `value` on the heap if it is `Some`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::OptionAtomic;
+    ///
+    /// let a = OptionAtomic::new(Some(1234));
+    /// let b = OptionAtomic::<i32>::new(None);
+    /// ```
+    pub fn new(value: Option<T>) -> Self {
+        match value {
+            None => Self::none(),
+            Some(v) => OptionAtomic { inner: Atomic::new(v) },
+        }
+    }
+
+    /// Loads the pointer, returning `None` if it is null.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, OptionAtomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = OptionAtomic::new(Some(1234));
+    /// epoch::pin(|scope| {
+    ///     assert!(a.load(SeqCst, scope).is_some());
+    /// });
+    /// ```
+    pub fn load<'scope>(&self, ord: Ordering, scope: &'scope Scope) -> Option<Ptr<'scope, T>> {
+        to_option(self.inner.load(ord, scope))
+    }
+
+    /// Stores a box into the atomic pointer, or makes it empty if `new` is `None`.
+    ///
+    /// The previous object is not reclaimed. If there may be one, use [`swap`] instead.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`swap`]: struct.OptionAtomic.html#method.swap
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::OptionAtomic;
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = OptionAtomic::none();
+    /// a.store(Some(Box::new(1234)), SeqCst);
+    /// ```
+    pub fn store(&self, new: Option<Box<T>>, ord: Ordering) {
+        match new {
+            None => self.inner.store(Ptr::null(), ord),
+            Some(b) => self.inner.store_owned(Owned::from_box(b), ord),
+        }
+    }
+
+    /// Stores a box into the atomic pointer, or makes it empty if `new` is `None`, returning the
+    /// previous pointer.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, OptionAtomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = OptionAtomic::new(Some(1));
+    /// epoch::pin(|scope| {
+    ///     let old = a.swap(Some(Box::new(2)), SeqCst, scope);
+    ///     assert_eq!(old.map(|p| unsafe { *p.deref() }), Some(1));
+    ///     # unsafe { scope.defer_free(old.unwrap()) }
+    ///     # unsafe { scope.defer_free(a.take(SeqCst, scope).unwrap()) }
+    /// });
+    /// ```
+    #[must_use = "the returned pointer may need to be unlinked and reclaimed"]
+    pub fn swap<'scope>(
+        &self,
+        new: Option<Box<T>>,
+        ord: Ordering,
+        scope: &'scope Scope,
+    ) -> Option<Ptr<'scope, T>> {
+        let new = match new {
+            None => Ptr::null(),
+            Some(b) => Owned::from_box(b).into_ptr(scope),
+        };
+        to_option(self.inner.swap(new, ord, scope))
+    }
+
+    /// Makes the atomic pointer empty, returning the previous pointer.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, OptionAtomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = OptionAtomic::new(Some(1234));
+    /// epoch::pin(|scope| {
+    ///     assert!(a.take(SeqCst, scope).is_some());
+    ///     assert!(a.take(SeqCst, scope).is_none());
+    /// });
+    /// ```
+    #[must_use = "the returned pointer may need to be unlinked and reclaimed"]
+    pub fn take<'scope>(&self, ord: Ordering, scope: &'scope Scope) -> Option<Ptr<'scope, T>> {
+        to_option(self.inner.swap_null(ord, scope))
+    }
+
+    /// Returns a reference to the underlying `Atomic<T>`.
+    ///
+    /// This gives access to the full set of operations, e.g. compare-and-swap and tags.
+    pub fn as_atomic(&self) -> &Atomic<T> {
+        &self.inner
+    }
+}
+
+impl<T> Default for OptionAtomic<T> {
+    fn default() -> Self {
+        OptionAtomic::none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use epoch::{self, Atomic, OptionAtomic, Ptr};
+
+    #[test]
+    fn same_size() {
+        assert_eq!(mem::size_of::<OptionAtomic<u64>>(), mem::size_of::<Atomic<u64>>());
+    }
+
+    #[test]
+    fn tagged_null_is_none() {
+        let a = OptionAtomic::<u64>::none();
+
+        epoch::pin(|scope| unsafe {
+            a.as_atomic().store(Ptr::null().with_tag(1), SeqCst);
+            assert!(a.load(SeqCst, scope).is_none());
+
+            a.store(Some(Box::new(7)), SeqCst);
+            let p = a.swap(None, SeqCst, scope).unwrap();
+            assert_eq!(*p.deref(), 7);
+            assert!(a.take(SeqCst, scope).is_none());
+
+            scope.defer_free(p);
+        });
+    }
+}
@@ -0,0 +1,151 @@
+//! A shared cell holding a single value that can be replaced concurrently.
+//!
+//! Working with `Atomic<T>` directly requires care: pointers may be null, and replaced objects
+//! must be manually deferred for destruction at the right moment. `Shared<T>` is a simple cell
+//! that always holds a value and takes care of reclamation internally, so its interface is
+//! entirely safe.
+
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed};
+
+use epoch::{self, Atomic, Owned, Scope};
+
+/// A heap-allocated value that can be read and replaced by many threads at the same time.
+///
+/// Replacing the value defers destruction of the previous one until no thread can be reading it
+/// anymore.
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch::{self, share};
+///
+/// let s = share(String::from("hello"));
+///
+/// epoch::pin(|scope| {
+///     assert_eq!(s.load(scope), "hello");
+///     s.update(String::from("world"), scope);
+///     assert_eq!(s.load(scope), "world");
+/// });
+/// ```
+#[derive(Debug)]
+pub struct Shared<T> {
+    inner: Atomic<T>,
+}
+
+unsafe impl<T: Send + Sync> Send for Shared<T> {}
+unsafe impl<T: Send + Sync> Sync for Shared<T> {}
+
+impl<T: Send + 'static> Shared<T> {
+    /// Returns a new cell holding `value`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::Shared;
+    ///
+    /// let s = Shared::new(1234);
+    /// ```
+    pub fn new(value: T) -> Self {
+        Shared { inner: Atomic::new(value) }
+    }
+
+    /// Returns a reference to the current value.
+    ///
+    /// The reference stays valid even if the value gets replaced in the meantime, but it can't
+    /// outlive the pinned scope nor the cell itself, which drops the current value immediately.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Shared};
+    ///
+    /// let s = Shared::new(1234);
+    /// epoch::pin(|scope| assert_eq!(*s.load(scope), 1234));
+    /// ```
+    ///
+    /// The reference can't be used after the cell is dropped:
+    ///
+    /// ```compile_fail
+    /// use coco::epoch::{self, Shared};
+    ///
+    /// epoch::pin(|scope| {
+    ///     let s = Shared::new(vec![1]);
+    ///     let r = s.load(scope);
+    ///     drop(s);
+    ///     r.len()
+    /// });
+    /// ```
+    pub fn load<'a>(&'a self, scope: &'a Scope) -> &'a T {
+        unsafe { self.inner.load(Acquire, scope).deref() }
+    }
+
+    /// Replaces the current value with `new`.
+    ///
+    /// The previous value is dropped once no thread can be reading it anymore.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Shared};
+    ///
+    /// let s = Shared::new(1);
+    /// epoch::pin(|scope| s.update(2, scope));
+    /// ```
+    pub fn update(&self, new: T, scope: &Scope) {
+        let old = self.inner.swap(Owned::new(new).into_ptr(scope), AcqRel, scope);
+        unsafe { scope.defer_drop(old) }
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        // Nobody else can be accessing the cell, so the current value can be dropped immediately.
+        unsafe { epoch::unprotected(|scope| drop(self.inner.load(Relaxed, scope).into_box())) }
+    }
+}
+
+/// Moves `value` into a new [`Shared`] cell.
+///
+/// [`Shared`]: struct.Shared.html
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch::{self, share};
+///
+/// let s = share(vec![1, 2, 3]);
+/// epoch::pin(|scope| assert_eq!(s.load(scope).len(), 3));
+/// ```
+pub fn share<T: Send + 'static>(value: T) -> Shared<T> {
+    Shared::new(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use epoch::{self, Shared};
+
+    #[test]
+    fn concurrent_updates() {
+        let s = Arc::new(Shared::new(vec![0usize; 16]));
+
+        let threads = (0..4).map(|t| {
+            let s = s.clone();
+            thread::spawn(move || {
+                for i in 0..1000 {
+                    epoch::pin(|scope| {
+                        let v = s.load(scope);
+                        assert!(v.iter().all(|&x| x == v[0]));
+                        s.update(vec![t * 1000 + i; 16], scope);
+                    });
+                }
+            })
+        }).collect::<Vec<_>>();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+    }
+}
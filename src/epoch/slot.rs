@@ -0,0 +1,202 @@
+//! Classification of loaded pointers in slots with a sentinel.
+//!
+//! Lock-free hash tables and similar structures often have slots in one of three states: empty,
+//! deleted, or holding a live object. Deleted slots are marked by pointing to a distinguished
+//! sentinel object (a tombstone), which must never be mistaken for a live one. A [`SlotAtomic`]
+//! remembers the sentinel it was created with by `Atomic::with_sentinel`, and its `load_slot`
+//! classifies loaded pointers as a [`Slot`], so that every access has to handle all three states
+//! explicitly.
+//!
+//! [`SlotAtomic`]: struct.SlotAtomic.html
+//! [`Slot`]: enum.Slot.html
+
+use std::ops::Deref;
+use std::sync::atomic::Ordering;
+
+use epoch::{Atomic, Ptr, Scope};
+
+/// The state of a slot, as returned by [`SlotAtomic::load_slot`].
+///
+/// [`SlotAtomic::load_slot`]: struct.SlotAtomic.html#method.load_slot
+#[derive(Debug)]
+pub enum Slot<'scope, T: 'scope> {
+    /// The slot holds a null pointer.
+    Empty,
+    /// The slot points to the sentinel object.
+    Sentinel,
+    /// The slot points to a live object.
+    Value(Ptr<'scope, T>),
+}
+
+impl<'scope, T> Clone for Slot<'scope, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'scope, T> Copy for Slot<'scope, T> {}
+
+impl<'scope, T> PartialEq for Slot<'scope, T> {
+    fn eq(&self, other: &Self) -> bool {
+        match (*self, *other) {
+            (Slot::Empty, Slot::Empty) | (Slot::Sentinel, Slot::Sentinel) => true,
+            (Slot::Value(a), Slot::Value(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl<'scope, T> Eq for Slot<'scope, T> {}
+
+impl<'scope, T> Slot<'scope, T> {
+    /// Classifies `ptr`, treating pointers to `sentinel` as [`Sentinel`].
+    ///
+    /// Tags are ignored, i.e. a null pointer with a non-zero tag is still [`Empty`], and a tagged
+    /// pointer to the sentinel is still [`Sentinel`]. Pointers to live objects keep their tags.
+    ///
+    /// [`Empty`]: enum.Slot.html#variant.Empty
+    /// [`Sentinel`]: enum.Slot.html#variant.Sentinel
+    pub fn classify(ptr: Ptr<'scope, T>, sentinel: *const T) -> Self {
+        if ptr.is_null() {
+            Slot::Empty
+        } else if ptr.as_raw() == sentinel {
+            Slot::Sentinel
+        } else {
+            Slot::Value(ptr)
+        }
+    }
+
+    /// Returns `true` if the slot is empty.
+    pub fn is_empty(&self) -> bool {
+        matches!(*self, Slot::Empty)
+    }
+
+    /// Returns `true` if the slot points to the sentinel.
+    pub fn is_sentinel(&self) -> bool {
+        matches!(*self, Slot::Sentinel)
+    }
+
+    /// Returns the pointer to the live object, if there is one.
+    pub fn value(&self) -> Option<Ptr<'scope, T>> {
+        match *self {
+            Slot::Value(p) => Some(p),
+            _ => None,
+        }
+    }
+}
+
+/// An atomic pointer with a registered sentinel.
+///
+/// Created by [`Atomic::with_sentinel`]. It dereferences to the underlying [`Atomic`], so it can
+/// be stored into and compared-and-swapped like any other atomic pointer.
+///
+/// [`Atomic::with_sentinel`]: struct.Atomic.html#method.with_sentinel
+/// [`Atomic`]: struct.Atomic.html
+#[derive(Debug)]
+pub struct SlotAtomic<T> {
+    inner: Atomic<T>,
+    sentinel: *const T,
+}
+
+// The sentinel is only compared against, never dereferenced.
+unsafe impl<T: Send + Sync> Send for SlotAtomic<T> {}
+unsafe impl<T: Send + Sync> Sync for SlotAtomic<T> {}
+
+impl<T> Atomic<T> {
+    /// Returns a new null atomic pointer that treats pointers to `sentinel` as deleted slots.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic, Owned, Ptr, Slot};
+    /// use std::sync::atomic::Ordering::{Acquire, Release};
+    ///
+    /// // Deleted slots point to the tombstone.
+    /// let tombstone = Box::new(0);
+    /// let a = Atomic::with_sentinel(&*tombstone);
+    ///
+    /// epoch::pin(|scope| {
+    ///     a.store_owned(Owned::new(1234), Release);
+    ///     let old = a.swap(unsafe { Ptr::from_raw(&*tombstone) }, Release, scope);
+    ///     unsafe { scope.defer_drop(old) }
+    ///
+    ///     match a.load_slot(Acquire, scope) {
+    ///         Slot::Empty | Slot::Sentinel => {}
+    ///         Slot::Value(p) => println!("{}", unsafe { p.deref() }),
+    ///     }
+    /// });
+    /// ```
+    pub fn with_sentinel(sentinel: *const T) -> SlotAtomic<T> {
+        SlotAtomic {
+            inner: Atomic::null(),
+            sentinel,
+        }
+    }
+}
+
+impl<T> SlotAtomic<T> {
+    /// Returns the registered sentinel.
+    pub fn sentinel(&self) -> *const T {
+        self.sentinel
+    }
+
+    /// Loads a `Ptr` from the atomic pointer and classifies it as a [`Slot`].
+    ///
+    /// A null pointer yields [`Slot::Empty`], a pointer to the sentinel yields
+    /// [`Slot::Sentinel`], and any other pointer yields [`Slot::Value`]. Tags are ignored for the
+    /// classification.
+    ///
+    /// This method takes an [`Ordering`] argument which describes the memory ordering of this
+    /// operation.
+    ///
+    /// [`Slot`]: enum.Slot.html
+    /// [`Slot::Empty`]: enum.Slot.html#variant.Empty
+    /// [`Slot::Sentinel`]: enum.Slot.html#variant.Sentinel
+    /// [`Slot::Value`]: enum.Slot.html#variant.Value
+    /// [`Ordering`]: https://doc.rust-lang.org/std/sync/atomic/enum.Ordering.html
+    pub fn load_slot<'scope>(&self, ord: Ordering, scope: &'scope Scope) -> Slot<'scope, T> {
+        Slot::classify(self.inner.load(ord, scope), self.sentinel)
+    }
+}
+
+impl<T> Deref for SlotAtomic<T> {
+    type Target = Atomic<T>;
+
+    fn deref(&self) -> &Atomic<T> {
+        &self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use epoch::{self, Atomic, Owned, Ptr};
+    use super::Slot;
+
+    #[test]
+    fn classify() {
+        let tombstone = Box::new(0u64);
+        let sentinel = &*tombstone as *const u64;
+        let a = Atomic::with_sentinel(sentinel);
+        assert_eq!(a.sentinel(), sentinel);
+
+        epoch::pin(|scope| unsafe {
+            assert_eq!(a.load_slot(SeqCst, scope), Slot::Empty);
+
+            a.store(Ptr::null().with_tag(1), SeqCst);
+            assert!(a.load_slot(SeqCst, scope).is_empty());
+
+            a.store(Ptr::from_raw(sentinel).with_tag(1), SeqCst);
+            assert!(a.load_slot(SeqCst, scope).is_sentinel());
+
+            let p = Owned::new(7u64).into_ptr(scope).with_tag(2);
+            a.store(p, SeqCst);
+            let slot = a.load_slot(SeqCst, scope);
+            assert_eq!(slot.value(), Some(p));
+            assert_eq!(*slot.value().unwrap().deref(), 7);
+
+            drop(p.into_box());
+        });
+    }
+}
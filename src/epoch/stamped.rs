@@ -0,0 +1,332 @@
+//! Atomic pointers with version stamps.
+//!
+//! A tag stored in the unused bits of an aligned pointer can distinguish only a few states, which
+//! is not always enough to prevent the ABA problem. The classic solution is to pair the pointer
+//! with a counter that gets incremented on every update.
+//!
+//! A pointer and a full-width counter don't fit into a single word, and two words cannot be
+//! atomically compared-and-swapped on most platforms. Instead, `StampedAtomic<T>` points to an
+//! immutable heap-allocated pair of the pointer and the stamp. Every update allocates a new pair
+//! and installs it with a single compare-and-swap, while the old pair is reclaimed through the
+//! epoch GC. Since a pair cannot be reclaimed while a thread that loaded it is still pinned, its
+//! address cannot be reused during a compare-and-swap either.
+
+use std::marker::PhantomData;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed};
+
+use epoch::{self, Atomic, Owned, Ptr, Scope};
+
+/// An immutable pair of a tagged pointer and a stamp.
+struct Stamp {
+    /// The untagged address.
+    raw: usize,
+    /// The tag.
+    tag: usize,
+    /// The number of updates performed so far.
+    stamp: usize,
+}
+
+/// An atomic pointer paired with a version stamp.
+///
+/// The stamp starts at zero and is incremented on every update, so two loads that return the same
+/// stamp are guaranteed to have observed the same pointer, even if the pointer has in the
+/// meantime been changed to another value and back.
+///
+/// Updates allocate memory, so this type is slower than [`Atomic`]. It should be used only when
+/// tags are not enough to prevent the ABA problem, e.g. when objects are recycled instead of
+/// being reclaimed by the epoch GC.
+///
+/// [`Atomic`]: struct.Atomic.html
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch::{self, Owned, Ptr, StampedAtomic};
+///
+/// let a = StampedAtomic::<i32>::null();
+///
+/// epoch::pin(|scope| {
+///     let (p, stamp) = a.load(scope);
+///     assert!(p.is_null());
+///     assert_eq!(stamp, 0);
+///
+///     let new = Owned::new(1234).into_ptr(scope);
+///     assert_eq!(a.compare_and_swap(p, stamp, new, scope).ok(), Some(1));
+///
+///     // The pointer is back to null, but the stamp has changed.
+///     a.store(Ptr::null(), scope);
+///     assert!(a.compare_and_swap(p, stamp, new, scope).is_err());
+///
+///     unsafe { scope.defer_free(new) }
+/// });
+/// ```
+pub struct StampedAtomic<T> {
+    /// The current pair. Null stands for a null pointer with stamp zero.
+    inner: Atomic<Stamp>,
+    _marker: PhantomData<*mut T>,
+}
+
+unsafe impl<T: Send + Sync> Send for StampedAtomic<T> {}
+unsafe impl<T: Send + Sync> Sync for StampedAtomic<T> {}
+
+impl<T> StampedAtomic<T> {
+    /// Returns a new null atomic pointer with stamp zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::StampedAtomic;
+    ///
+    /// let a = StampedAtomic::<i32>::null();
+    /// ```
+    pub fn null() -> Self {
+        StampedAtomic {
+            inner: Atomic::null(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Allocates `value` on the heap and returns a new atomic pointer with stamp zero pointing to
+    /// it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::StampedAtomic;
+    ///
+    /// let a = StampedAtomic::new(1234);
+    /// ```
+    pub fn new(value: T) -> Self {
+        let raw = Box::into_raw(Box::new(value));
+        StampedAtomic {
+            inner: Atomic::new(Stamp {
+                raw: raw as usize,
+                tag: 0,
+                stamp: 0,
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Reads the pointer and the stamp from a pair.
+    fn read<'scope>(pair: Ptr<'scope, Stamp>) -> (Ptr<'scope, T>, usize) {
+        match unsafe { pair.as_ref() } {
+            None => (Ptr::null(), 0),
+            Some(s) => {
+                let p = unsafe { Ptr::from_raw(s.raw as *const T).with_tag(s.tag) };
+                (p, s.stamp)
+            }
+        }
+    }
+
+    /// Loads the pointer and the stamp.
+    ///
+    /// The load uses `Acquire` ordering.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, StampedAtomic};
+    ///
+    /// let a = StampedAtomic::new(1234);
+    /// epoch::pin(|scope| {
+    ///     let (p, stamp) = a.load(scope);
+    ///     assert_eq!(unsafe { p.as_ref() }, Some(&1234));
+    ///     assert_eq!(stamp, 0);
+    /// });
+    /// ```
+    pub fn load<'scope>(&self, scope: &'scope Scope) -> (Ptr<'scope, T>, usize) {
+        Self::read(self.inner.load(Acquire, scope))
+    }
+
+    /// Stores a `Ptr` into the atomic pointer and increments the stamp.
+    ///
+    /// Returns the new stamp. The store uses `AcqRel` ordering.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Ptr, StampedAtomic};
+    ///
+    /// let a = StampedAtomic::<i32>::null();
+    /// epoch::pin(|scope| {
+    ///     assert_eq!(a.store(Ptr::null(), scope), 1);
+    ///     assert_eq!(a.store(Ptr::null(), scope), 2);
+    /// });
+    /// ```
+    pub fn store(&self, new: Ptr<T>, scope: &Scope) -> usize {
+        let mut pair = self.inner.load(Acquire, scope);
+        loop {
+            let (_, stamp) = Self::read(pair);
+            match self.compare_and_swap_pair(pair, stamp, new, scope) {
+                Ok(stamp) => return stamp,
+                Err(p) => pair = p,
+            }
+        }
+    }
+
+    /// Stores `new` into the atomic pointer if the current pointer and stamp are the same as
+    /// `current` and `stamp`.
+    ///
+    /// On success the stamp is incremented and the new stamp is returned. On failure the actual
+    /// current pointer and stamp are returned. The compare-and-swap uses `AcqRel` ordering.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Owned, StampedAtomic};
+    ///
+    /// let a = StampedAtomic::new(1);
+    /// epoch::pin(|scope| {
+    ///     let (p, stamp) = a.load(scope);
+    ///     let new = Owned::new(2).into_ptr(scope);
+    ///
+    ///     assert_eq!(a.compare_and_swap(p, stamp, new, scope).ok(), Some(stamp + 1));
+    ///     assert!(a.compare_and_swap(p, stamp, new, scope).is_err());
+    ///
+    ///     unsafe {
+    ///         scope.defer_free(p);
+    ///         scope.defer_free(new);
+    ///     }
+    /// });
+    /// ```
+    pub fn compare_and_swap<'scope>(
+        &self,
+        current: Ptr<T>,
+        stamp: usize,
+        new: Ptr<T>,
+        scope: &'scope Scope,
+    ) -> Result<usize, (Ptr<'scope, T>, usize)> {
+        let mut pair = self.inner.load(Acquire, scope);
+        loop {
+            let (p, s) = Self::read(pair);
+            if p != current || s != stamp {
+                return Err((p, s));
+            }
+
+            match self.compare_and_swap_pair(pair, stamp, new, scope) {
+                Ok(stamp) => return Ok(stamp),
+                Err(p) => pair = p,
+            }
+        }
+    }
+
+    /// Replaces `pair` (which holds `stamp`) with a new pair holding `new` and the next stamp.
+    ///
+    /// On success the new stamp is returned, and the old pair is deferred for reclamation. On
+    /// failure the actual current pair is returned.
+    fn compare_and_swap_pair<'scope>(
+        &self,
+        pair: Ptr<'scope, Stamp>,
+        stamp: usize,
+        new: Ptr<T>,
+        scope: &'scope Scope,
+    ) -> Result<usize, Ptr<'scope, Stamp>> {
+        let stamp = stamp.wrapping_add(1);
+        let new = Owned::new(Stamp {
+            raw: new.as_raw() as usize,
+            tag: new.tag(),
+            stamp,
+        });
+
+        match self.inner.compare_and_swap_owned(pair, new, AcqRel, scope) {
+            Ok(_) => {
+                unsafe { scope.defer_free(pair) }
+                Ok(stamp)
+            }
+            Err((pair, new)) => {
+                drop(new.into_box());
+                Err(pair)
+            }
+        }
+    }
+}
+
+impl<T> Default for StampedAtomic<T> {
+    fn default() -> Self {
+        StampedAtomic::null()
+    }
+}
+
+impl<T> Drop for StampedAtomic<T> {
+    fn drop(&mut self) {
+        // Destroy the current pair. The object it points to is not owned by us.
+        unsafe {
+            epoch::unprotected(|scope| {
+                let pair = self.inner.load(Relaxed, scope);
+                if !pair.is_null() {
+                    drop(pair.into_box());
+                }
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use epoch::{self, Owned, Ptr, StampedAtomic};
+
+    #[test]
+    fn aba() {
+        let a = StampedAtomic::new(1);
+
+        epoch::pin(|scope| unsafe {
+            let (p, stamp) = a.load(scope);
+            let q = Owned::new(2).into_ptr(scope);
+
+            // Change the pointer from `p` to `q` and back.
+            assert_eq!(a.store(q, scope), stamp + 1);
+            assert_eq!(a.store(p, scope), stamp + 2);
+
+            match a.compare_and_swap(p, stamp, Ptr::null(), scope) {
+                Ok(_) => panic!(),
+                Err((c, s)) => {
+                    assert_eq!(c.as_raw(), p.as_raw());
+                    assert_eq!(s, stamp + 2);
+                }
+            }
+            let res = a.compare_and_swap(p, stamp + 2, Ptr::null(), scope);
+            assert_eq!(res.ok(), Some(stamp + 3));
+
+            scope.defer_free(p);
+            scope.defer_free(q);
+        });
+    }
+
+    #[test]
+    fn stress() {
+        const THREADS: usize = 8;
+        const COUNT: usize = 10_000;
+
+        let a = Arc::new(StampedAtomic::<i32>::null());
+
+        let threads = (0..THREADS).map(|_| {
+            let a = a.clone();
+            thread::spawn(move || {
+                for _ in 0..COUNT {
+                    epoch::pin(|scope| {
+                        let (mut p, mut stamp) = a.load(scope);
+                        loop {
+                            match a.compare_and_swap(p, stamp, p, scope) {
+                                Ok(_) => break,
+                                Err((q, s)) => {
+                                    p = q;
+                                    stamp = s;
+                                }
+                            }
+                        }
+                    })
+                }
+            })
+        }).collect::<Vec<_>>();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        epoch::pin(|scope| assert_eq!(a.load(scope).1, THREADS * COUNT));
+    }
+}
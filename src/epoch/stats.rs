@@ -0,0 +1,110 @@
+//! Per-type garbage statistics for leak diagnosis.
+//!
+//! When memory keeps growing, it helps to know which types of objects are piling up in garbage.
+//! With the `debug_stats` feature, every object deferred through a [`Scope`] is counted by the name
+//! of its type, and counted again when it gets reclaimed.
+//!
+//! The counters are protected by a global lock, so they slow down deferring and reclaiming
+//! considerably. This feature is meant for debugging only.
+//!
+//! [`Scope`]: ../struct.Scope.html
+
+use std::any;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+
+/// Counters for a single type.
+struct Counts {
+    /// Number of deferred objects.
+    deferred: usize,
+    /// Number of reclaimed objects.
+    reclaimed: usize,
+}
+
+/// Counters for all types, keyed by type name.
+static COUNTS: Mutex<BTreeMap<&'static str, Counts>> = Mutex::new(BTreeMap::new());
+
+/// Locks the counters.
+///
+/// A panic while holding the lock can't leave the counters in an inconsistent state, so poisoning
+/// is ignored.
+fn lock() -> MutexGuard<'static, BTreeMap<&'static str, Counts>> {
+    COUNTS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Returns the counters for type `T`.
+fn counts<'a, T>(map: &'a mut BTreeMap<&'static str, Counts>) -> &'a mut Counts {
+    map.entry(any::type_name::<T>()).or_insert(Counts {
+        deferred: 0,
+        reclaimed: 0,
+    })
+}
+
+/// Records that `count` objects of type `T` have been deferred.
+pub fn record_deferred<T>(count: usize) {
+    counts::<T>(&mut lock()).deferred += count;
+}
+
+/// Records that `count` objects of type `T` have been reclaimed.
+pub fn record_reclaimed<T>(count: usize) {
+    counts::<T>(&mut lock()).reclaimed += count;
+}
+
+/// Returns the number of deferred but not yet reclaimed objects for each type.
+///
+/// Types without any such objects are omitted. The list is sorted by the number of objects, in
+/// descending order.
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch::{self, Owned};
+///
+/// epoch::pin(|scope| unsafe {
+///     scope.defer_drop(Owned::new(String::from("hello")).into_ptr(scope));
+/// });
+///
+/// for (name, count) in epoch::garbage_by_type() {
+///     println!("{}: {}", name, count);
+/// }
+/// ```
+pub fn garbage_by_type() -> Vec<(&'static str, usize)> {
+    let mut v = lock()
+        .iter()
+        .map(|(&name, c)| (name, c.deferred.wrapping_sub(c.reclaimed)))
+        .filter(|&(_, pending)| pending > 0)
+        .collect::<Vec<_>>();
+    v.sort_by_key(|&(_, pending)| cmp::Reverse(pending));
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use epoch::{self, Owned};
+
+    struct Leaky {
+        _data: u64,
+    }
+
+    fn pending() -> usize {
+        epoch::garbage_by_type()
+            .into_iter()
+            .find(|&(name, _)| name.ends_with("Leaky"))
+            .map_or(0, |(_, count)| count)
+    }
+
+    #[test]
+    fn count_by_type() {
+        epoch::pin(|scope| unsafe {
+            for i in 0..10 {
+                scope.defer_drop(Owned::new(Leaky { _data: i }).into_ptr(scope));
+            }
+        });
+        assert!(pending() > 0);
+
+        while pending() > 0 {
+            epoch::collect_all();
+        }
+    }
+}
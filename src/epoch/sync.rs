@@ -0,0 +1,256 @@
+//! Memory barriers for announcing pinned threads.
+//!
+//! When a thread gets pinned, it stores its new state and must then make sure that succeeding
+//! loads from shared memory don't get reordered with that store. This requires a full memory
+//! barrier, and there are several ways of issuing one. Which is the fastest depends on the target
+//! architecture:
+//!
+//! * On x86 and x86-64, `atomic::fence(SeqCst)` compiles to a `mfence` instruction, but a
+//!   `SeqCst` read-modify-write operation (`lock cmpxchg` or `xchg`) has the same effect and is
+//!   usually faster.
+//! * On AArch64, `atomic::fence(SeqCst)` compiles to a `dmb ish` instruction. Read-modify-write
+//!   operations are not full barriers there, so they would have to be followed by the same `dmb
+//!   ish` anyway. The fence is therefore the best choice.
+//! * On other architectures, we conservatively use the fence as well.
+//!
+//! The default strategy can be overridden at compile time for benchmarking by enabling one of the
+//! features `fence_seqcst`, `fence_swap`, or `fence_cmpxchg`. If several are enabled, the first one
+//! in this list wins. On architectures other than x86, read-modify-write strategies are followed
+//! by a fence, so every strategy is correct on every architecture.
+//!
+//! Unpinning needs no barrier at all, just a `Release` store (see [`unpin_ordering`]). The
+//! feature `unpin_seqcst` strengthens it to `SeqCst`, again only for benchmarking.
+//!
+//! [`unpin_ordering`]: fn.unpin_ordering.html
+
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+use std::sync::atomic::Ordering::{Relaxed, Release, SeqCst};
+
+/// A way of storing a value and issuing a full memory barrier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FenceStrategy {
+    /// A relaxed store followed by `atomic::fence(SeqCst)`.
+    Fence,
+    /// A `swap` with `SeqCst` ordering.
+    Swap,
+    /// A `compare_exchange` with `SeqCst` ordering.
+    CompareAndSwap,
+}
+
+/// Returns `true` if `SeqCst` read-modify-write operations are full barriers on this architecture.
+#[inline]
+fn rmw_is_full_barrier() -> bool {
+    cfg!(any(target_arch = "x86", target_arch = "x86_64"))
+}
+
+impl FenceStrategy {
+    /// Returns the strategy selected at compile time.
+    #[inline]
+    pub fn current() -> FenceStrategy {
+        if cfg!(feature = "fence_seqcst") {
+            FenceStrategy::Fence
+        } else if cfg!(feature = "fence_swap") {
+            FenceStrategy::Swap
+        } else if cfg!(feature = "fence_cmpxchg") || rmw_is_full_barrier() {
+            FenceStrategy::CompareAndSwap
+        } else {
+            FenceStrategy::Fence
+        }
+    }
+
+    /// Stores `val` into `dest` and issues a full memory barrier.
+    ///
+    /// No load after this call can be reordered before the store.
+    #[inline]
+    pub fn store(self, dest: &AtomicUsize, val: usize) {
+        match self {
+            FenceStrategy::Fence => {
+                dest.store(val, Relaxed);
+                atomic::fence(SeqCst);
+            }
+            FenceStrategy::Swap => {
+                dest.swap(val, SeqCst);
+            }
+            FenceStrategy::CompareAndSwap => {
+                let previous = dest.load(Relaxed);
+                let _ = dest.compare_exchange(previous, val, SeqCst, Relaxed);
+            }
+        }
+
+        if self != FenceStrategy::Fence && !rmw_is_full_barrier() {
+            atomic::fence(SeqCst);
+        }
+    }
+}
+
+/// Returns the ordering of the store that marks a thread as unpinned.
+///
+/// `Release` is sufficient, and here is why. Every load of shared memory performed while pinned
+/// is sequenced before the unpinning store, and `Release` keeps it there. A thread trying to
+/// advance the epoch reads the states of all participants with a `SeqCst` load. If it observes
+/// the unpinned state, that load synchronizes with the store, so all loads from the pinned region
+/// happen before the epoch advancement, and thus before any garbage from that region gets
+/// destroyed two advancements later.
+///
+/// A `SeqCst` fence on unpin isn't needed either. The next pin of the same thread announces itself
+/// with a full barrier (see [`FenceStrategy`]), so loads in the next pinned region cannot be
+/// reordered before that announcement, let alone before the loads of the previous region. A
+/// `Relaxed` store, on the other hand, would allow loads from the pinned region to be performed
+/// after other threads already consider the thread unpinned.
+///
+/// A model of this protocol is checked with loom by the tests in this module, both with this
+/// ordering and with `Relaxed`, which must fail.
+///
+/// [`FenceStrategy`]: enum.FenceStrategy.html
+#[inline]
+pub fn unpin_ordering() -> Ordering {
+    if cfg!(feature = "unpin_seqcst") {
+        SeqCst
+    } else {
+        Release
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::{Release, SeqCst};
+
+    use super::{FenceStrategy, unpin_ordering};
+
+    #[test]
+    fn store() {
+        let strategies = [
+            FenceStrategy::Fence,
+            FenceStrategy::Swap,
+            FenceStrategy::CompareAndSwap,
+        ];
+
+        for &s in strategies.iter() {
+            let a = AtomicUsize::new(0);
+            s.store(&a, 7);
+            assert_eq!(a.load(SeqCst), 7);
+            s.store(&a, 8);
+            assert_eq!(a.load(SeqCst), 8);
+        }
+    }
+
+    #[test]
+    fn current() {
+        if cfg!(any(target_arch = "x86", target_arch = "x86_64")) {
+            if !cfg!(any(feature = "fence_seqcst", feature = "fence_swap")) {
+                assert_eq!(FenceStrategy::current(), FenceStrategy::CompareAndSwap);
+            }
+        } else if !cfg!(any(feature = "fence_swap", feature = "fence_cmpxchg")) {
+            assert_eq!(FenceStrategy::current(), FenceStrategy::Fence);
+        }
+    }
+
+    #[test]
+    fn unpin_ordering_releases() {
+        // Anything weaker than `Release` would let loads from the pinned region leak past unpin.
+        assert!(matches!(unpin_ordering(), Release | SeqCst));
+    }
+
+    /// A model of pinning, unpinning, epoch advancement and reclamation, checked with loom.
+    ///
+    /// Run with `RUSTFLAGS="--cfg loom" cargo test --release model`.
+    #[cfg(loom)]
+    mod model {
+        extern crate loom;
+
+        use std::sync::atomic::Ordering::{self, AcqRel, Acquire, Relaxed, SeqCst};
+
+        use self::loom::cell::UnsafeCell;
+        use self::loom::sync::Arc;
+        use self::loom::sync::atomic::{self, AtomicBool, AtomicUsize};
+        use self::loom::thread;
+
+        use super::super::unpin_ordering;
+
+        /// The shared state: the global epoch, the states of two participants, and an atomic
+        /// pointer (modeled as a flag) to an object.
+        struct Shared {
+            epoch: AtomicUsize,
+            states: [AtomicUsize; 2],
+            linked: AtomicBool,
+            object: UnsafeCell<usize>,
+        }
+
+        impl Shared {
+            /// Pins participant `i`, announcing its epoch with a `SeqCst` fence.
+            fn pin(&self, i: usize) {
+                let epoch = self.epoch.load(Relaxed);
+                self.states[i].store(epoch | 1, Relaxed);
+                atomic::fence(SeqCst);
+            }
+
+            /// Unpins participant `i`.
+            fn unpin(&self, i: usize, ord: Ordering) {
+                self.states[i].store(0, ord);
+            }
+
+            /// Tries advancing the epoch on behalf of participant `i`, like `try_advance`.
+            fn try_advance(&self, i: usize) {
+                let epoch = self.epoch.load(SeqCst);
+                let other = self.states[1 - i].load(SeqCst);
+                if other & 1 == 1 && other & !1 != epoch {
+                    return;
+                }
+                let _ = self.epoch.compare_exchange(epoch, epoch + 2, SeqCst, SeqCst);
+            }
+        }
+
+        /// Runs a reader that dereferences the object while pinned, concurrently with a thread
+        /// that unlinks the object and destroys it once the epoch has advanced twice.
+        fn check(unpin: Ordering) {
+            loom::model(move || {
+                let shared = Arc::new(Shared {
+                    epoch: AtomicUsize::new(0),
+                    states: [AtomicUsize::new(0), AtomicUsize::new(0)],
+                    linked: AtomicBool::new(true),
+                    object: UnsafeCell::new(7),
+                });
+
+                let s = shared.clone();
+                let reader = thread::spawn(move || {
+                    s.pin(0);
+                    if s.linked.load(Acquire) {
+                        assert_eq!(s.object.with(|p| unsafe { *p }), 7);
+                    }
+                    s.unpin(0, unpin);
+                });
+
+                // Unlink the object and mark it with the current epoch.
+                shared.pin(1);
+                shared.linked.swap(false, AcqRel);
+                let garbage_epoch = shared.epoch.load(SeqCst);
+                shared.unpin(1, unpin);
+
+                for _ in 0..2 {
+                    shared.pin(1);
+                    shared.try_advance(1);
+                    shared.unpin(1, unpin);
+                }
+
+                // Destroy the object if it has expired.
+                if shared.epoch.load(SeqCst).wrapping_sub(garbage_epoch) > 2 {
+                    shared.object.with_mut(|p| unsafe { *p = 0 });
+                }
+
+                reader.join().unwrap();
+            });
+        }
+
+        #[test]
+        fn unpin_ordering_is_sufficient() {
+            check(unpin_ordering());
+        }
+
+        #[test]
+        #[should_panic(expected = "Causality violation")]
+        fn relaxed_unpin_is_insufficient() {
+            check(Relaxed);
+        }
+    }
+}
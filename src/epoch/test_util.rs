@@ -0,0 +1,100 @@
+//! Utilities for deterministic testing of code built on the epoch GC.
+//!
+//! Normally the global epoch advances and garbage gets collected whenever threads happen to pin
+//! themselves, which makes it hard to test exactly when objects get destroyed. The functions in
+//! this module allow tests to drive the garbage collector by hand.
+//!
+//! This module is available only with the `test_util` feature.
+//!
+//! # Examples
+//!
+//! After two epoch advancements, deferred objects can be destroyed:
+//!
+//! ```
+//! use coco::epoch::{self, Owned};
+//! use coco::epoch::test_util;
+//! use std::sync::atomic::AtomicUsize;
+//! use std::sync::atomic::Ordering::SeqCst;
+//!
+//! static DROPS: AtomicUsize = AtomicUsize::new(0);
+//!
+//! struct Elem(i32);
+//!
+//! impl Drop for Elem {
+//!     fn drop(&mut self) {
+//!         DROPS.fetch_add(1, SeqCst);
+//!     }
+//! }
+//!
+//! epoch::pin(|scope| unsafe {
+//!     scope.defer_drop(Owned::new(Elem(1)).into_ptr(scope));
+//!     assert_eq!(test_util::drain_once(scope), 0);
+//! });
+//! assert_eq!(DROPS.load(SeqCst), 0);
+//!
+//! unsafe { test_util::force_epoch(test_util::epoch() + 2) }
+//!
+//! epoch::pin(|scope| test_util::drain_once(scope));
+//! assert_eq!(DROPS.load(SeqCst), 1);
+//! ```
+
+use std::sync::atomic::Ordering::SeqCst;
+
+use epoch::{self, Scope};
+use epoch::garbage::{self, EPOCH};
+use epoch::thread;
+
+/// Returns the current global epoch, counted in advancements.
+pub fn epoch() -> usize {
+    EPOCH.load(SeqCst) / 2
+}
+
+/// Sets the global epoch to `epoch`, counted in advancements.
+///
+/// The epoch wraps around, so `epoch` is considered ahead of the current one if it is less than
+/// half of the epoch range forward from it.
+///
+/// # Panics
+///
+/// Panics if `epoch` is behind the current epoch. Moving the epoch backwards would make garbage
+/// deferred in the meantime look older than it is, and get it destroyed too early.
+///
+/// # Safety
+///
+/// Advancing the epoch by force lets garbage be destroyed while other threads might still be
+/// using it. No other thread may be pinned while the epoch is forced forward.
+pub unsafe fn force_epoch(epoch: usize) {
+    let current = EPOCH.load(SeqCst);
+    let epoch = epoch.wrapping_mul(2);
+    assert!(
+        epoch.wrapping_sub(current) <= usize::MAX / 2,
+        "the epoch can't be forced backwards"
+    );
+    EPOCH.store(epoch, SeqCst);
+}
+
+/// Moves the local garbage into the global queue and collects it once, without advancing the
+/// epoch.
+///
+/// Returns the number of destroyed objects.
+pub fn drain_once(scope: &Scope) -> usize {
+    thread::push_bag(scope);
+    garbage::collect(scope)
+}
+
+/// Destroys all garbage regardless of epochs and unlinks entries of exited threads.
+///
+/// Returns the number of destroyed objects. Calling this between tests makes sure that garbage
+/// left over by one test doesn't affect the next one.
+///
+/// # Safety
+///
+/// No other thread may be pinned, and none of the garbage may still be in use.
+pub unsafe fn reset() -> usize {
+    epoch::pin(|scope| {
+        thread::push_bag(scope);
+        let destroyed = garbage::destroy_all(scope);
+        thread::try_advance(scope);
+        destroyed
+    })
+}
@@ -14,14 +14,46 @@
 //! Thread entries are implemented as the `Thread` data type. Every entry contains an integer that
 //! tells whether the thread is pinned and if so, what was the global epoch at the time it was
 //! pinned. Entries also hold a pin counter that aids in periodic global epoch advancement.
+//!
+//! # Leak checking
+//!
+//! With the `leak_check` feature, the last participant to exit reclaims all remaining garbage in
+//! the global queue and records how many bags of it couldn't be reclaimed. This happens within a
+//! thread-local destructor, where panicking would abort the process, so the leak is only printed
+//! there and reported by `leaked_bags` afterwards, e.g. to a test that has joined all its threads.
+//! The check is skipped if some participant never exits, e.g. the main thread.
+//!
+//! With the `debug_backtrace` feature as well, a backtrace is captured every time an object gets
+//! deferred, and the leak check prints where each leaked object was deferred. Capturing backtraces
+//! is very slow, so this is meant only for hunting down leaks.
 
+use std::alloc::{self, Layout};
 use std::cell::Cell;
+use std::cmp;
+use std::fmt;
+use std::hint;
+#[cfg(feature = "leak_check")]
+use std::io::{self, Write};
+use std::marker::PhantomData;
 use std::mem;
-use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT};
-use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release, SeqCst};
+use std::ops::Deref;
+use std::panic::Location;
+use std::ptr;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicPtr, AtomicUsize, ATOMIC_USIZE_INIT};
+use std::sync::atomic::Ordering::{self, AcqRel, Acquire, Relaxed, Release, SeqCst};
 
 use epoch::{Atomic, Owned, Ptr};
+use epoch::config;
 use epoch::garbage::{self, Bag, EPOCH};
+#[cfg(feature = "latency_stats")]
+use epoch::latency;
+#[cfg(feature = "metrics")]
+use epoch::metrics;
+#[cfg(feature = "debug_stats")]
+use epoch::stats;
+use epoch::sync::{self, FenceStrategy};
 
 thread_local! {
     /// The thread registration harness.
@@ -29,12 +61,13 @@ thread_local! {
     /// The harness is lazily initialized on it's first use. Initialization performs registration.
     /// If initialized, the harness will get destructed on thread exit, which in turn unregisters
     /// the thread.
-    static HARNESS: Harness = Harness {
-        thread: Thread::register(),
-        is_pinned: Cell::new(false),
-        pin_count: Cell::new(0),
-        bag: Cell::new(Box::into_raw(Box::new(Bag::new()))),
+    static HARNESS: Harness = {
+        REGISTERED.with(|r| r.set(true));
+        Harness::new()
     };
+
+    /// Whether `HARNESS` has been initialized on this thread.
+    static REGISTERED: Cell<bool> = const { Cell::new(false) };
 }
 
 /// Holds thread-local data and unregisters the thread when dropped.
@@ -47,6 +80,312 @@ struct Harness {
     pin_count: Cell<usize>,
     /// The local bag of objects that will be later freed.
     bag: Cell<*mut Bag>,
+    /// The backoff from garbage collection while the epoch is stalled.
+    backoff: Backoff,
+    /// The moment when the thread got pinned.
+    #[cfg(feature = "latency_stats")]
+    pinned_at: Cell<Instant>,
+}
+
+impl Harness {
+    /// Registers a new thread entry and returns a harness holding it.
+    fn new() -> Harness {
+        Harness {
+            thread: Thread::register(),
+            is_pinned: Cell::new(false),
+            pin_count: Cell::new(0),
+            bag: Cell::new(Box::into_raw(garbage::alloc_bag())),
+            backoff: Backoff::new(),
+            #[cfg(feature = "latency_stats")]
+            pinned_at: Cell::new(Instant::now()),
+        }
+    }
+
+    /// Tries advancing the epoch, collects some global garbage, and returns the number of destroyed
+    /// objects.
+    ///
+    /// Does nothing while the thread is backing off from a stalled epoch.
+    fn reclaim(&self, pin: &Scope) -> usize {
+        if self.backoff.is_active() {
+            return 0;
+        }
+
+        let advanced = try_advance(pin);
+        let destroyed = garbage::collect(pin);
+        self.backoff.record(advanced, destroyed);
+        destroyed
+    }
+
+    /// Pins the thread and returns `true` if it was already pinned.
+    #[inline]
+    fn pin(&self) -> bool {
+        let was_pinned = self.is_pinned.get();
+        if !was_pinned {
+            let thread = unsafe { &*self.thread };
+            let pin = &Scope { harness: self };
+
+            // Pin the thread.
+            self.is_pinned.set(true);
+            thread.set_pinned(pin);
+
+            #[cfg(feature = "metrics")]
+            metrics::record_pin();
+
+            #[cfg(feature = "latency_stats")]
+            self.pinned_at.set(Instant::now());
+
+            // Increment the pin counter.
+            let count = self.pin_count.get();
+            self.pin_count.set(count.wrapping_add(1));
+
+            // Count down the collection backoff, if any.
+            self.backoff.tick();
+
+            // Every few pinnings, if the collection policy says so, try advancing the epoch and
+            // collecting garbage. Garbage piling up in the global queue is collected more often,
+            // so that threads that don't produce garbage share the cost of collecting it.
+            if count & (config::PINS_BETWEEN_CHECKS - 1) == 0 {
+                let help = !self.backoff.is_active() && garbage::has_backlog();
+                if help || config::should_collect(LIVE_PARTICIPANTS.load(Relaxed), count) {
+                    self.reclaim(pin);
+                }
+            }
+        }
+        was_pinned
+    }
+
+    /// Unpins the thread.
+    #[inline]
+    fn unpin(&self) {
+        let thread = unsafe { &*self.thread };
+        thread.set_unpinned();
+        self.is_pinned.set(false);
+
+        #[cfg(feature = "latency_stats")]
+        latency::record(self.pinned_at.get().elapsed());
+
+        // The epoch has been stalled for a while. Let other threads run instead of spinning.
+        if self.backoff.yield_on_unpin.get() {
+            self.backoff.yield_on_unpin.set(false);
+            thread::yield_now();
+        }
+    }
+
+    /// Pins the harness for the duration of `f`.
+    fn pinned<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&Scope) -> T
+    {
+        let was_pinned = self.pin();
+
+        // This will unpin even if `f` panics.
+        defer! {
+            if !was_pinned {
+                self.unpin();
+            }
+        }
+
+        f(&Scope { harness: self })
+    }
+
+    /// Destroys as much garbage as possible, pinning through this harness.
+    ///
+    /// See `collect_all` for details.
+    fn collect_all(&self) -> (usize, bool) {
+        /// Number of stalled attempts after which we give up.
+        const MAX_STALLS: usize = 16;
+
+        let mut destroyed = self.pinned(|scope| scope.flush_counted());
+        let mut stalls = 0;
+
+        loop {
+            let (advanced, count, is_empty) = self.pinned(|scope| {
+                let advanced = try_advance(scope);
+                let count = garbage::collect(scope);
+                (advanced, count, garbage::is_empty(scope))
+            });
+            destroyed += count;
+
+            if is_empty {
+                return (destroyed, true);
+            }
+
+            if advanced || count > 0 {
+                stalls = 0;
+            } else {
+                // Maybe some other thread is pinned just for a moment. Let it make progress.
+                stalls += 1;
+                if stalls == MAX_STALLS {
+                    return (destroyed, false);
+                }
+                thread::yield_now();
+            }
+        }
+    }
+}
+
+/// Tracks fruitless attempts at reclaiming garbage while the epoch is stalled.
+///
+/// See `config::set_stall_backoff` for the policy.
+struct Backoff {
+    /// Number of consecutive attempts that neither advanced the epoch nor destroyed anything.
+    stalls: Cell<usize>,
+    /// Number of remaining pinnings during which the thread doesn't collect garbage.
+    pins: Cell<usize>,
+    /// Whether the thread should yield to the scheduler as soon as it gets unpinned.
+    yield_on_unpin: Cell<bool>,
+}
+
+impl Backoff {
+    /// Returns a backoff that is not active.
+    fn new() -> Backoff {
+        Backoff {
+            stalls: Cell::new(0),
+            pins: Cell::new(0),
+            yield_on_unpin: Cell::new(false),
+        }
+    }
+
+    /// Returns `true` if the thread is currently skipping garbage collection.
+    #[inline]
+    fn is_active(&self) -> bool {
+        self.pins.get() > 0
+    }
+
+    /// Counts down one pinning of the backoff, if any.
+    #[inline]
+    fn tick(&self) {
+        let pins = self.pins.get();
+        if pins > 0 {
+            self.pins.set(pins - 1);
+        }
+    }
+
+    /// Records the outcome of an attempt at advancing the epoch and collecting garbage.
+    ///
+    /// After every `config::stall_backoff()` fruitless attempts in a row, the thread backs off for
+    /// twice as many pinnings as the previous time, and yields once it gets unpinned. Yielding
+    /// while pinned would only hold the epoch back even longer. Any progress resets the backoff.
+    fn record(&self, advanced: bool, destroyed: usize) {
+        /// Number of pinnings skipped by the first backoff.
+        const MIN_PINS: usize = 128;
+        /// Maximum number of pinnings skipped by a backoff.
+        const MAX_PINS: usize = 8192;
+
+        if advanced || destroyed > 0 {
+            self.reset();
+            return;
+        }
+
+        let limit = config::stall_backoff();
+        let stalls = self.stalls.get() + 1;
+        self.stalls.set(stalls);
+
+        if limit > 0 && stalls.is_multiple_of(limit) {
+            let shift = cmp::min(stalls / limit - 1, 16);
+            self.pins.set(cmp::min(MIN_PINS << shift, MAX_PINS));
+            self.yield_on_unpin.set(true);
+        }
+    }
+
+    /// Cancels the backoff, so that the thread collects garbage again.
+    fn reset(&self) {
+        self.stalls.set(0);
+        self.pins.set(0);
+    }
+}
+
+#[cfg(feature = "leak_check")]
+impl Harness {
+    /// Reclaims all garbage if this is the last participant, and records how much is left over.
+    ///
+    /// Must be called after the local bag has been pushed and the thread unpinned, but before the
+    /// thread entry is unregistered.
+    fn check_leaks(&self) {
+        /// Number of consecutive rounds without any progress after which we give up.
+        const IDLE_ROUNDS: usize = 8;
+
+        if LIVE_PARTICIPANTS.load(SeqCst) != 1 {
+            return;
+        }
+
+        let thread = unsafe { &*self.thread };
+        let pin = &Scope { harness: self };
+
+        // The local bag has already been pushed, so garbage produced by collection (i.e. the
+        // entries of the queue itself) must go into a new one.
+        self.bag.set(Box::into_raw(garbage::alloc_bag()));
+
+        // Nobody else is pinned, so the epoch can advance freely. The thread is pinned in every
+        // round separately, so that it doesn't hold the epoch back.
+        let mut idle = 0;
+        let mut is_empty = false;
+        while !is_empty && idle < IDLE_ROUNDS {
+            thread.set_pinned(pin);
+            let advanced = try_advance(pin);
+            let destroyed = garbage::collect(pin);
+            is_empty = garbage::is_empty(pin);
+            thread.set_unpinned();
+
+            if advanced || destroyed > 0 {
+                idle = 0;
+            } else {
+                idle += 1;
+            }
+        }
+
+        thread.set_pinned(pin);
+        let bag = unsafe { Box::from_raw(self.bag.get()) };
+        let leaked = garbage::bag_count();
+        garbage::push(bag, pin);
+        thread.set_unpinned();
+
+        // Another participant may have registered in the meantime and held the epoch back.
+        if !is_empty && LIVE_PARTICIPANTS.load(SeqCst) == 1 {
+            #[cfg(feature = "debug_backtrace")]
+            {
+                thread.set_pinned(pin);
+                garbage::print_backtraces(pin);
+                thread.set_unpinned();
+            }
+
+            // Printing must not panic in a thread-local destructor.
+            let _ = writeln!(
+                io::stderr(),
+                "garbage left unreclaimed after all participants have exited: {} bags",
+                leaked
+            );
+            LEAKED_BAGS.store(leaked, SeqCst);
+        } else if is_empty {
+            LEAKED_BAGS.store(0, SeqCst);
+        }
+    }
+}
+
+/// Number of bags the last leak check failed to reclaim.
+#[cfg(feature = "leak_check")]
+static LEAKED_BAGS: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of bags of garbage that the last participant to exit failed to reclaim.
+///
+/// The last participant to exit a process reclaims all remaining garbage. If some of it can't be
+/// reclaimed, e.g. because another thread registered in the meantime and held the epoch back, it
+/// gets leaked and reported here. Once a later check reclaims everything, this goes back to zero.
+///
+/// This function is available only with the `leak_check` feature.
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+/// use std::thread;
+///
+/// thread::spawn(|| epoch::pin(|_| ())).join().unwrap();
+/// assert_eq!(epoch::leaked_bags(), 0);
+/// ```
+#[cfg(feature = "leak_check")]
+pub fn leaked_bags() -> usize {
+    LEAKED_BAGS.load(SeqCst)
 }
 
 impl Drop for Harness {
@@ -57,7 +396,7 @@ impl Drop for Harness {
 
         // If we called `pin()` here, it would try to access `HARNESS` and then panic.
         // To work around the problem, we manually pin the thread.
-        let pin = &Scope { bag: &self.bag };
+        let pin = &Scope { harness: self };
         thread.set_pinned(pin);
 
         // Spare some cycles on garbage collection.
@@ -72,11 +411,18 @@ impl Drop for Harness {
         // Manually unpin the thread.
         thread.set_unpinned();
 
+        // If this is the last participant, make sure all garbage can be reclaimed.
+        #[cfg(feature = "leak_check")]
+        self.check_leaks();
+
         // Mark the thread entry as deleted.
         thread.unregister();
     }
 }
 
+/// Number of registered participants that haven't been unregistered yet.
+static LIVE_PARTICIPANTS: AtomicUsize = AtomicUsize::new(0);
+
 /// An entry in the linked list of participating threads.
 struct Thread {
     /// The least significant bit is set if the thread is currently pinned. The rest of the bits
@@ -87,6 +433,80 @@ struct Thread {
     next: Atomic<Thread>,
 }
 
+/// The amount by which the global epoch advances.
+const EPOCH_STEP: usize = 2;
+
+// The deletion mark in `Thread::next` is a tag, which lives in the unused least significant bits
+// of pointers to `Thread`. There is room for it only if entries are at least 2-byte aligned.
+const _: () = assert!(mem::align_of::<Thread>() >= 2);
+
+// `Thread::state` keeps the pin bit in bit 0 and the epoch in the rest, so every epoch must have
+// bit 0 clear. The epoch starts at zero and advances by `EPOCH_STEP`, so the step must be even.
+// The epoch also wraps around, which preserves evenness only if the step divides 2^N, i.e. it is
+// a power of two.
+const _: () = assert!(EPOCH_STEP & 1 == 0 && EPOCH_STEP.is_power_of_two());
+
+/// Maximum number of reclaimed participant entries kept for reuse.
+const MAX_FREE_THREADS: usize = 16;
+
+/// Participant entries that have been unlinked and reclaimed, waiting to be reused.
+///
+/// An entry gets here only once the epoch GC has proved that no thread can still reach it, so a
+/// registering thread can take it over right away. Every slot is either null or owns an entry.
+/// Slots are claimed with a `swap`, so the same entry can't be taken twice.
+static FREE_THREADS: [AtomicPtr<Thread>; MAX_FREE_THREADS] =
+    [const { AtomicPtr::new(ptr::null_mut()) }; MAX_FREE_THREADS];
+
+/// Returns a fresh participant entry, reusing a reclaimed one if possible.
+fn alloc_thread() -> Box<Thread> {
+    for slot in FREE_THREADS.iter() {
+        if !slot.load(Relaxed).is_null() {
+            let raw = slot.swap(ptr::null_mut(), Acquire);
+            if !raw.is_null() {
+                let thread = unsafe { Box::from_raw(raw) };
+                thread.state.store(0, Relaxed);
+                thread.next.store(Ptr::null(), Relaxed);
+                return thread;
+            }
+        }
+    }
+
+    Box::new(Thread {
+        state: AtomicUsize::new(0),
+        next: Atomic::null(),
+    })
+}
+
+/// Offers a reclaimed participant entry for reuse, or deallocates it if all slots are taken.
+fn recycle_thread(thread: Box<Thread>) {
+    let raw = Box::into_raw(thread);
+    for slot in FREE_THREADS.iter() {
+        if slot.load(Relaxed).is_null()
+            && slot.compare_exchange(ptr::null_mut(), raw, Release, Relaxed).is_ok()
+        {
+            return;
+        }
+    }
+    unsafe { drop(Box::from_raw(raw)) }
+}
+
+/// Defers reclamation of a participant entry that was unlinked from the list.
+///
+/// Instead of being freed, the entry will be offered for reuse by future registrations.
+unsafe fn defer_thread(scope: &Scope, thread: Ptr<Thread>) {
+    unsafe fn recycle(ptr: *mut Thread, _count: usize) {
+        #[cfg(feature = "debug_stats")]
+        stats::record_reclaimed::<Thread>(1);
+
+        recycle_thread(Box::from_raw(ptr));
+    }
+
+    scope.defer_destroy(recycle, thread.as_raw(), 1);
+
+    #[cfg(feature = "debug_stats")]
+    stats::record_deferred::<Thread>(1);
+}
+
 impl Thread {
     /// Marks the thread as pinned.
     ///
@@ -99,57 +519,68 @@ impl Thread {
         // Now we must store `state` into `self.state`. It's important that any succeeding loads
         // don't get reordered with this store. In order words, this thread's epoch must be fully
         // announced to other threads. Only then it becomes safe to load from the shared memory.
-        if cfg!(any(target_arch = "x86", target_arch = "x86_64")) {
-            // On x86 architectures we have a choice:
-            // 1. `atomic::fence(SeqCst)`, which compiles to a `mfence` instruction.
-            // 2. `compare_and_swap(_, _, SeqCst)`, which compiles to a `lock cmpxchg` instruction.
-            //
-            // Both instructions have the effect of a full barrier, but the second one seems to be
-            // faster in this particular case.
-            let previous = self.state.load(Relaxed);
-            self.state.compare_and_swap(previous, state, SeqCst);
-        } else {
-            self.state.store(state, Relaxed);
-            ::std::sync::atomic::fence(SeqCst);
-        }
+        // The best way of doing that depends on the architecture (see the `sync` module).
+        FenceStrategy::current().store(&self.state, state);
     }
 
     /// Marks the thread as unpinned.
     #[inline]
     fn set_unpinned(&self) {
         // Clear the last bit.
-        // We don't need to preserve the epoch, so just store the number zero.
-        self.state.store(0, Release);
+        // We don't need to preserve the epoch, so just store the number zero. All loads from the
+        // pinned region must be done before other threads see this store, which takes `Release`
+        // but no fence (see `sync::unpin_ordering` for the full argument).
+        self.state.store(0, sync::unpin_ordering());
     }
 
     /// Registers a thread by adding a new entry to the list of participanting threads.
     ///
-    /// Returns a pointer to the newly allocated entry.
+    /// Returns a pointer to the new entry, which may be a reused one of an exited thread.
     fn register() -> *const Thread {
+        LIVE_PARTICIPANTS.fetch_add(1, SeqCst);
+
         let list = participants();
 
-        let mut new = Owned::new(Thread {
-            state: AtomicUsize::new(0),
-            next: Atomic::null(),
-        });
+        let mut new = Owned::from_box(alloc_thread());
 
         // This code is executing while the thread harness is initializing, so normal pinning would
         // try to access it while it is being initialized. Such accesses fail with a panic. We must
-        // therefore cheat by creating a fake pin.
-        let pin = unsafe { &mem::zeroed::<Scope>() };
+        // therefore use an unprotected scope. That is fine because we don't dereference anything.
+        unsafe {
+            unprotected(|pin| {
+                /// Number of failed attempts after which the backoff yields instead of spinning.
+                const SPIN_LIMIT: u32 = 6;
+
+                let mut step = 0;
+                let mut head = list.load(Acquire, pin);
+                loop {
+                    new.next.store(head, Relaxed);
+
+                    // Try installing this thread's entry as the new head.
+                    match list.compare_and_swap_weak_owned(head, new, AcqRel, pin) {
+                        Ok(n) => return n.as_raw(),
+                        Err((h, n)) => {
+                            head = h;
+                            new = n;
+                        }
+                    }
 
-        let mut head = list.load(Acquire, pin);
-        loop {
-            new.next.store(head, Relaxed);
-
-            // Try installing this thread's entry as the new head.
-            match list.compare_and_swap_weak_owned(head, new, AcqRel, pin) {
-                Ok(n) => return n.as_raw(),
-                Err((h, n)) => {
-                    head = h;
-                    new = n;
+                    #[cfg(feature = "metrics")]
+                    metrics::record_registration_retry();
+
+                    // Many threads might be registering at the same time, e.g. while a thread pool
+                    // is warming up. Back off exponentially so that they don't keep stealing the
+                    // cache line of the list head from each other.
+                    if step <= SPIN_LIMIT {
+                        for _ in 0..1 << step {
+                            hint::spin_loop();
+                        }
+                        step += 1;
+                    } else {
+                        thread::yield_now();
+                    }
                 }
-            }
+            })
         }
     }
 
@@ -158,18 +589,22 @@ impl Thread {
     /// This function doesn't physically remove the entry from the linked list, though. That will
     /// do a future call to `try_advance`.
     fn unregister(&self) {
-        // This code is executing while the thread harness is initializing, so normal pinning would
-        // try to access it while it is being initialized. Such accesses fail with a panic. We must
-        // therefore cheat by creating a fake pin.
-        let pin = unsafe { &mem::zeroed::<Scope>() };
+        LIVE_PARTICIPANTS.fetch_sub(1, SeqCst);
 
-        // Simply mark the next-pointer in this thread's entry.
-        let mut next = self.next.load(Acquire, pin);
-        while next.tag() == 0 {
-            match self.next.compare_and_swap(next, next.with_tag(1), AcqRel, pin) {
-                Ok(()) => break,
-                Err(n) => next = n,
-            }
+        // This code is executing while the thread harness is being destructed, so normal pinning
+        // would try to access it. Such accesses fail with a panic. We must therefore use an
+        // unprotected scope. That is fine because we don't dereference anything.
+        unsafe {
+            unprotected(|pin| {
+                // Simply mark the next-pointer in this thread's entry.
+                let mut next = self.next.load(Acquire, pin);
+                while next.tag() == 0 {
+                    match self.next.compare_and_swap(next, next.with_tag(1), AcqRel, pin) {
+                        Ok(()) => break,
+                        Err(n) => next = n,
+                    }
+                }
+            })
         }
     }
 }
@@ -180,57 +615,247 @@ fn participants() -> &'static Atomic<Thread> {
     unsafe { &*(&PARTICIPANTS as *const _ as *const _) }
 }
 
+/// Returns an identifier of the garbage collector used by this copy of the crate.
+///
+/// The identifier is the same everywhere in the process, unless the crate is linked several
+/// times, e.g. into a dynamic library and the main binary. In that case each copy has its own
+/// collector with a different identifier, and epoch-protected pointers must not be passed from one
+/// copy to the other.
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+/// use std::thread;
+///
+/// let id = epoch::collector_instance_id();
+/// assert_eq!(thread::spawn(epoch::collector_instance_id).join().unwrap(), id);
+/// ```
+pub fn collector_instance_id() -> u64 {
+    participants() as *const _ as usize as u64
+}
+
 /// Attempts to advance the global epoch.
 ///
 /// The global epoch can advance only if all currently pinned threads have been pinned in the
 /// current epoch.
+///
+/// Returns `true` if the global epoch has moved past the one loaded at the beginning of the call,
+/// regardless of which thread managed to advance it.
 #[cold]
-pub fn try_advance(scope: &Scope) {
+pub fn try_advance(scope: &Scope) -> bool {
+    // If the epoch has moved since the current thread was pinned, its own entry holds the epoch
+    // back, so the traversal below can't succeed. Relaxed loads are enough for this check: the
+    // thread reads its own state, and the epoch it was pinned in was loaded from `EPOCH` before,
+    // so a different value can only be a newer one. Exited entries stay in the list until the
+    // next full traversal, which any advancement has to make.
+    if let Some(harness) = unsafe { scope.harness.as_ref() } {
+        if harness.is_pinned.get() {
+            let pinned_epoch = unsafe { &*harness.thread }.state.load(Relaxed) & !1;
+            if EPOCH.load(Relaxed) != pinned_epoch {
+                return false;
+            }
+        }
+    }
+
     let epoch = EPOCH.load(SeqCst);
 
     // Traverse the linked list of participating threads.
-    let mut pred = participants();
-    let mut curr = pred.load(Acquire, scope);
-
-    while let Some(c) = unsafe { curr.as_ref() } {
-        let succ = c.next.load(Acquire, scope);
+    'retry: loop {
+        let mut pred = participants();
+        let mut curr = pred.load(Acquire, scope);
+
+        while let Some(c) = unsafe { curr.as_ref() } {
+            let (succ, tag) = c.next.load_split(Acquire, scope);
+
+            if tag == 1 {
+                // This thread has exited. Try unlinking it from the list.
+                match pred.compare_and_swap(curr, succ, AcqRel, scope) {
+                    Ok(()) => {
+                        // The unlinked entry can later be reused.
+                        unsafe { defer_thread(scope, curr) }
+
+                        // Move forward, but don't change the predecessor.
+                        curr = succ;
+                    }
+                    Err(actual) => {
+                        // Another thread has changed the predecessor's link in the meantime. We
+                        // mustn't leave the job to that thread, since its traversal might have
+                        // already passed this entry.
+                        if actual.tag() == 1 {
+                            // The predecessor has exited as well. Start over from the beginning.
+                            continue 'retry;
+                        }
 
-        if succ.tag() == 1 {
-            // This thread has exited. Try unlinking it from the list.
-            let succ = succ.with_tag(0);
+                        // Continue from the predecessor's new successor.
+                        curr = actual;
+                    }
+                }
+            } else {
+                let thread_state = c.state.load(SeqCst);
+                let thread_is_pinned = thread_state & 1 == 1;
+                let thread_epoch = thread_state & !1;
+
+                // If the thread was pinned in a different epoch, we cannot advance the global
+                // epoch just yet.
+                if thread_is_pinned && thread_epoch != epoch {
+                    return false;
+                }
 
-            if pred.compare_and_swap(curr, succ, AcqRel, scope).is_err() {
-                // We lost the race to unlink the thread. Usually that means we should traverse the
-                // list again from the beginning, but since another thread trying to advance the
-                // epoch has won the race, we leave the job to that one.
-                return;
+                // Move one step forward.
+                pred = &c.next;
+                curr = succ;
             }
+        }
 
-            // The unlinked entry can later be freed.
-            unsafe { scope.defer_free(curr) }
+        break;
+    }
 
-            // Move forward, but don't change the predecessor.
-            curr = succ;
-        } else {
-            let thread_state = c.state.load(SeqCst);
-            let thread_is_pinned = thread_state & 1 == 1;
-            let thread_epoch = thread_state & !1;
+    // All pinned threads were pinned in the current global epoch.
+    // Finally, try advancing the epoch. We increment by 2 and simply wrap around on overflow.
+    EPOCH.compare_and_swap(epoch, epoch.wrapping_add(EPOCH_STEP), SeqCst);
+    true
+}
 
-            // If the thread was pinned in a different epoch, we cannot advance the global epoch
-            // just yet.
-            if thread_is_pinned && thread_epoch != epoch {
-                return;
+/// Unlinks all entries of exited threads from the list of participants.
+///
+/// Entries of exited threads are normally unlinked lazily while advancing the epoch. In
+/// applications that spawn many short-lived threads, such entries may pile up and slow down every
+/// traversal of the list. This function walks the whole list once and unlinks every such entry,
+/// e.g. when called periodically from a maintenance thread.
+///
+/// Returns the number of unlinked entries. They are reclaimed through the epoch GC, and then reused
+/// by threads that register later.
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+/// use std::thread;
+///
+/// thread::spawn(|| epoch::pin(|_| ())).join().unwrap();
+/// epoch::pin(|scope| epoch::compact_participants(scope));
+/// ```
+pub fn compact_participants(scope: &Scope) -> usize {
+    let mut unlinked = 0;
+
+    'retry: loop {
+        let mut pred = participants();
+        let mut curr = pred.load(Acquire, scope);
+
+        while let Some(c) = unsafe { curr.as_ref() } {
+            let succ = c.next.load(Acquire, scope);
+
+            if succ.tag() == 1 {
+                // This thread has exited. Try unlinking it from the list.
+                let succ = succ.with_tag(0);
+
+                if pred.compare_and_swap(curr, succ, AcqRel, scope).is_err() {
+                    // The predecessor has changed. Start over from the beginning.
+                    continue 'retry;
+                }
+
+                // The unlinked entry can later be reused.
+                unsafe { defer_thread(scope, curr) }
+                unlinked += 1;
+
+                // Move forward, but don't change the predecessor.
+                curr = succ;
+            } else {
+                // Move one step forward.
+                pred = &c.next;
+                curr = succ;
             }
+        }
+
+        return unlinked;
+    }
+}
+
+/// The state of a participant in garbage collection, as captured by [`debug_snapshot`].
+///
+/// [`debug_snapshot`]: fn.debug_snapshot.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParticipantSnapshot {
+    /// Whether the participant was pinned.
+    pub is_pinned: bool,
+    /// The epoch the participant was pinned in, or zero if it wasn't pinned.
+    pub epoch: usize,
+}
 
-            // Move one step forward.
-            pred = &c.next;
-            curr = succ;
+/// A snapshot of the garbage collector's state, returned by [`debug_snapshot`].
+///
+/// [`debug_snapshot`]: fn.debug_snapshot.html
+#[derive(Clone, Debug)]
+pub struct DebugSnapshot {
+    /// The global epoch.
+    pub epoch: usize,
+    /// States of the live participants, in the order of the participant list.
+    pub participants: Vec<ParticipantSnapshot>,
+    /// Number of exited participants whose entries haven't been unlinked yet.
+    pub exited: usize,
+    /// Number of bags of garbage waiting in the global queue.
+    pub pending_bags: usize,
+    /// Number of upcoming pinnings during which the current thread will skip garbage collection
+    /// because its recent attempts at it have made no progress while the epoch was stalled.
+    pub collect_backoff: usize,
+}
+
+/// Captures the state of all participants and the global epoch, for diagnosing stuck reclamation.
+///
+/// If garbage keeps piling up, the snapshot usually shows a participant that has been pinned in
+/// an old epoch and prevents the global epoch from advancing.
+///
+/// The states are read one by one while traversing the participant list, so the snapshot is not
+/// atomic. Participants may be pinned and unpinned while it is being taken. The current thread is
+/// pinned while taking the snapshot, so it appears in the list as pinned.
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+///
+/// let snapshot = epoch::debug_snapshot();
+/// for p in &snapshot.participants {
+///     if p.is_pinned && p.epoch != snapshot.epoch {
+///         println!("a participant is lagging behind: {:?}", p);
+///     }
+/// }
+/// ```
+pub fn debug_snapshot() -> DebugSnapshot {
+    pin(snapshot)
+}
+
+/// Captures the state of all participants and the global epoch while the current thread is pinned.
+fn snapshot(scope: &Scope) -> DebugSnapshot {
+    let epoch = EPOCH.load(SeqCst);
+    let mut states = Vec::new();
+    let mut exited = 0;
+
+    let mut curr = participants().load(Acquire, scope);
+    while let Some(c) = unsafe { curr.as_ref() } {
+        let succ = c.next.load(Acquire, scope);
+
+        if succ.tag() == 1 {
+            exited += 1;
+        } else {
+            let state = c.state.load(SeqCst);
+            states.push(ParticipantSnapshot {
+                is_pinned: state & 1 == 1,
+                epoch: state & !1,
+            });
         }
+
+        curr = succ.with_tag(0);
     }
 
-    // All pinned threads were pinned in the current global epoch.
-    // Finally, try advancing the epoch. We increment by 2 and simply wrap around on overflow.
-    EPOCH.compare_and_swap(epoch, epoch.wrapping_add(2), SeqCst);
+    DebugSnapshot {
+        epoch,
+        participants: states,
+        exited,
+        pending_bags: garbage::bag_count(),
+        collect_backoff: scope.harness().backoff.pins.get(),
+    }
 }
 
 /// A witness that the current thread is pinned.
@@ -245,12 +870,11 @@ pub fn try_advance(scope: &Scope) {
 /// [`Atomic`]: struct.Atomic.html
 #[derive(Debug)]
 pub struct Scope {
-    /// A pointer to the cell within the harness, which holds a pointer to the local bag.
+    /// A pointer to the harness of the current thread, which holds the local bag.
     ///
     /// This pointer is kept within `Scope` as a matter of convenience. It could also be reached
-    /// through the harness itself, but that doesn't work if we're in the process of it's
-    /// destruction.
-    bag: *const Cell<*mut Bag>, // !Send + !Sync
+    /// through `HARNESS`, but that doesn't work if we're in the process of it's destruction.
+    harness: *const Harness, // !Send + !Sync
 }
 
 impl Scope {
@@ -262,31 +886,55 @@ impl Scope {
     /// If the object is unusually large, it is wise to follow up with a call to [`flush`] so that
     /// it doesn't get stuck waiting in the buffer for a long time.
     ///
+    /// The pointer is passed as loaded from an [`Atomic`], so there is no need to extract the raw
+    /// address first. The tag is ignored, which means a pointer can be deferred right after it was
+    /// logically deleted by tagging it. If `ptr` is null (regardless of the tag), this method does
+    /// nothing.
+    ///
     /// [`Garbage`]: struct.Garbage.html
     /// [`flush`]: struct.Scope.html#method.flush
+    /// [`Atomic`]: struct.Atomic.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic, Ptr};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    ///
+    /// epoch::pin(|scope| {
+    ///     // Logically delete the object by tagging the pointer.
+    ///     let p = a.load(SeqCst, scope);
+    ///     a.compare_and_swap(p, p.with_tag(1), SeqCst, scope).unwrap();
+    ///
+    ///     // Unlink it and defer freeing in one go.
+    ///     let p = a.swap(Ptr::null(), SeqCst, scope);
+    ///     assert_eq!(p.tag(), 1);
+    ///     unsafe { scope.defer_free(p) }
+    /// });
+    /// ```
     pub unsafe fn defer_free<T>(&self, ptr: Ptr<T>) {
         let object = ptr.as_raw();
         let count = 1;
 
+        // There's nothing to free behind a null pointer.
+        if object.is_null() {
+            return;
+        }
+
         unsafe fn free<T>(ptr: *mut T, count: usize) {
+            #[cfg(feature = "debug_stats")]
+            stats::record_reclaimed::<T>(count);
+
             // Free the memory, but don't run the destructors.
             drop(Vec::from_raw_parts(ptr as *mut T, 0, count));
         }
 
-        loop {
-            // Get the thread-local bag.
-            let cell = &*self.bag;
-            let bag = cell.get();
-
-            // Try inserting the object into the bag.
-            if (*bag).try_insert(free::<T>, object, count) {
-                // Success! We're done.
-                break;
-            }
+        self.defer_destroy(free::<T>, object, count);
 
-            // Flush the garbage and create a new bag.
-            self.flush();
-        }
+        #[cfg(feature = "debug_stats")]
+        stats::record_deferred::<T>(count);
     }
 
     /// Adds an object that will later be dropped and freed.
@@ -297,30 +945,286 @@ impl Scope {
     /// This method inserts the object into the garbage buffer. When the buffers becomes full, it's
     /// objects are flushed into the garbage queue.
     ///
-    /// Note: The object must be `Send + 'self`.
-    pub unsafe fn defer_drop<T>(&self, ptr: Ptr<T>) {
+    /// If `ptr` is null (regardless of the tag), this method does nothing.
+    ///
+    /// The object may get dropped by any thread that happens to collect garbage, which is why it
+    /// must be `Send`. Objects that merely need to be freed, like those passed to [`defer_free`],
+    /// don't have that requirement.
+    ///
+    /// Note: The object must outlive `'self`.
+    ///
+    /// [`defer_free`]: struct.Scope.html#method.defer_free
+    ///
+    /// # Examples
+    ///
+    /// An `Rc` must be dropped on the thread that owns it, so it can't be deferred:
+    ///
+    /// ```compile_fail
+    /// use coco::epoch::{self, Owned};
+    /// use std::rc::Rc;
+    ///
+    /// epoch::pin(|scope| unsafe {
+    ///     scope.defer_drop(Owned::new(Rc::new(1)).into_ptr(scope));
+    /// });
+    /// ```
+    pub unsafe fn defer_drop<T: Send>(&self, ptr: Ptr<T>) {
         let object = ptr.as_raw();
         let count = 1;
 
+        // There's nothing to drop behind a null pointer.
+        if object.is_null() {
+            return;
+        }
+
         unsafe fn destruct<T>(ptr: *mut T, count: usize) {
+            #[cfg(feature = "debug_stats")]
+            stats::record_reclaimed::<T>(count);
+
             // Run the destructors and free the memory.
             drop(Vec::from_raw_parts(ptr, count, count));
         }
 
-        loop {
-            // Get the thread-local bag.
-            let cell = &*self.bag;
-            let bag = cell.get();
-
-            // Try inserting the object into the bag.
-            if (*bag).try_insert(destruct::<T>, object, count) {
-                // Success! We're done.
-                break;
-            }
+        self.defer_destroy(destruct::<T>, object, count);
 
-            // Flush the garbage and create a new bag.
-            self.flush();
-        }
+        #[cfg(feature = "debug_stats")]
+        stats::record_deferred::<T>(count);
+    }
+
+    /// Stashes away an object with a custom memory layout that will later be deallocated.
+    ///
+    /// This is the counterpart of [`defer_free`] for objects that were not allocated as a single
+    /// `T`, but with [`alloc`] using `layout`, e.g. nodes consisting of a header followed by a
+    /// variable-length payload. The memory is deallocated with [`dealloc`] and `layout`, without
+    /// running any destructors.
+    ///
+    /// If `ptr` is null (regardless of the tag), this method does nothing.
+    ///
+    /// # Safety
+    ///
+    /// The object must have been allocated with `layout` by the global allocator, and no thread
+    /// may be able to obtain a new pointer to it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size of `layout` doesn't fit into `usize::MAX >> 6`, which can only happen
+    /// on 32-bit platforms with objects larger than 64 MiB.
+    ///
+    /// [`defer_free`]: struct.Scope.html#method.defer_free
+    /// [`alloc`]: https://doc.rust-lang.org/std/alloc/fn.alloc.html
+    /// [`dealloc`]: https://doc.rust-lang.org/std/alloc/fn.dealloc.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic, Ptr};
+    /// use std::alloc::{self, Layout};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// // A node with a header and room for 100 inline bytes.
+    /// let layout = Layout::from_size_align(8 + 100, 8).unwrap();
+    ///
+    /// let a = Atomic::<u64>::null();
+    /// unsafe {
+    ///     let raw = alloc::alloc(layout) as *mut u64;
+    ///     raw.write(100);
+    ///     a.store(Ptr::from_raw(raw), SeqCst);
+    /// }
+    ///
+    /// epoch::pin(|scope| unsafe {
+    ///     let p = a.swap(Ptr::null(), SeqCst, scope);
+    ///     assert_eq!(*p.deref(), 100);
+    ///     scope.defer_dealloc(p, layout);
+    /// });
+    /// ```
+    pub unsafe fn defer_dealloc<T>(&self, ptr: Ptr<T>, layout: Layout) {
+        /// Number of bits in which the base-2 logarithm of the alignment is packed.
+        const ALIGN_BITS: usize = 6;
+
+        let object = ptr.as_raw();
+
+        // There's nothing to deallocate behind a null pointer.
+        if object.is_null() {
+            return;
+        }
+
+        // A bag has room for only one word besides the pointer, so pack the layout into it.
+        assert!(
+            layout.size() <= usize::MAX >> ALIGN_BITS,
+            "layout too large to be deferred: {} bytes",
+            layout.size()
+        );
+        let packed = layout.size() << ALIGN_BITS | layout.align().trailing_zeros() as usize;
+
+        unsafe fn dealloc<T>(ptr: *mut T, packed: usize) {
+            #[cfg(feature = "debug_stats")]
+            stats::record_reclaimed::<T>(1);
+
+            let size = packed >> ALIGN_BITS;
+            let align = 1 << (packed & ((1 << ALIGN_BITS) - 1));
+            alloc::dealloc(ptr as *mut u8, Layout::from_size_align_unchecked(size, align));
+        }
+
+        self.defer_destroy(dealloc::<T>, object, packed);
+
+        #[cfg(feature = "debug_stats")]
+        stats::record_deferred::<T>(1);
+    }
+
+    /// Stashes away an object that will later be handed back to `recycle` instead of being freed.
+    ///
+    /// Once no pinned thread can hold a reference to the object anymore, it is turned back into a
+    /// `Box<T>` and passed to `recycle`, e.g. a function putting it into a pool of free objects.
+    /// Data structures with high churn can reuse their nodes this way instead of going through the
+    /// allocator for every insertion and removal.
+    ///
+    /// `recycle` is called on whichever thread happens to collect the object, possibly while it is
+    /// pinned, so it must not block for long. If it panics, the panic is caught and the object is
+    /// leaked.
+    ///
+    /// If `ptr` is null (regardless of the tag), this method does nothing.
+    ///
+    /// # Safety
+    ///
+    /// The object must have been allocated as a `Box<T>`, and no thread may be able to obtain a
+    /// new pointer to it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic, Owned, Ptr};
+    /// use std::sync::Mutex;
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// static POOL: Mutex<Vec<Box<u64>>> = Mutex::new(Vec::new());
+    ///
+    /// fn recycle(b: Box<u64>) {
+    ///     POOL.lock().unwrap().push(b);
+    /// }
+    ///
+    /// let a = Atomic::new(7u64);
+    /// epoch::pin(|scope| unsafe {
+    ///     let p = a.swap(Ptr::null(), SeqCst, scope);
+    ///     scope.defer_recycle(p, recycle);
+    /// });
+    ///
+    /// // Later, take a node from the pool if there is one.
+    /// let node = POOL.lock().unwrap().pop().map_or_else(|| Owned::new(0), Owned::from_box);
+    /// ```
+    pub unsafe fn defer_recycle<T: Send>(&self, ptr: Ptr<T>, recycle: fn(Box<T>)) {
+        let object = ptr.as_raw();
+
+        // There's nothing to recycle behind a null pointer.
+        if object.is_null() {
+            return;
+        }
+
+        unsafe fn recycle_box<T>(ptr: *mut T, recycle: usize) {
+            #[cfg(feature = "debug_stats")]
+            stats::record_reclaimed::<T>(1);
+
+            // The word next to the object holds the recycling function.
+            let recycle: fn(Box<T>) = mem::transmute(recycle);
+            recycle(Box::from_raw(ptr));
+        }
+
+        self.defer_destroy(recycle_box::<T>, object, recycle as usize);
+
+        #[cfg(feature = "debug_stats")]
+        stats::record_deferred::<T>(1);
+    }
+
+    /// Stashes away an object that will later be destroyed using `destroy`.
+    ///
+    /// The object at address `object` must not be null. The value `count` is passed to `destroy`
+    /// along with the object, and usually holds the number of elements in it.
+    unsafe fn defer_destroy<T>(
+        &self,
+        destroy: unsafe fn(*mut T, usize),
+        object: *const T,
+        count: usize,
+    ) {
+        loop {
+            // Get the thread-local bag.
+            let cell = &self.harness().bag;
+            let bag = cell.get();
+
+            // Try inserting the object into the bag.
+            if (*bag).try_insert(destroy, object, count) {
+                // Success! We're done.
+                break;
+            }
+
+            // Flush the garbage and create a new bag.
+            config::bag_full();
+            self.flush();
+
+            if garbage::object_count() > config::max_pending_garbage() {
+                self.reclaim_pending();
+            }
+        }
+    }
+
+    /// Makes an extra effort to reclaim garbage because too much of it is pending.
+    #[cold]
+    fn reclaim_pending(&self) {
+        /// Number of attempts at advancing the epoch and collecting garbage.
+        const ROUNDS: usize = 4;
+
+        let harness = self.harness();
+        let max = config::max_pending_garbage();
+
+        // Don't let the backoff skip collection in the future either.
+        harness.backoff.reset();
+
+        for _ in 0..ROUNDS {
+            let advanced = try_advance(self);
+            let destroyed = garbage::collect(self);
+
+            if garbage::object_count() <= max {
+                return;
+            }
+            if !advanced && destroyed == 0 {
+                break;
+            }
+        }
+
+        // The epoch is stalled. Give the threads holding it back a chance to make progress.
+        harness.backoff.yield_on_unpin.set(true);
+
+        if let Some(f) = config::garbage_stall_hook() {
+            // Walk the participants within this scope rather than pinning again: the thread may be
+            // exiting, or this may be the scope of a task harness.
+            let snapshot = snapshot(self);
+            let lagging = snapshot
+                .participants
+                .iter()
+                .filter(|p| p.is_pinned && p.epoch != snapshot.epoch)
+                .count();
+            f(garbage::object_count(), lagging);
+        }
+    }
+
+    /// Returns the number of objects that can still be deferred before the thread-local bag
+    /// becomes full.
+    ///
+    /// A producer of garbage can use this to throttle itself, e.g. by pacing deletions so that the
+    /// bag doesn't fill up too quickly. See also [`on_bag_full`].
+    ///
+    /// [`on_bag_full`]: fn.on_bag_full.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Owned};
+    ///
+    /// epoch::pin(|scope| unsafe {
+    ///     let remaining = scope.bag_remaining();
+    ///     scope.defer_free(Owned::new(1234).into_ptr(scope));
+    ///     assert!(scope.bag_remaining() < remaining || remaining == 0);
+    /// });
+    /// ```
+    pub fn bag_remaining(&self) -> usize {
+        unsafe { (*self.harness().bag.get()).remaining() }
     }
 
     /// Flushes the buffered thread-local garbage.
@@ -330,26 +1234,152 @@ impl Scope {
     ///
     /// [`defer_free`]: struct.Scope.html#method.defer_free
     pub fn flush(&self) {
+        self.flush_counted();
+    }
+
+    /// Flushes the buffered thread-local garbage and returns the number of objects destroyed by
+    /// the collection that follows.
+    fn flush_counted(&self) -> usize {
         unsafe {
             // Get the thread-local bag.
-            let cell = &*self.bag;
+            let cell = &self.harness().bag;
             let bag = cell.get();
 
             if !(*bag).is_empty() {
                 // The bag is full. We must replace it with a fresh one.
-                cell.set(Box::into_raw(Box::new(Bag::new())));
+                cell.set(Box::into_raw(garbage::alloc_bag()));
 
                 // Push the old bag into the garbage queue.
                 let bag = Box::from_raw(bag);
                 garbage::push(bag, self);
 
-                // Spare some cycles on garbage collection.
+                // Spare some cycles on garbage collection, unless the epoch has been stalled lately.
                 // Note: This may itself produce garbage and allocate new bags.
-                try_advance(self);
-                garbage::collect(self);
+                self.harness().reclaim(self)
+            } else {
+                0
             }
         }
     }
+
+    /// Runs `f` with a dedicated thread-local bag for deferred garbage.
+    ///
+    /// All objects deferred within `f` are inserted into a fresh bag, which gets pushed into the
+    /// global garbage queue as soon as `f` returns, regardless of how full it is. Afterwards the
+    /// previous bag is restored, so garbage deferred before the call is not flushed.
+    ///
+    /// This is useful for bounding the reclamation latency of a specific batch of garbage.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Owned};
+    ///
+    /// epoch::pin(|scope| {
+    ///     scope.with_isolated_bag(|scope| unsafe {
+    ///         let p = Owned::new(1234).into_ptr(scope);
+    ///         scope.defer_drop(p);
+    ///     });
+    /// });
+    /// ```
+    pub fn with_isolated_bag<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Scope) -> R
+    {
+        unsafe {
+            let cell = &self.harness().bag;
+            let previous = cell.replace(Box::into_raw(garbage::alloc_bag()));
+
+            // This will restore the previous bag even if `f` panics.
+            defer! {{
+                let isolated = Box::from_raw(cell.replace(previous));
+                if isolated.is_empty() {
+                    garbage::recycle_bag(isolated);
+                } else {
+                    garbage::push(isolated, self);
+                }
+            }}
+
+            f(self)
+        }
+    }
+
+    /// Returns a guard that calls `f` when it gets dropped.
+    ///
+    /// Unlike the `defer_*` methods, this doesn't wait for the epoch to advance: `f` is called
+    /// synchronously when the guard is dropped, which normally happens at the end of the closure
+    /// that received the scope, while the thread is still pinned, and even if the closure is left
+    /// by a panic. That makes it a good fit for cleanup that must happen together with the
+    /// critical section, e.g. releasing a lock taken during it.
+    ///
+    /// The guard borrows the scope, so it can't escape the pinned region. Guards are dropped in
+    /// reverse order of creation, like any other local variables. Forgetting a guard skips the
+    /// call.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch;
+    /// use std::sync::atomic::AtomicBool;
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let locked = AtomicBool::new(false);
+    ///
+    /// epoch::pin(|scope| {
+    ///     assert!(!locked.swap(true, SeqCst));
+    ///     let _guard = scope.with_scope_guard(|| locked.store(false, SeqCst));
+    ///
+    ///     assert!(locked.load(SeqCst));
+    /// });
+    /// assert!(!locked.load(SeqCst));
+    /// ```
+    pub fn with_scope_guard<'scope, F>(&'scope self, f: F) -> ScopeGuard<'scope, F>
+    where
+        F: FnOnce() + 'scope
+    {
+        ScopeGuard {
+            f: Some(f),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the harness of the thread that created this scope.
+    ///
+    /// In debug builds, this panics if the scope is unprotected, which catches attempts to defer
+    /// garbage without actually pinning the thread.
+    #[inline]
+    fn harness(&self) -> &Harness {
+        debug_assert!(
+            !self.harness.is_null(),
+            "garbage can be deferred only within a pinned scope, not an unprotected one"
+        );
+        unsafe { &*self.harness }
+    }
+}
+
+/// A guard that calls a function when dropped.
+///
+/// Created by [`Scope::with_scope_guard`].
+///
+/// [`Scope::with_scope_guard`]: struct.Scope.html#method.with_scope_guard
+#[must_use = "the function is called as soon as the guard is dropped"]
+pub struct ScopeGuard<'scope, F: FnOnce()> {
+    f: Option<F>,
+    _marker: PhantomData<&'scope Scope>,
+}
+
+impl<'scope, F: FnOnce()> Drop for ScopeGuard<'scope, F> {
+    fn drop(&mut self) {
+        if let Some(f) = self.f.take() {
+            f();
+        }
+    }
+}
+
+impl<'scope, F: FnOnce()> fmt::Debug for ScopeGuard<'scope, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ScopeGuard").finish()
+    }
 }
 
 /// Scopes the current thread.
@@ -374,115 +1404,1198 @@ pub fn pin<F, T>(f: F) -> T
 where
     F: FnOnce(&Scope) -> T
 {
-    /// Number of pinnings after which a thread will collect some global garbage.
-    const PINS_BETWEEN_COLLECT: usize = 128;
-
-    HARNESS.with(|harness| {
-        let thread = unsafe { &*harness.thread };
-        let pin = &Scope { bag: &harness.bag };
-
-        let was_pinned = harness.is_pinned.get();
-        if !was_pinned {
-            // Scope the thread.
-            harness.is_pinned.set(true);
-            thread.set_pinned(pin);
-
-            // Increment the pin counter.
-            let count = harness.pin_count.get();
-            harness.pin_count.set(count.wrapping_add(1));
+    HARNESS.with(|harness| harness.pinned(f))
+}
 
-            // If the counter progressed enough, try advancing the epoch and collecting garbage.
-            if count % PINS_BETWEEN_COLLECT == 0 {
-                try_advance(pin);
-                garbage::collect(pin);
-            }
-        }
+/// Pins the current thread, but only if it is already registered.
+///
+/// This behaves like [`pin`], except that it never registers the thread. Registration happens the
+/// first time a thread gets pinned: it allocates an entry in the list of participating threads and
+/// installs it with a compare-and-swap. That is undesirable in some contexts, e.g. threads that
+/// must not allocate.
+///
+/// Returns `None` without calling `f` if the current thread has never been pinned before, or if
+/// it is exiting and has already been unregistered. Otherwise returns `Some` with the result of
+/// `f`. Pinning through a [`TaskHarness`] doesn't register the current thread.
+///
+/// [`pin`]: fn.pin.html
+/// [`TaskHarness`]: struct.TaskHarness.html
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+/// use std::thread;
+///
+/// thread::spawn(|| {
+///     assert_eq!(epoch::try_pin(|_| 1), None);
+///     epoch::pin(|_| ());
+///     assert_eq!(epoch::try_pin(|_| 1), Some(1));
+/// }).join().unwrap();
+/// ```
+pub fn try_pin<F, T>(f: F) -> Option<T>
+where
+    F: FnOnce(&Scope) -> T
+{
+    if !REGISTERED.with(|r| r.get()) {
+        return None;
+    }
+    HARNESS.try_with(|harness| {
+        let was_pinned = harness.pin();
 
         // This will unpin the thread even if `f` panics.
         defer! {
             if !was_pinned {
-                // Unpin the thread.
-                thread.set_unpinned();
-                harness.is_pinned.set(false);
+                harness.unpin();
             }
         }
 
-        f(pin)
-    })
+        f(&Scope { harness })
+    }).ok()
 }
 
-/// Creates an unprotected scope without pinning the current thread.
-pub unsafe fn unprotected<F, T>(f: F) -> T
+/// Calls `f` with the scope of the current thread if it is already pinned.
+///
+/// This is useful in callbacks that can't receive the `Scope` through their arguments, e.g. ones
+/// called by foreign code that was in turn called from within [`pin`]. The callback joins the pin
+/// that is already active instead of pinning the thread anew, and can't accidentally pin it when
+/// it's not supposed to be pinned.
+///
+/// Returns `None` without calling `f` if the current thread is not pinned. Unlike [`pin`], this
+/// never registers the thread.
+///
+/// # Panics
+///
+/// Panics if the thread's entry in the list of participants doesn't announce it as pinned, which
+/// would mean that the pin is not genuine.
+///
+/// [`pin`]: fn.pin.html
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+///
+/// // A callback that might be called from foreign code.
+/// extern "C" fn callback() -> bool {
+///     epoch::with_current_pin(|_scope| ()).is_some()
+/// }
+///
+/// assert!(!callback());
+/// assert!(epoch::pin(|_| callback()));
+/// ```
+pub fn with_current_pin<F, T>(f: F) -> Option<T>
 where
     F: FnOnce(&Scope) -> T
 {
-    let pin = &Scope { bag: ::std::ptr::null() };
-    f(pin)
+    if !REGISTERED.with(|r| r.get()) {
+        return None;
+    }
+    HARNESS.try_with(|harness| {
+        if !harness.is_pinned.get() {
+            return None;
+        }
+
+        let thread = unsafe { &*harness.thread };
+        assert!(
+            thread.state.load(Relaxed) & 1 == 1,
+            "the current thread is marked as pinned, but its participant entry isn't"
+        );
+        Some(f(&Scope { harness }))
+    }).ok().and_then(|r| r)
 }
 
-/// Returns `true` if the current thread is pinned.
-#[inline]
-pub fn is_pinned() -> bool {
-    HARNESS.with(|harness| harness.is_pinned.get())
+/// A pinned scope for data structures whose objects borrow from the environment.
+///
+/// Objects deferred through a [`Scope`] may be destroyed long after the function that deferred
+/// them has returned, so they must not borrow anything that might not live that long. That
+/// effectively requires objects reclaimed by the epoch GC to be `'static`.
+///
+/// A `BorrowedScope<'env>` is for data structures that never reclaim objects while in use, e.g.
+/// lock-free structures shared by scoped threads, whose nodes hold references into the stack of
+/// the spawning function. Objects reachable through such a structure may borrow data living for
+/// `'env`, and the following operations on [`Atomic`]s pointing to them are available:
+///
+/// * [`load`], [`swap`], [`compare_and_swap`], and [`compare_and_swap_owned`], which return
+///   pointers that can't outlive the borrow of the scope, nor the borrow of the `Atomic` they were
+///   read from. Since the objects must outlive `'env`, which in turn outlives the scope, a pointer
+///   can't outlive the data its object borrows either.
+///
+/// Deferring destruction is not available at all. Removed objects must instead be destroyed by
+/// the owner of the data structure once no thread can access it anymore, e.g. after all scoped
+/// threads sharing it have been joined, typically by traversing it within [`unprotected`].
+///
+/// Created by [`pin_borrowed`]. The current thread stays pinned for the lifetime of the scope.
+///
+/// # Examples
+///
+/// ```compile_fail
+/// use coco::epoch::{self, Owned};
+///
+/// epoch::pin_borrowed(|scope| unsafe {
+///     // Deferral is not available.
+///     scope.defer_drop(Owned::new(1).into_ptr(scope));
+/// });
+/// ```
+///
+/// Pointers can't escape the pinned region:
+///
+/// ```compile_fail
+/// use coco::epoch::{self, Atomic};
+/// use std::sync::atomic::Ordering::Acquire;
+///
+/// let x = 1;
+/// let a = Atomic::new(&x);
+/// let p = epoch::pin_borrowed(|scope| scope.load(&a, Acquire));
+/// ```
+///
+/// Nor can they outlive the `Atomic` they were loaded from:
+///
+/// ```compile_fail
+/// use coco::epoch::{self, Atomic};
+/// use std::sync::atomic::Ordering::Acquire;
+///
+/// let x = 1;
+/// epoch::pin_borrowed(|scope| {
+///     let p = {
+///         let a = Atomic::new(&x);
+///         scope.load(&a, Acquire)
+///     };
+///     unsafe { p.deref(); }
+/// });
+/// ```
+///
+/// [`Scope`]: struct.Scope.html
+/// [`Atomic`]: struct.Atomic.html
+/// [`load`]: struct.BorrowedScope.html#method.load
+/// [`swap`]: struct.BorrowedScope.html#method.swap
+/// [`compare_and_swap`]: struct.BorrowedScope.html#method.compare_and_swap
+/// [`compare_and_swap_owned`]: struct.BorrowedScope.html#method.compare_and_swap_owned
+/// [`unprotected`]: fn.unprotected.html
+/// [`pin_borrowed`]: fn.pin_borrowed.html
+#[derive(Debug)]
+pub struct BorrowedScope<'env> {
+    /// The scope of the pinned thread, which must not be handed out.
+    scope: Scope,
+    /// Makes `'env` invariant, so that it can't be shortened to accept shorter-lived objects.
+    _marker: PhantomData<&'env mut &'env ()>,
 }
 
-#[cfg(test)]
-mod tests {
-    use std::thread;
-    use std::sync::atomic::Ordering::SeqCst;
+impl<'env> BorrowedScope<'env> {
+    /// Loads a `Ptr` from `atomic`, just like [`Atomic::load`].
+    ///
+    /// [`Atomic::load`]: struct.Atomic.html#method.load
+    pub fn load<'scope, T: 'env>(&'scope self, atomic: &'scope Atomic<T>, ord: Ordering)
+                                 -> Ptr<'scope, T> {
+        atomic.load(ord, &self.scope)
+    }
 
-    use epoch::{self, Owned};
-    use epoch::garbage::EPOCH;
-    use epoch::thread::{HARNESS, try_advance};
+    /// Stores `new` into `atomic` and returns the previous `Ptr`, just like [`Atomic::swap`].
+    ///
+    /// [`Atomic::swap`]: struct.Atomic.html#method.swap
+    #[must_use = "the returned pointer may need to be unlinked and reclaimed"]
+    pub fn swap<'scope, T: 'env>(
+        &'scope self,
+        atomic: &'scope Atomic<T>,
+        new: Ptr<T>,
+        ord: Ordering,
+    ) -> Ptr<'scope, T> {
+        atomic.swap(new, ord, &self.scope)
+    }
 
-    #[test]
-    fn pin_reentrant() {
-        assert!(!epoch::is_pinned());
-        epoch::pin(|_| {
-            assert!(epoch::is_pinned());
-            epoch::pin(|_| {
-                assert!(epoch::is_pinned());
-            });
-            assert!(epoch::is_pinned());
-        });
-        assert!(!epoch::is_pinned());
+    /// Stores `new` into `atomic` if it contains `current`, just like
+    /// [`Atomic::compare_and_swap`].
+    ///
+    /// [`Atomic::compare_and_swap`]: struct.Atomic.html#method.compare_and_swap
+    pub fn compare_and_swap<'scope, T: 'env>(
+        &'scope self,
+        atomic: &'scope Atomic<T>,
+        current: Ptr<T>,
+        new: Ptr<T>,
+        ord: Ordering,
+    ) -> Result<(), Ptr<'scope, T>> {
+        atomic.compare_and_swap(current, new, ord, &self.scope)
     }
 
-    #[test]
-    fn flush_local_garbage() {
-        for _ in 0..100 {
-            epoch::pin(|scope| {
-                unsafe {
-                    let a = Owned::new(7).into_ptr(scope);
-                    scope.defer_free(a);
+    /// Stores `new` into `atomic` if it contains `current`, just like
+    /// [`Atomic::compare_and_swap_owned`].
+    ///
+    /// [`Atomic::compare_and_swap_owned`]: struct.Atomic.html#method.compare_and_swap_owned
+    pub fn compare_and_swap_owned<'scope, T: 'env>(
+        &'scope self,
+        atomic: &'scope Atomic<T>,
+        current: Ptr<T>,
+        new: Owned<T>,
+        ord: Ordering,
+    ) -> Result<Ptr<'scope, T>, (Ptr<'scope, T>, Owned<T>)> {
+        atomic.compare_and_swap_owned(current, new, ord, &self.scope)
+    }
+}
 
-                    HARNESS.with(|h| {
-                        assert!(!(*h.bag.get()).is_empty());
+/// Pins the current thread for accessing data structures whose objects borrow from `'env`.
+///
+/// This behaves like [`pin`], except that `f` gets a [`BorrowedScope`], which allows loading and
+/// updating atomic pointers to objects that aren't `'static`, but doesn't allow deferring their
+/// destruction.
+///
+/// [`pin`]: fn.pin.html
+/// [`BorrowedScope`]: struct.BorrowedScope.html
+///
+/// # Examples
+///
+/// A stack shared by scoped threads, whose nodes borrow strings from the enclosing function:
+///
+/// ```
+/// use coco::epoch::{self, Atomic, Owned};
+/// use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed};
+/// use std::thread;
+///
+/// struct Node<'a> {
+///     name: &'a str,
+///     next: Atomic<Node<'a>>,
+/// }
+///
+/// let names = vec!["a".to_string(), "b".to_string()];
+/// let head = Atomic::null();
+///
+/// thread::scope(|s| {
+///     for name in &names {
+///         let head = &head;
+///         s.spawn(move || {
+///             epoch::pin_borrowed(|scope| {
+///                 let mut node = Owned::new(Node { name, next: Atomic::null() });
+///                 loop {
+///                     let h = scope.load(&head, Acquire);
+///                     node.next.store(h, Relaxed);
+///                     match scope.compare_and_swap_owned(&head, h, node, AcqRel) {
+///                         Ok(_) => break,
+///                         Err((_, n)) => node = n,
+///                     }
+///                 }
+///             })
+///         });
+///     }
+/// });
+///
+/// // All threads have been joined, so the nodes can be destroyed right away.
+/// unsafe {
+///     epoch::unprotected(|scope| {
+///         let mut count = 0;
+///         let mut curr = head.load(Relaxed, scope);
+///         while !curr.is_null() {
+///             let node = curr.into_box();
+///             assert!(node.name == "a" || node.name == "b");
+///             curr = node.next.load(Relaxed, scope);
+///             count += 1;
+///         }
+///         assert_eq!(count, 2);
+///     })
+/// }
+/// ```
+pub fn pin_borrowed<'env, F, R>(f: F) -> R
+where
+    F: FnOnce(&BorrowedScope<'env>) -> R,
+{
+    pin(|scope| {
+        let scope = BorrowedScope {
+            scope: Scope { harness: scope.harness },
+            _marker: PhantomData,
+        };
+        f(&scope)
+    })
+}
 
-                        while !(*h.bag.get()).is_empty() {
-                            scope.flush();
-                        }
-                    });
-                }
-            });
+/// Pins the current thread and flushes the local garbage just before unpinning.
+///
+/// This behaves like [`pin`], except that when the provided function returns, the thread-local
+/// bag is pushed into the global queue as if by [`Scope::flush`]. Garbage produced by the function
+/// is then handed off immediately instead of lingering in the bag until it fills up.
+///
+/// This is useful in thread pools, where a worker thread might process a single task and then
+/// sleep indefinitely. The bag is flushed even if the thread was already pinned, and nested calls
+/// to [`pin`] inside the function work as usual.
+///
+/// [`pin`]: fn.pin.html
+/// [`Scope::flush`]: struct.Scope.html#method.flush
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch::{self, Owned};
+///
+/// epoch::pin_flushing(|scope| unsafe {
+///     let p = Owned::new(1234).into_ptr(scope);
+///     scope.defer_free(p);
+/// });
+/// ```
+pub fn pin_flushing<F, T>(f: F) -> T
+where
+    F: FnOnce(&Scope) -> T
+{
+    pin(|scope| {
+        // This will flush the bag even if `f` panics.
+        defer! {
+            scope.flush()
         }
-    }
 
-    #[test]
-    fn garbage_buffering() {
-        HARNESS.with(|h| unsafe {
-            while !(*h.bag.get()).is_empty() {
-                epoch::pin(|scope| scope.flush());
-            }
+        f(scope)
+    })
+}
 
-            epoch::pin(|scope| {
-                for _ in 0..10 {
-                    let a = Owned::new(7).into_ptr(scope);
-                    scope.defer_free(a);
-                }
-                assert!(!(*h.bag.get()).is_empty());
+/// Pins the current thread and checks that the provided function returns within `max`.
+///
+/// This behaves like [`pin`], except that in debug builds the function is timed, and if it keeps
+/// the thread pinned for longer than `max`, a warning naming the location of the over-long pinned
+/// section is printed to standard error and the overrun is counted in [`deadline_overruns`]. This
+/// helps catching blocking calls accidentally made while pinned, which stall garbage collection
+/// for all threads. Release builds skip the timing altogether.
+///
+/// [`pin`]: fn.pin.html
+/// [`deadline_overruns`]: fn.deadline_overruns.html
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch::{self, Atomic};
+/// use std::sync::atomic::Ordering::SeqCst;
+/// use std::time::Duration;
+///
+/// let a = Atomic::new(7);
+/// epoch::pin_with_deadline(Duration::from_millis(100), |scope| {
+///     let p = a.load(SeqCst, scope);
+/// });
+/// ```
+#[track_caller]
+pub fn pin_with_deadline<F, T>(max: Duration, f: F) -> T
+where
+    F: FnOnce(&Scope) -> T
+{
+    if cfg!(debug_assertions) {
+        let location = Location::caller();
+        let (result, elapsed) = pin(|scope| {
+            let start = Instant::now();
+            let result = f(scope);
+            (result, start.elapsed())
+        });
+        if elapsed > max {
+            DEADLINE_OVERRUNS.fetch_add(1, Relaxed);
+            eprintln!(
+                "warning: pinned section at {} took {:?}, longer than the deadline of {:?}",
+                location,
+                elapsed,
+                max
+            );
+        }
+        result
+    } else {
+        pin(f)
+    }
+}
+
+/// Number of pinned sections that have overrun the deadline given to `pin_with_deadline`.
+static DEADLINE_OVERRUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of times a function passed to [`pin_with_deadline`] has kept the thread
+/// pinned for longer than its deadline.
+///
+/// Overruns are detected only in debug builds, so in release builds this is always zero.
+///
+/// [`pin_with_deadline`]: fn.pin_with_deadline.html
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+/// use std::time::Duration;
+///
+/// let before = epoch::deadline_overruns();
+/// epoch::pin_with_deadline(Duration::from_secs(60), |_| ());
+/// assert_eq!(epoch::deadline_overruns(), before);
+/// ```
+pub fn deadline_overruns() -> usize {
+    DEADLINE_OVERRUNS.load(Relaxed)
+}
+
+/// A handle to an explicitly registered participant in garbage collection.
+///
+/// Normally every thread is registered automatically the first time it is pinned, and unregistered
+/// when it exits. A `LocalHandle` is a registration that is instead owned by the user: it has its
+/// own entry in the list of participants and its own bag of garbage, and gets unregistered when
+/// dropped.
+///
+/// This is useful in runtimes where a thread-local registration is not a good fit, e.g. when
+/// the set of threads is dynamic or when pinning happens in foreign callbacks. A handle is not
+/// `Send`, so it must be created, used, and dropped on the same thread.
+///
+/// All handles participate in the same, global garbage collector as threads pinned through
+/// [`pin`]. Objects loaded through one scope may therefore be deferred through any other scope,
+/// regardless of whether it belongs to a handle, a [`TaskHarness`], or the current thread.
+///
+/// Handles are created by [`register`].
+///
+/// [`register`]: fn.register.html
+/// [`pin`]: fn.pin.html
+/// [`TaskHarness`]: struct.TaskHarness.html
+pub struct LocalHandle {
+    harness: Box<Harness>,
+}
+
+impl LocalHandle {
+    /// Pins the handle.
+    ///
+    /// This works just like [`pin`], except that the pin is announced through this handle's
+    /// entry, and deferred garbage goes into this handle's bag. Pinning is reentrant, and pinning
+    /// a handle is independent of pinning the current thread through [`pin`].
+    ///
+    /// [`pin`]: fn.pin.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Atomic};
+    /// use std::sync::atomic::Ordering::SeqCst;
+    ///
+    /// let a = Atomic::new(1234);
+    /// let handle = epoch::register();
+    ///
+    /// handle.pin(|scope| {
+    ///     let p = a.load(SeqCst, scope);
+    ///     assert_eq!(unsafe { p.as_ref() }, Some(&1234));
+    /// });
+    /// ```
+    pub fn pin<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&Scope) -> T
+    {
+        self.harness.pinned(f)
+    }
+
+    /// Destroys as much garbage as possible right now, pinning through this handle.
+    ///
+    /// This works just like [`collect_all`], except that this handle's bag is flushed instead of
+    /// the current thread's, and the current thread doesn't get registered.
+    ///
+    /// [`collect_all`]: fn.collect_all.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Owned};
+    ///
+    /// let handle = epoch::register();
+    /// handle.pin(|scope| unsafe {
+    ///     scope.defer_drop(Owned::new(1234).into_ptr(scope));
+    /// });
+    ///
+    /// let (destroyed, is_empty) = handle.collect_all();
+    /// ```
+    pub fn collect_all(&self) -> (usize, bool) {
+        self.harness.collect_all()
+    }
+
+    /// Returns `true` if the handle is pinned.
+    pub fn is_pinned(&self) -> bool {
+        self.harness.is_pinned.get()
+    }
+}
+
+impl fmt::Debug for LocalHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LocalHandle {{ ... }}")
+    }
+}
+
+/// Registers a new participant in garbage collection and returns a handle to it.
+///
+/// The participant is unregistered, and its remaining garbage moved into the global queue, when
+/// the handle is dropped.
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+///
+/// let handle = epoch::register();
+/// handle.pin(|scope| scope.flush());
+/// drop(handle);
+/// ```
+pub fn register() -> LocalHandle {
+    LocalHandle {
+        harness: Box::new(Harness::new()),
+    }
+}
+
+/// A participant in garbage collection owned by a task rather than a thread.
+///
+/// Cooperative runtimes may run many tasks on a single executor thread, and a task may be resumed
+/// on a different thread than the one it was suspended on. Thread-local registration doesn't fit
+/// that model, so a runtime can instead create a `TaskHarness` for every task. It has its own
+/// entry in the list of participants and its own bag of garbage, exactly like a thread, and gets
+/// unregistered when dropped.
+///
+/// Unlike [`LocalHandle`], a task harness is `Send`. Pinning requires a mutable reference, so a
+/// harness can't be pinned by two threads at the same time, and the scope can't outlive the pin.
+///
+/// [`LocalHandle`]: struct.LocalHandle.html
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch::{Atomic, TaskHarness};
+/// use std::sync::atomic::Ordering::SeqCst;
+/// use std::thread;
+///
+/// let a = Atomic::new(1234);
+/// let mut task = TaskHarness::new();
+///
+/// task.pin(|scope| unsafe { scope.defer_free(a.swap_null(SeqCst, scope)) });
+///
+/// // The task is resumed on another thread.
+/// thread::spawn(move || {
+///     task.pin(|scope| scope.flush());
+/// }).join().unwrap();
+/// ```
+pub struct TaskHarness {
+    /// The harness of this participant.
+    harness: Box<Harness>,
+}
+
+unsafe impl Send for TaskHarness {}
+
+impl TaskHarness {
+    /// Registers a new participant for a task.
+    pub fn new() -> TaskHarness {
+        TaskHarness {
+            harness: Box::new(Harness::new()),
+        }
+    }
+
+    /// Pins the task, executes a function, and unpins the task.
+    ///
+    /// The task stays pinned while `f` is running, exactly like a thread would within [`pin`].
+    ///
+    /// [`pin`]: fn.pin.html
+    pub fn pin<F, T>(&mut self, f: F) -> T
+    where
+        F: FnOnce(&Scope) -> T
+    {
+        let harness = &*self.harness;
+        harness.pin();
+
+        // This will unpin the task even if `f` panics.
+        defer! {
+            harness.unpin()
+        }
+
+        f(&Scope { harness })
+    }
+}
+
+impl Default for TaskHarness {
+    fn default() -> Self {
+        TaskHarness::new()
+    }
+}
+
+impl fmt::Debug for TaskHarness {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TaskHarness {{ ... }}")
+    }
+}
+
+/// A token proving that the current thread was pinned by [`pin_raw`].
+///
+/// The token must be passed back to [`unpin_raw`] in order to unpin the thread.
+///
+/// A token dereferences to a [`Scope`], so it can be passed wherever a scope is expected, e.g. to
+/// [`Atomic::load`]. Loaded pointers borrow the token, so they cannot be used after it has been
+/// unpinned:
+///
+/// ```compile_fail
+/// use coco::epoch::{self, Atomic};
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// let a = Atomic::new(1234);
+/// unsafe {
+///     let token = epoch::pin_raw();
+///     let p = a.load(SeqCst, &token);
+///     epoch::unpin_raw(token);
+///     println!("{:?}", p.as_ref());
+/// }
+/// ```
+///
+/// [`pin_raw`]: fn.pin_raw.html
+/// [`unpin_raw`]: fn.unpin_raw.html
+/// [`Scope`]: struct.Scope.html
+/// [`Atomic::load`]: struct.Atomic.html#method.load
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch::{self, Atomic};
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// let a = Atomic::new(1234);
+/// unsafe {
+///     let token = epoch::pin_raw();
+///     let p = a.load(SeqCst, &token);
+///     assert_eq!(p.as_ref(), Some(&1234));
+///     epoch::unpin_raw(token);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct PinToken {
+    /// The scope that is valid while the token is alive.
+    scope: Scope,
+    /// Whether the thread was already pinned when the token was created.
+    was_pinned: bool,
+}
+
+impl PinToken {
+    /// Returns the scope belonging to this pin.
+    pub fn scope(&self) -> &Scope {
+        &self.scope
+    }
+}
+
+/// A token dereferences to its scope, so garbage can be deferred directly through it, just like
+/// through the scope passed to the closure in [`pin`]. Pointers loaded this way borrow the token
+/// and therefore cannot outlive it.
+///
+/// [`pin`]: fn.pin.html
+impl Deref for PinToken {
+    type Target = Scope;
+
+    fn deref(&self) -> &Scope {
+        &self.scope
+    }
+}
+
+/// Pins the current thread without setting up a guard that unpins it.
+///
+/// This is a low-level building block for custom guard types that want to avoid the overhead of
+/// running a closure within [`pin`]. Most users should simply call [`pin`] instead.
+///
+/// # Safety
+///
+/// The returned token must be passed to [`unpin_raw`] on the same thread. If there are several
+/// live tokens, they must be unpinned in reverse order of creation. Forgetting to unpin a token
+/// leaks the pin, which keeps the thread pinned forever and blocks all garbage collection.
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch;
+///
+/// unsafe {
+///     let token = epoch::pin_raw();
+///     assert!(epoch::is_pinned());
+///     token.scope().flush();
+///     epoch::unpin_raw(token);
+/// }
+/// assert!(!epoch::is_pinned());
+/// ```
+///
+/// [`pin`]: fn.pin.html
+/// [`unpin_raw`]: fn.unpin_raw.html
+pub unsafe fn pin_raw() -> PinToken {
+    HARNESS.with(|harness| {
+        PinToken {
+            was_pinned: harness.pin(),
+            scope: Scope { harness },
+        }
+    })
+}
+
+/// Unpins the current thread pinned by [`pin_raw`].
+///
+/// # Safety
+///
+/// The token must have been created by [`pin_raw`] on the current thread, and all tokens created
+/// after it must already be unpinned.
+///
+/// [`pin_raw`]: fn.pin_raw.html
+pub unsafe fn unpin_raw(token: PinToken) {
+    if !token.was_pinned {
+        (*token.scope.harness).unpin();
+    }
+}
+
+/// Pushes the local bag into the global queue without collecting any garbage.
+#[cfg(feature = "test_util")]
+pub fn push_bag(scope: &Scope) {
+    let cell = &scope.harness().bag;
+    let bag = unsafe { Box::from_raw(cell.replace(Box::into_raw(garbage::alloc_bag()))) };
+    if bag.is_empty() {
+        garbage::recycle_bag(bag);
+    } else {
+        garbage::push(bag, scope);
+    }
+}
+
+/// Returns the epoch the current thread is pinned in.
+pub fn pinned_epoch(scope: &Scope) -> usize {
+    // Only this thread writes its own state, so there is nothing to synchronize with.
+    let thread = unsafe { &*scope.harness().thread };
+    thread.state.load(Relaxed) & !1
+}
+
+/// Returns `true` if an object that was reachable while some thread was pinned in `epoch` can't be
+/// destroyed for as long as the current thread stays pinned.
+///
+/// Such an object gets unlinked in `epoch` at the earliest, and is destroyed only after the global
+/// epoch has advanced twice past that. While the current thread is pinned, the global epoch can
+/// advance at most once past the epoch it's pinned in. The object is therefore safe if the thread
+/// is pinned in `epoch` or the one just before it.
+pub fn protects_since(scope: &Scope, epoch: usize) -> bool {
+    epoch.wrapping_sub(pinned_epoch(scope)) <= EPOCH_STEP
+}
+
+/// Defers reclamation of a bag that was unlinked from the garbage queue.
+///
+/// Instead of being freed, the bag will be put into the free list of the thread that destroys
+/// it, so that it can be reused.
+pub unsafe fn defer_bag(scope: &Scope, bag: Ptr<Bag>) {
+    unsafe fn recycle(ptr: *mut Bag, _count: usize) {
+        #[cfg(feature = "debug_stats")]
+        stats::record_reclaimed::<Bag>(1);
+
+        garbage::recycle_bag(Box::from_raw(ptr));
+    }
+
+    if !bag.is_null() {
+        scope.defer_destroy(recycle, bag.as_raw(), 1);
+
+        #[cfg(feature = "debug_stats")]
+        stats::record_deferred::<Bag>(1);
+    }
+}
+
+/// Creates an unprotected scope without pinning the current thread.
+pub unsafe fn unprotected<F, T>(f: F) -> T
+where
+    F: FnOnce(&Scope) -> T
+{
+    let pin = &Scope { harness: ::std::ptr::null() };
+    f(pin)
+}
+
+/// Destroys as much garbage as possible right now.
+///
+/// The local garbage of the current thread is flushed into the global queue, and then the global
+/// epoch is repeatedly advanced while collecting garbage from the queue, until the queue becomes
+/// empty or no more progress can be made. Progress stops when some other thread stays pinned in
+/// an old epoch, which prevents any further epoch advancement.
+///
+/// Returns the number of destroyed objects and whether the global queue is empty.
+///
+/// This is useful for reclaiming memory at shutdown or in tests that check for leaks.
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch::{self, Owned};
+///
+/// epoch::pin(|scope| unsafe {
+///     scope.defer_drop(Owned::new(1234).into_ptr(scope));
+/// });
+///
+/// let (destroyed, is_empty) = epoch::collect_all();
+/// ```
+pub fn collect_all() -> (usize, bool) {
+    HARNESS.with(|harness| harness.collect_all())
+}
+
+/// Destroys all garbage right now, provided that no other thread is pinned.
+///
+/// If no thread other than the current one is pinned, nothing can hold the global epoch back. In
+/// that case the local garbage is flushed, and the epoch is advanced enough times for the whole
+/// global queue to be drained. Unlike [`collect_all`], this doesn't wait for pinned threads to make
+/// progress: if another thread is pinned, `Err(())` is returned right away.
+///
+/// Returns the number of destroyed objects. Garbage deferred by other threads while this function
+/// is running might be left over.
+///
+/// This is useful for deterministic teardown, e.g. at the end of the program when all other
+/// threads have finished.
+///
+/// # Panics
+///
+/// Panics if the current thread is pinned, since the epoch couldn't advance.
+///
+/// [`collect_all`]: fn.collect_all.html
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch::{self, Owned};
+///
+/// epoch::pin(|scope| unsafe {
+///     scope.defer_drop(Owned::new(1234).into_ptr(scope));
+/// });
+///
+/// if let Ok(destroyed) = epoch::try_reclaim_all_if_quiescent() {
+///     println!("destroyed {} objects", destroyed);
+/// }
+/// ```
+// Failure carries no information beyond the fact that some thread was pinned.
+#[allow(clippy::result_unit_err)]
+pub fn try_reclaim_all_if_quiescent() -> Result<usize, ()> {
+    /// Number of rounds, each advancing the epoch once. Two advancements make any garbage in the
+    /// queue reclaimable, and another one takes care of garbage produced by collection itself.
+    const ROUNDS: usize = 3;
+
+    assert!(!is_pinned(), "cannot reclaim all garbage while the thread is pinned");
+
+    let own = HARNESS.with(|h| h.thread);
+    let mut destroyed = 0;
+
+    for _ in 0..ROUNDS {
+        destroyed += pin(|scope| {
+            // Check that no other participant is pinned.
+            let mut curr = participants().load(Acquire, scope);
+            while let Some(c) = unsafe { curr.as_ref() } {
+                let (succ, exited) = c.next.load_marked(0, Acquire, scope);
+                let pinned = c.state.load(SeqCst) & 1 == 1;
+                if pinned && !exited && !ptr::eq(c, own) {
+                    return Err(());
+                }
+                curr = succ.with_tag(0);
+            }
+
+            scope.flush();
+            try_advance(scope);
+
+            let mut count = 0;
+            loop {
+                let n = garbage::collect(scope);
+                count += n;
+                if n == 0 || garbage::is_empty(scope) {
+                    return Ok(count);
+                }
+            }
+        })?;
+    }
+    Ok(destroyed)
+}
+
+/// Waits until all garbage deferred so far by the current thread becomes reclaimable.
+///
+/// The local garbage of the current thread is flushed into the global queue, and then this
+/// function blocks until the global epoch advances twice. After that, any object that was deferred
+/// before the call can be safely destroyed, and every pinned thread has observed a newer epoch.
+///
+/// This is a quiescence barrier useful at phase boundaries of fork-join workloads. It yields to
+/// the scheduler while waiting, but if some other thread stays pinned for a long time, it will
+/// block for just as long.
+///
+/// # Panics
+///
+/// Panics if the current thread is pinned, since the barrier could never be passed.
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch::{self, Atomic, Owned};
+/// use std::sync::atomic::Ordering::SeqCst;
+///
+/// let a = Atomic::new(1234);
+///
+/// epoch::pin(|scope| unsafe {
+///     let p = a.swap(Owned::new(5678).into_ptr(scope), SeqCst, scope);
+///     scope.defer_free(p);
+/// });
+///
+/// // The old object is no longer reachable by any thread.
+/// epoch::barrier();
+/// # epoch::pin(|scope| unsafe { scope.defer_free(a.load(SeqCst, scope)) });
+/// ```
+pub fn barrier() {
+    /// The difference between two epochs that are two advancements apart.
+    const TWO_ADVANCEMENTS: usize = 4;
+
+    assert!(!is_pinned(), "cannot pass an epoch barrier while the thread is pinned");
+
+    let start = pin(|scope| {
+        scope.flush();
+        EPOCH.load(SeqCst)
+    });
+
+    loop {
+        let epoch = pin(|scope| {
+            try_advance(scope);
+            EPOCH.load(SeqCst)
+        });
+
+        if epoch.wrapping_sub(start) >= TWO_ADVANCEMENTS {
+            break;
+        }
+        thread::yield_now();
+    }
+}
+
+/// Returns `true` if the current thread is pinned.
+#[inline]
+pub fn is_pinned() -> bool {
+    HARNESS.with(|harness| harness.is_pinned.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::panic;
+    use std::ptr;
+    use std::thread;
+    use std::time::Duration;
+    use std::sync::{Arc, Barrier, Mutex, mpsc};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::{AcqRel, Acquire, SeqCst};
+
+    use epoch::{self, Atomic, Owned, Ptr, Scope, TaskHarness};
+    use epoch::config;
+    use epoch::garbage::EPOCH;
+    use epoch::thread::{HARNESS, REGISTERED, Thread, alloc_thread, participants, recycle_thread,
+                        try_advance};
+
+    /// Runs `f` while another thread stays pinned, so that the epoch gets stalled.
+    fn with_stalled_epoch<F: FnOnce()>(f: F) {
+        /// Unpins the other thread when dropped, even if `f` panics.
+        struct Release(mpsc::Sender<()>);
+
+        impl Drop for Release {
+            fn drop(&mut self) {
+                let _ = self.0.send(());
+            }
+        }
+
+        let (pinned_tx, pinned_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+
+        let t = thread::spawn(move || {
+            epoch::pin(|_| {
+                pinned_tx.send(()).unwrap();
+                done_rx.recv().unwrap();
+            })
+        });
+        pinned_rx.recv().unwrap();
+
+        let release = Release(done_tx);
+        f();
+        drop(release);
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn pin_reentrant() {
+        assert!(!epoch::is_pinned());
+        epoch::pin(|_| {
+            assert!(epoch::is_pinned());
+            epoch::pin(|_| {
+                assert!(epoch::is_pinned());
             });
+            assert!(epoch::is_pinned());
+        });
+        assert!(!epoch::is_pinned());
+    }
+
+    #[test]
+    fn pin_raw_reentrant() {
+        assert!(!epoch::is_pinned());
+        unsafe {
+            let outer = epoch::pin_raw();
+            assert!(epoch::is_pinned());
+
+            epoch::pin(|_| {
+                let inner = epoch::pin_raw();
+                epoch::unpin_raw(inner);
+                assert!(epoch::is_pinned());
+            });
+
+            assert!(epoch::is_pinned());
+            epoch::unpin_raw(outer);
+        }
+        assert!(!epoch::is_pinned());
+    }
+
+    #[test]
+    fn defer_through_pin_token() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Elem(usize);
+
+        impl Drop for Elem {
+            fn drop(&mut self) {
+                DROPS.fetch_add(self.0, SeqCst);
+            }
+        }
+
+        unsafe {
+            let token = epoch::pin_raw();
+            token.defer_drop(Owned::new(Elem(1)).into_ptr(&token));
+            token.flush();
+            epoch::unpin_raw(token);
+        }
+
+        while DROPS.load(SeqCst) == 0 {
+            epoch::collect_all();
+        }
+    }
+
+    #[test]
+    fn flush_local_garbage() {
+        for _ in 0..100 {
+            epoch::pin(|scope| {
+                unsafe {
+                    let a = Owned::new(7).into_ptr(scope);
+                    scope.defer_free(a);
+
+                    HARNESS.with(|h| {
+                        assert!(!(*h.bag.get()).is_empty());
+
+                        while !(*h.bag.get()).is_empty() {
+                            scope.flush();
+                        }
+                    });
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn garbage_buffering() {
+        HARNESS.with(|h| unsafe {
+            while !(*h.bag.get()).is_empty() {
+                epoch::pin(|scope| scope.flush());
+            }
+
+            epoch::pin(|scope| {
+                for _ in 0..10 {
+                    let a = Owned::new(7).into_ptr(scope);
+                    scope.defer_free(a);
+                }
+                assert!(!(*h.bag.get()).is_empty());
+            });
+        });
+    }
+
+    #[test]
+    fn defer_null() {
+        HARNESS.with(|h| unsafe {
+            epoch::pin(|scope| {
+                let len = (*h.bag.get()).len();
+
+                scope.defer_free(Ptr::<i32>::null());
+                scope.defer_drop(Ptr::<i32>::null());
+                assert_eq!((*h.bag.get()).len(), len);
+
+                // A null pointer with a tag is still a null pointer.
+                scope.defer_free(Ptr::<i32>::null().with_tag(1));
+                scope.defer_drop(Ptr::<i32>::null().with_tag(3));
+                assert_eq!((*h.bag.get()).len(), len);
+            });
+        });
+    }
+
+    #[test]
+    fn isolated_bag() {
+        HARNESS.with(|h| unsafe {
+            epoch::pin(|scope| {
+                scope.defer_free(Owned::new(7).into_ptr(scope));
+
+                let bag = h.bag.get();
+                let len = (*bag).len();
+
+                scope.with_isolated_bag(|scope| {
+                    assert_ne!(h.bag.get(), bag);
+                    assert!((*h.bag.get()).is_empty());
+
+                    scope.defer_free(Owned::new(8).into_ptr(scope));
+                    assert_eq!((*h.bag.get()).len(), 1);
+                });
+
+                assert_eq!(h.bag.get(), bag);
+                assert_eq!((*bag).len(), len);
+            });
+        });
+    }
+
+    #[test]
+    fn collect_all() {
+        const COUNT: usize = 1000;
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        thread_local! {
+            /// Number of objects destroyed by the current thread.
+            static LOCAL_DROPS: Cell<usize> = const { Cell::new(0) };
+        }
+
+        struct Elem(&'static AtomicUsize);
+
+        impl Drop for Elem {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, SeqCst);
+                LOCAL_DROPS.with(|d| d.set(d.get() + 1));
+            }
+        }
+
+        epoch::pin(|scope| unsafe {
+            for _ in 0..COUNT {
+                scope.defer_drop(Owned::new(Elem(&DROPS)).into_ptr(scope));
+            }
+        });
+
+        // Other threads may destroy some of the objects, but the ones destroyed by this thread
+        // must be included in the counts returned by `collect_all`, along with other garbage.
+        let before = LOCAL_DROPS.with(|d| d.get());
+        let mut destroyed = 0;
+        while DROPS.load(SeqCst) < COUNT {
+            destroyed += epoch::collect_all().0;
+        }
+        assert_eq!(DROPS.load(SeqCst), COUNT);
+        assert!(destroyed >= LOCAL_DROPS.with(|d| d.get()) - before);
+    }
+
+    #[test]
+    fn register_concurrently() {
+        const THREADS: usize = 16;
+
+        let registered = Arc::new(Barrier::new(THREADS + 1));
+        let counted = Arc::new(Barrier::new(THREADS + 1));
+
+        let threads = (0..THREADS).map(|_| {
+            let registered = registered.clone();
+            let counted = counted.clone();
+
+            thread::spawn(move || {
+                HARNESS.with(|h| {
+                    registered.wait();
+                    counted.wait();
+                    h.thread
+                }) as usize
+            })
+        }).collect::<Vec<_>>();
+
+        registered.wait();
+
+        // All spawned threads are registered and alive, so their entries must be in the list.
+        let mut live = Vec::new();
+        epoch::pin(|scope| {
+            let mut curr = participants().load(Acquire, scope);
+            while let Some(c) = unsafe { curr.as_ref() } {
+                let succ = c.next.load(Acquire, scope);
+                if succ.tag() == 0 {
+                    live.push(curr.as_raw() as usize);
+                }
+                curr = succ.with_tag(0);
+            }
         });
+
+        counted.wait();
+
+        for t in threads {
+            let entry = t.join().unwrap();
+            assert!(live.contains(&entry));
+        }
     }
 
     #[test]
@@ -505,4 +2618,677 @@ mod tests {
             t.join().unwrap();
         }
     }
+
+    #[test]
+    fn stall_backoff() {
+        with_stalled_epoch(|| HARNESS.with(|h| {
+            epoch::pin(|scope| unsafe {
+                // Old garbage may still get destroyed at first, but eventually nothing will be.
+                for _ in 0..1000 {
+                    if h.backoff.is_active() {
+                        break;
+                    }
+                    scope.defer_free(Owned::new(7).into_ptr(scope));
+                    scope.flush();
+                }
+                assert!(h.backoff.is_active());
+                assert!(h.backoff.yield_on_unpin.get());
+
+                let pins = h.backoff.pins.get();
+                assert_eq!(epoch::debug_snapshot().collect_backoff, pins);
+
+                // Flushing doesn't collect while backing off.
+                scope.defer_free(Owned::new(7).into_ptr(scope));
+                assert_eq!(scope.flush_counted(), 0);
+            });
+            assert!(!h.backoff.yield_on_unpin.get());
+
+            let pins = h.backoff.pins.get();
+            epoch::pin(|_| ());
+            assert_eq!(h.backoff.pins.get(), pins - 1);
+
+            // Every further run of fruitless attempts doubles the backoff.
+            for _ in 0..config::stall_backoff() {
+                h.backoff.record(false, 0);
+            }
+            assert_eq!(h.backoff.pins.get(), 2 * pins);
+
+            // Progress resets it.
+            h.backoff.record(true, 0);
+            assert!(!h.backoff.is_active());
+            h.backoff.yield_on_unpin.set(false);
+        }));
+    }
+
+    #[test]
+    fn max_pending_garbage() {
+        static STALLS: AtomicUsize = AtomicUsize::new(0);
+
+        fn report(pending: usize, _lagging: usize) {
+            assert!(pending > 0);
+            STALLS.fetch_add(1, SeqCst);
+        }
+
+        let _lock = config::test_lock();
+        with_stalled_epoch(|| HARNESS.with(|h| {
+            epoch::on_garbage_stall(Some(report));
+            epoch::set_max_pending_garbage(0);
+            epoch::pin(|scope| unsafe {
+                // Filling the bag pushes it into the global queue, where it can't be reclaimed.
+                for _ in 0..scope.bag_remaining() + 1 {
+                    scope.defer_free(Owned::new(7).into_ptr(scope));
+                }
+                assert!(h.backoff.yield_on_unpin.get());
+            });
+            epoch::set_max_pending_garbage(usize::MAX);
+            epoch::on_garbage_stall(None);
+        }));
+        assert!(STALLS.load(SeqCst) > 0);
+    }
+
+    #[test]
+    fn try_pin() {
+        thread::spawn(|| {
+            assert!(epoch::try_pin(|_| ()).is_none());
+            assert!(epoch::try_pin(|_| ()).is_none());
+
+            epoch::pin(|_| ());
+            assert_eq!(epoch::try_pin(|_| epoch::is_pinned()), Some(true));
+            assert!(!epoch::is_pinned());
+
+            // Reentrant pinning works as usual.
+            epoch::pin(|_| assert_eq!(epoch::try_pin(|_| 7), Some(7)));
+        }).join().unwrap();
+    }
+
+    #[test]
+    fn with_current_pin() {
+        thread::spawn(|| {
+            // The thread is neither registered nor pinned.
+            assert!(epoch::with_current_pin(|_| ()).is_none());
+            assert!(!REGISTERED.with(|r| r.get()));
+
+            epoch::pin(|outer| {
+                let count = HARNESS.with(|h| h.pin_count.get());
+                let same = epoch::with_current_pin(|scope| ptr::eq(scope.harness, outer.harness));
+                assert_eq!(same, Some(true));
+
+                // The thread wasn't pinned again.
+                assert_eq!(HARNESS.with(|h| h.pin_count.get()), count);
+            });
+            assert!(epoch::with_current_pin(|_| ()).is_none());
+        }).join().unwrap();
+    }
+
+    #[test]
+    fn recycle_entries() {
+        let t = Box::new(Thread {
+            state: AtomicUsize::new(7),
+            next: Atomic::null(),
+        });
+        t.next.store(Ptr::null().with_tag(1), SeqCst);
+        recycle_thread(t);
+
+        // Other threads might register in the meantime and take the entry, but whichever entry we
+        // get must be clean.
+        let t = alloc_thread();
+        assert_eq!(t.state.load(SeqCst), 0);
+        epoch::pin(|scope| {
+            let next = t.next.load(SeqCst, scope);
+            assert!(next.is_null());
+            assert_eq!(next.tag(), 0);
+        });
+    }
+
+    #[test]
+    fn scope_guard() {
+        thread_local! {
+            static LOG: RefCell<Vec<(u32, bool)>> = const { RefCell::new(Vec::new()) };
+        }
+
+        fn log(id: u32) {
+            LOG.with(|l| l.borrow_mut().push((id, epoch::is_pinned())));
+        }
+
+        epoch::pin(|outer| {
+            let _outer = outer.with_scope_guard(|| log(1));
+            epoch::pin(|inner| {
+                let _inner = inner.with_scope_guard(|| log(2));
+            });
+
+            // The inner guard runs when the inner region ends, while still pinned.
+            assert_eq!(LOG.with(|l| l.borrow().clone()), [(2, true)]);
+        });
+        assert_eq!(LOG.with(|l| l.replace(Vec::new())), [(2, true), (1, true)]);
+
+        let result = panic::catch_unwind(|| {
+            epoch::pin(|scope| {
+                let _guard = scope.with_scope_guard(|| log(3));
+                panic!("oops");
+            })
+        });
+        assert!(result.is_err());
+        assert_eq!(LOG.with(|l| l.replace(Vec::new())), [(3, true)]);
+
+        // Guards may borrow local data and are dropped in reverse order.
+        let ids = RefCell::new(Vec::new());
+        epoch::pin(|scope| {
+            let _a = scope.with_scope_guard(|| ids.borrow_mut().push(4));
+            let _b = scope.with_scope_guard(|| ids.borrow_mut().push(5));
+        });
+        assert_eq!(ids.into_inner(), [5, 4]);
+    }
+
+    #[test]
+    fn pin_borrowed() {
+        let (x, y) = (1, 2);
+        let a = Atomic::new(&x);
+
+        epoch::pin_borrowed(|scope| {
+            assert!(epoch::is_pinned());
+            assert_eq!(**unsafe { scope.load(&a, Acquire).deref() }, 1);
+
+            let p = scope.load(&a, Acquire);
+            let new = Owned::new(&y);
+            let q = scope.compare_and_swap_owned(&a, p, new, AcqRel).unwrap();
+            assert!(scope.compare_and_swap(&a, p, Ptr::null(), AcqRel).is_err());
+
+            let old = scope.swap(&a, Ptr::null(), AcqRel);
+            assert_eq!(old, q);
+            unsafe {
+                drop(p.into_box());
+                drop(q.into_box());
+            }
+        });
+        assert!(!epoch::is_pinned());
+    }
+
+    #[test]
+    fn defer_recycle() {
+        const COUNT: usize = 1000;
+        // The pool holds the recycled boxes themselves.
+        #[allow(clippy::vec_box)]
+        static POOL: Mutex<Vec<Box<usize>>> = Mutex::new(Vec::new());
+
+        fn recycle(b: Box<usize>) {
+            POOL.lock().unwrap().push(b);
+        }
+
+        epoch::pin(|scope| unsafe {
+            scope.defer_recycle(Ptr::null(), recycle);
+            for i in 0..COUNT {
+                scope.defer_recycle(Owned::new(i).into_ptr(scope), recycle);
+            }
+        });
+
+        // Other tests might be pinning at the same time, so keep trying.
+        while POOL.lock().unwrap().len() < COUNT {
+            epoch::collect_all();
+            thread::yield_now();
+        }
+
+        let mut pool = POOL.lock().unwrap();
+        pool.sort();
+        assert!(pool.iter().map(|b| **b).eq(0..COUNT));
+        pool.clear();
+    }
+
+    #[test]
+    fn collect_policy() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        thread_local! {
+            static COUNTED: Cell<bool> = const { Cell::new(false) };
+        }
+
+        fn counting(thread_count: usize, pin_count: usize) -> bool {
+            assert!(thread_count > 0);
+            assert_eq!(pin_count % config::PINS_BETWEEN_CHECKS, 0);
+            // Other tests might be pinning at the same time.
+            if COUNTED.with(|c| c.get()) {
+                CALLS.fetch_add(1, SeqCst);
+            }
+            epoch::default_collect_policy(thread_count, pin_count)
+        }
+
+        let _lock = config::test_lock();
+        epoch::set_collect_policy(counting);
+        thread::spawn(|| {
+            COUNTED.with(|c| c.set(true));
+            for _ in 0..100 {
+                // Reentrant pinning doesn't consult the policy.
+                epoch::pin(|_| epoch::pin(|_| ()));
+            }
+        }).join().unwrap();
+        epoch::set_collect_policy(epoch::default_collect_policy);
+
+        // The policy is consulted on pinnings 0, 16, ..., 96, unless a backlog forces collection.
+        assert!(CALLS.load(SeqCst) <= 7);
+        assert!(CALLS.load(SeqCst) > 0);
+    }
+
+    #[test]
+    fn reclamation_waits_for_pinned_threads() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Elem(u64);
+
+        impl Drop for Elem {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, SeqCst);
+            }
+        }
+
+        let a = Arc::new(Atomic::new(Elem(0x1234_5678)));
+        let (pinned_tx, pinned_rx) = mpsc::channel();
+        let (unpin_tx, unpin_rx) = mpsc::channel::<()>();
+
+        // Thread A loads the object and keeps reading it while pinned.
+        let t = {
+            let a = a.clone();
+            thread::spawn(move || {
+                epoch::pin(|scope| {
+                    let p = a.load(Acquire, scope);
+                    pinned_tx.send(p.as_raw() as usize).unwrap();
+                    unpin_rx.recv().unwrap();
+                    assert_eq!(unsafe { p.deref().0 }, 0x1234_5678);
+                })
+            })
+        };
+        let raw = pinned_rx.recv().unwrap();
+
+        // Thread B unlinks the object and defers dropping it.
+        epoch::pin(|scope| unsafe {
+            let p = a.swap(Ptr::null(), AcqRel, scope);
+            assert_eq!(p.as_raw() as usize, raw);
+            scope.defer_drop(p);
+        });
+
+        // No matter how hard B tries, the object can't be dropped while A is pinned.
+        for _ in 0..10 {
+            epoch::collect_all();
+            assert_eq!(DROPS.load(SeqCst), 0);
+        }
+
+        unpin_tx.send(()).unwrap();
+        t.join().unwrap();
+
+        // Once A is unpinned, the object gets dropped after enough epochs pass.
+        for _ in 0..1000 {
+            if DROPS.load(SeqCst) == 1 {
+                return;
+            }
+            epoch::collect_all();
+        }
+        panic!("the object was never dropped");
+    }
+
+    #[test]
+    fn pin_flushing() {
+        HARNESS.with(|h| unsafe {
+            // Make flushing only push the bag, without collecting garbage into it.
+            h.backoff.pins.set(1000);
+
+            epoch::pin_flushing(|scope| {
+                epoch::pin(|scope| scope.defer_free(Owned::new(7).into_ptr(scope)));
+                assert!(!(*h.bag.get()).is_empty());
+
+                scope.defer_free(Owned::new(7).into_ptr(scope));
+            });
+            assert!((*h.bag.get()).is_empty());
+
+            h.backoff.pins.set(0);
+        });
+    }
+
+    #[test]
+    fn local_handle_holds_advance() {
+        let handle = epoch::register();
+
+        handle.pin(|_| {
+            assert!(handle.is_pinned());
+            assert!(!epoch::is_pinned());
+
+            let before = EPOCH.load(SeqCst);
+            for _ in 0..100 {
+                epoch::pin(try_advance);
+            }
+            let after = EPOCH.load(SeqCst);
+
+            assert!(after.wrapping_sub(before) <= 2);
+        });
+        assert!(!handle.is_pinned());
+    }
+
+    #[test]
+    fn local_handle_collect_all() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Elem(usize);
+
+        impl Drop for Elem {
+            fn drop(&mut self) {
+                DROPS.fetch_add(self.0, SeqCst);
+            }
+        }
+
+        // Collecting through a handle doesn't register the current thread.
+        thread::spawn(|| {
+            let handle = epoch::register();
+            handle.pin(|scope| unsafe { scope.defer_drop(Owned::new(Elem(1)).into_ptr(scope)) });
+
+            // Other tests might be pinned for a moment, so try several times.
+            for _ in 0..1000 {
+                handle.collect_all();
+                if DROPS.load(SeqCst) == 1 {
+                    break;
+                }
+            }
+            assert_eq!(DROPS.load(SeqCst), 1);
+            assert!(!REGISTERED.with(|r| r.get()));
+        }).join().unwrap();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "pinned scope")]
+    fn defer_unprotected() {
+        unsafe {
+            epoch::unprotected(|scope| scope.defer_free(Owned::new(7).into_ptr(scope)));
+        }
+    }
+
+    #[test]
+    fn task_harness_holds_epoch() {
+        let mut task = TaskHarness::new();
+
+        task.pin(|_| {
+            // The task is pinned, so the epoch can advance at most once.
+            let before = EPOCH.load(SeqCst);
+            for _ in 0..10 {
+                epoch::pin(try_advance);
+            }
+            assert!(EPOCH.load(SeqCst).wrapping_sub(before) <= 2);
+        });
+
+        thread::spawn(move || {
+            task.pin(|scope| unsafe { scope.defer_free(Owned::new(7).into_ptr(scope)) });
+        }).join().unwrap();
+    }
+
+    #[test]
+    fn debug_snapshot() {
+        let snapshot = epoch::debug_snapshot();
+        assert!(snapshot.participants.iter().any(|p| p.is_pinned));
+        for p in &snapshot.participants {
+            assert!(p.is_pinned || p.epoch == 0);
+        }
+    }
+
+    #[test]
+    fn try_advance_after_epoch_moved() {
+        epoch::pin(|scope| {
+            let pinned_epoch = HARNESS.with(|h| unsafe { &*h.thread }.state.load(SeqCst) & !1);
+
+            // While this thread is pinned, another thread can advance the epoch at most once.
+            thread::spawn(move || {
+                while EPOCH.load(SeqCst) == pinned_epoch {
+                    epoch::pin(try_advance);
+                }
+            }).join().unwrap();
+
+            // Now this thread's own entry holds the epoch back, which the pre-check notices.
+            let epoch = EPOCH.load(SeqCst);
+            assert!(!try_advance(scope));
+            assert_eq!(EPOCH.load(SeqCst), epoch);
+        });
+
+        // Once pinned anew, the thread can advance the epoch again.
+        thread::spawn(|| {
+            let epoch = EPOCH.load(SeqCst);
+            while EPOCH.load(SeqCst) == epoch {
+                epoch::pin(try_advance);
+            }
+        }).join().unwrap();
+    }
+
+    #[test]
+    fn exited_threads_get_unlinked() {
+        fn list_len() -> usize {
+            epoch::pin(|scope| {
+                let mut len = 0;
+                let mut curr = participants().load(Acquire, scope);
+                while let Some(c) = unsafe { curr.as_ref() } {
+                    len += 1;
+                    curr = c.next.load(Acquire, scope).with_tag(0);
+                }
+                len
+            })
+        }
+
+        let done = Arc::new(AtomicUsize::new(0));
+        let advancers = (0..2).map(|_| {
+            let done = done.clone();
+            thread::spawn(move || {
+                while done.load(SeqCst) == 0 {
+                    epoch::pin(try_advance);
+                }
+            })
+        }).collect::<Vec<_>>();
+
+        for _ in 0..50 {
+            let threads = (0..8).map(|_| thread::spawn(|| epoch::pin(|_| ()))).collect::<Vec<_>>();
+            for t in threads {
+                t.join().unwrap();
+            }
+        }
+
+        done.store(1, SeqCst);
+        for t in advancers {
+            t.join().unwrap();
+        }
+
+        // Other tests may be running concurrently, so allow for some of their threads.
+        for _ in 0..1000 {
+            if list_len() < 100 {
+                return;
+            }
+            epoch::pin(try_advance);
+            thread::yield_now();
+        }
+        panic!("exited threads were not unlinked: {} entries", list_len());
+    }
+
+    #[test]
+    fn unlink_head_entry() {
+        fn is_reachable(entry: usize, scope: &Scope) -> bool {
+            let mut curr = participants().load(Acquire, scope);
+            while let Some(c) = unsafe { curr.as_ref() } {
+                if c as *const _ as usize == entry {
+                    return true;
+                }
+                curr = c.next.load(Acquire, scope).with_tag(0);
+            }
+            false
+        }
+
+        let mut unlinked = 0;
+        for _ in 0..1000 {
+            // A new thread inserts its entry at the head of the list and exits.
+            let entry = thread::spawn(|| HARNESS.with(|h| h.thread as usize)).join().unwrap();
+
+            epoch::pin(|scope| {
+                // While this thread is pinned, the entry cannot be freed and its address reused.
+                let head = participants().load(Acquire, scope);
+                if head.as_raw() as usize != entry {
+                    // Another thread has registered in the meantime.
+                    return;
+                }
+
+                try_advance(scope);
+
+                let head = participants().load(Acquire, scope);
+                assert_eq!(head.tag(), 0);
+                if head.as_raw() as usize != entry {
+                    // The entry was unlinked by swinging the head of the list itself.
+                    assert!(!is_reachable(entry, scope));
+                    unlinked += 1;
+                }
+            });
+
+            // A thread registering afterwards must be linked in properly.
+            thread::spawn(|| {
+                let entry = HARNESS.with(|h| h.thread as usize);
+                epoch::pin(|scope| assert!(is_reachable(entry, scope)));
+            }).join().unwrap();
+        }
+        assert!(unlinked > 0);
+    }
+
+    #[test]
+    fn register_storm() {
+        const THREADS: usize = 64;
+
+        let barrier = Arc::new(Barrier::new(THREADS + 1));
+        let threads = (0..THREADS).map(|_| {
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                // Register all at once.
+                barrier.wait();
+                let entry = HARNESS.with(|h| h.thread as usize);
+
+                // Stay registered until the list has been checked.
+                barrier.wait();
+                barrier.wait();
+                entry
+            })
+        }).collect::<Vec<_>>();
+
+        barrier.wait();
+        barrier.wait();
+
+        // Every live thread's entry is in the list exactly once.
+        let mut reachable = Vec::new();
+        epoch::pin(|scope| {
+            let mut curr = participants().load(Acquire, scope);
+            while let Some(c) = unsafe { curr.as_ref() } {
+                let (succ, tag) = c.next.load_split(Acquire, scope);
+                if tag == 0 {
+                    reachable.push(c as *const _ as usize);
+                }
+                curr = succ;
+            }
+        });
+        barrier.wait();
+
+        let entries = threads.into_iter().map(|t| t.join().unwrap()).collect::<Vec<_>>();
+        for e in &entries {
+            assert_eq!(reachable.iter().filter(|&r| r == e).count(), 1);
+        }
+    }
+
+    #[test]
+    fn on_bag_full() {
+        static FULL: AtomicUsize = AtomicUsize::new(0);
+        fn count() {
+            FULL.fetch_add(1, SeqCst);
+        }
+
+        let _lock = config::test_lock();
+        epoch::on_bag_full(Some(count));
+        epoch::pin(|scope| unsafe {
+            let remaining = scope.bag_remaining();
+            for _ in 0..remaining {
+                scope.defer_free(Owned::new(7).into_ptr(scope));
+            }
+            assert_eq!(scope.bag_remaining(), 0);
+
+            let before = FULL.load(SeqCst);
+            scope.defer_free(Owned::new(7).into_ptr(scope));
+            assert!(FULL.load(SeqCst) > before);
+        });
+        epoch::on_bag_full(None);
+    }
+
+    #[test]
+    fn pin_with_deadline() {
+        let v = epoch::pin_with_deadline(Duration::from_secs(60), |_| 7);
+        assert_eq!(v, 7);
+    }
+
+    #[test]
+    fn pin_with_deadline_exceeded() {
+        // Exceeding the deadline only prints a warning and counts the overrun.
+        let before = epoch::deadline_overruns();
+        let v = epoch::pin_with_deadline(Duration::from_millis(1), |_| {
+            thread::sleep(Duration::from_millis(20));
+            7
+        });
+        assert_eq!(v, 7);
+        assert!(!epoch::is_pinned());
+        if cfg!(debug_assertions) {
+            assert!(epoch::deadline_overruns() > before);
+        }
+    }
+
+    #[test]
+    fn reclaim_all_if_quiescent() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Elem(usize);
+
+        impl Drop for Elem {
+            fn drop(&mut self) {
+                DROPS.fetch_add(self.0, SeqCst);
+            }
+        }
+
+        // While another thread is pinned, reclamation is refused.
+        with_stalled_epoch(|| {
+            epoch::pin(|scope| unsafe { scope.defer_drop(Owned::new(Elem(1)).into_ptr(scope)) });
+            assert_eq!(epoch::try_reclaim_all_if_quiescent(), Err(()));
+        });
+
+        // Other tests may be pinned for a moment, so try several times.
+        for _ in 0..1000 {
+            if epoch::try_reclaim_all_if_quiescent().is_ok() && DROPS.load(SeqCst) == 1 {
+                return;
+            }
+            thread::yield_now();
+        }
+        panic!("garbage was not reclaimed");
+    }
+
+    #[test]
+    fn barrier() {
+        let before = EPOCH.load(SeqCst);
+        epoch::barrier();
+        let after = EPOCH.load(SeqCst);
+        assert!(after.wrapping_sub(before) >= 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "pinned")]
+    fn barrier_while_pinned() {
+        epoch::pin(|_| epoch::barrier());
+    }
+
+    #[test]
+    fn compact_participants() {
+        epoch::pin(|scope| {
+            // While this thread is pinned, none of the unlinked entries can be freed and reused.
+            let threads = (0..8).map(|_| {
+                thread::spawn(|| HARNESS.with(|h| h.thread as usize))
+            }).collect::<Vec<_>>();
+            let exited = threads.into_iter().map(|t| t.join().unwrap()).collect::<Vec<_>>();
+
+            epoch::compact_participants(scope);
+
+            let mut curr = participants().load(Acquire, scope);
+            while let Some(c) = unsafe { curr.as_ref() } {
+                assert!(!exited.contains(&(c as *const _ as usize)));
+                curr = c.next.load(Acquire, scope).with_tag(0);
+            }
+        });
+    }
 }
@@ -0,0 +1,239 @@
+//! Atomic pointers that don't keep their objects alive.
+//!
+//! An `Atomic<T>` is usually the owner of the object it points to: an object gets unlinked from it
+//! and only then deferred for destruction. Caches often want to refer to an object owned by some
+//! other data structure, which might destroy it at any time. Such a reference is only useful if it
+//! can tell whether the object is still there.
+//!
+//! `WeakAtomic<T>` stores the pointer together with the epoch the storing thread was pinned in.
+//! An object that was reachable in that epoch can't be destroyed until the global epoch has
+//! advanced twice past it, so a thread pinned in the same epoch (or the one before it) may still
+//! safely dereference the pointer. Otherwise the pointer is considered stale and can't be
+//! upgraded anymore.
+//!
+//! Like `StampedAtomic<T>`, a weak atomic pointer points to an immutable heap-allocated pair of
+//! the pointer and the epoch, and every store allocates a new pair.
+
+use std::marker::PhantomData;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed};
+
+use epoch::{self, Atomic, Owned, Ptr, Scope};
+use epoch::thread;
+
+/// An immutable pair of a tagged pointer and the epoch in which it was stored.
+struct Entry {
+    /// The untagged address.
+    raw: usize,
+    /// The tag.
+    tag: usize,
+    /// The epoch the storing thread was pinned in.
+    epoch: usize,
+}
+
+/// An atomic pointer that may outlive the object it points to.
+///
+/// The pointer can be read back with [`upgrade`], which returns it only if it is provably still
+/// valid. The proof is based on epochs, not on the object itself: an upgrade succeeds only if the
+/// current thread is pinned in the same epoch the pointer was stored in, or the one just before
+/// it. Once the global epoch has advanced past that, the pointer can't be upgraded anymore, even
+/// if the object is still alive. Refreshing the pointer from the owning data structure makes it
+/// upgradable again.
+///
+/// Dropping a `WeakAtomic` never destroys the object it points to.
+///
+/// [`upgrade`]: struct.WeakAtomic.html#method.upgrade
+///
+/// # Examples
+///
+/// ```
+/// use coco::epoch::{self, Atomic, WeakAtomic};
+/// use std::sync::atomic::Ordering::Acquire;
+///
+/// let owner = Atomic::new(1234);
+/// let cache = WeakAtomic::null();
+///
+/// epoch::pin(|scope| {
+///     let p = owner.load(Acquire, scope);
+///     unsafe { cache.store(p, scope) }
+///
+///     let q = cache.upgrade(scope).unwrap();
+///     assert_eq!(unsafe { *q.deref() }, 1234);
+/// });
+/// # epoch::pin(|scope| unsafe { drop(owner.swap_null(Acquire, scope).into_box()) });
+/// ```
+pub struct WeakAtomic<T> {
+    /// The current pair. Null stands for a null pointer.
+    inner: Atomic<Entry>,
+    _marker: PhantomData<*mut T>,
+}
+
+unsafe impl<T: Send + Sync> Send for WeakAtomic<T> {}
+unsafe impl<T: Send + Sync> Sync for WeakAtomic<T> {}
+
+impl<T> WeakAtomic<T> {
+    /// Returns a new null weak atomic pointer.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::WeakAtomic;
+    ///
+    /// let a = WeakAtomic::<i32>::null();
+    /// ```
+    pub fn null() -> Self {
+        WeakAtomic {
+            inner: Atomic::null(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Stores a `Ptr` into the weak atomic pointer, stamped with the epoch the current thread is
+    /// pinned in.
+    ///
+    /// The store uses `AcqRel` ordering, and the previous pair is reclaimed through the epoch GC.
+    /// Null pointers are stored without a stamp, so the tag of a null pointer is not preserved.
+    ///
+    /// # Safety
+    ///
+    /// `new` must be null, or point to an object that was reachable through the owning data
+    /// structure at some point while the current thread has been pinned, e.g. because it was
+    /// loaded from an [`Atomic`] within `scope`. The object must be destroyed only through the
+    /// epoch GC, i.e. by one of the `defer_*` methods on [`Scope`] after it was unlinked.
+    ///
+    /// [`Atomic`]: struct.Atomic.html
+    /// [`Scope`]: struct.Scope.html
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Ptr, WeakAtomic};
+    ///
+    /// let a = WeakAtomic::<i32>::null();
+    /// epoch::pin(|scope| unsafe { a.store(Ptr::null(), scope) });
+    /// ```
+    pub unsafe fn store(&self, new: Ptr<T>, scope: &Scope) {
+        let old = if new.is_null() {
+            self.inner.swap_null(AcqRel, scope)
+        } else {
+            let entry = Owned::new(Entry {
+                raw: new.as_raw() as usize,
+                tag: new.tag(),
+                epoch: thread::pinned_epoch(scope),
+            });
+            self.inner.swap(entry.into_ptr(scope), AcqRel, scope)
+        };
+        scope.defer_free(old);
+    }
+
+    /// Loads the pointer if it is provably still valid.
+    ///
+    /// Returns `None` if the pointer is null, or if it was stored in an epoch that is too old to
+    /// prove that the object hasn't been destroyed. Otherwise the returned pointer can be safely
+    /// dereferenced for as long as `scope` lives. The load uses `Acquire` ordering.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::epoch::{self, Owned, WeakAtomic};
+    ///
+    /// let a = WeakAtomic::null();
+    /// epoch::pin(|scope| unsafe {
+    ///     assert!(a.upgrade(scope).is_none());
+    ///
+    ///     let p = Owned::new(7).into_ptr(scope);
+    ///     a.store(p, scope);
+    ///     assert_eq!(a.upgrade(scope), Some(p));
+    ///     scope.defer_free(p);
+    /// });
+    /// ```
+    pub fn upgrade<'scope>(&self, scope: &'scope Scope) -> Option<Ptr<'scope, T>> {
+        let entry = self.inner.load(Acquire, scope);
+        match unsafe { entry.as_ref() } {
+            Some(e) if thread::protects_since(scope, e.epoch) => unsafe {
+                Some(Ptr::from_raw(e.raw as *const T).with_tag(e.tag))
+            },
+            _ => None,
+        }
+    }
+}
+
+impl<T> Default for WeakAtomic<T> {
+    fn default() -> Self {
+        WeakAtomic::null()
+    }
+}
+
+impl<T> Drop for WeakAtomic<T> {
+    fn drop(&mut self) {
+        // Destroy the current pair. The object it points to is not owned by us.
+        unsafe {
+            epoch::unprotected(|scope| {
+                let entry = self.inner.load(Relaxed, scope);
+                if !entry.is_null() {
+                    drop(entry.into_box());
+                }
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering::{Acquire, SeqCst};
+    use std::thread;
+
+    use epoch::{self, Atomic, Owned, Ptr, WeakAtomic};
+    use epoch::garbage::EPOCH;
+    use epoch::thread::try_advance;
+
+    #[test]
+    fn null() {
+        let a = WeakAtomic::<u64>::null();
+        epoch::pin(|scope| unsafe {
+            assert!(a.upgrade(scope).is_none());
+            a.store(Owned::new(1).into_ptr(scope), scope);
+            let p = a.upgrade(scope).unwrap();
+            a.store(Ptr::null(), scope);
+            assert!(a.upgrade(scope).is_none());
+            drop(p.into_box());
+        });
+    }
+
+    #[test]
+    fn tag() {
+        let a = WeakAtomic::<u64>::null();
+        epoch::pin(|scope| unsafe {
+            let p = Owned::new(1).into_ptr(scope).with_tag(1);
+            a.store(p, scope);
+            assert_eq!(a.upgrade(scope).map(|q| q.tag()), Some(1));
+            drop(p.into_box());
+        });
+    }
+
+    #[test]
+    fn stale_after_two_advancements() {
+        let owner = Atomic::new(7u64);
+        let a = WeakAtomic::null();
+
+        epoch::pin(|scope| {
+            unsafe { a.store(owner.load(Acquire, scope), scope) }
+            assert!(a.upgrade(scope).is_some());
+        });
+
+        // Other threads may be pinned as well, so keep trying until the epoch moves twice.
+        let start = EPOCH.load(SeqCst);
+        while EPOCH.load(SeqCst).wrapping_sub(start) < 4 {
+            epoch::pin(try_advance);
+            thread::yield_now();
+        }
+
+        epoch::pin(|scope| {
+            assert!(a.upgrade(scope).is_none());
+
+            // Refreshing from the owner makes the pointer upgradable again.
+            unsafe { a.store(owner.load(Acquire, scope), scope) }
+            assert!(a.upgrade(scope).is_some());
+            unsafe { drop(owner.swap_null(Acquire, scope).into_box()) }
+        });
+    }
+}
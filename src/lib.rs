@@ -14,7 +14,9 @@
 //! The following collections are available:
 //!
 //! * [`Stack`]: A lock-free stack.
+//! * [`LockFreeSet`]: A lock-free ordered set.
 //! * [`deque`]: A lock-free work-stealing deque.
+//! * [`channel`]: A lock-free bounded channel.
 //!
 //! # Which collection should you use?
 //!
@@ -24,11 +26,22 @@
 //! * You want to avoid performance degradation due to locking.
 //! * You want the first-in first-out order of elements.
 //!
+//! ### Use a [`LockFreeSet`] when:
+//!
+//! * You want to test membership of a small number of elements concurrently.
+//! * You want each element to be present at most once.
+//!
 //! ### Use a [`deque`] when:
 //!
 //! * You want one thread inserting and removing objects, and multiple threads just removing them.
 //! * You don't care about the order of elements.
 //!
+//! ### Use a [`channel`] when:
+//!
+//! * You want to pass messages from multiple producers to multiple consumers.
+//! * You want the first-in first-out order of messages.
+//! * You want a fixed bound on the number of messages in flight.
+//!
 //! # Garbage collection
 //!
 //! An interesting problem concurrent collections deal with comes from the remove operation.
@@ -51,7 +64,9 @@
 //! of concurrent collections don't have to worry about.
 //!
 //! [`Stack`]: stack/struct.Stack.html
+//! [`LockFreeSet`]: set/struct.LockFreeSet.html
 //! [`deque`]: deque/fn.new.html
+//! [`channel`]: channel/fn.bounded.html
 
 #![cfg_attr(feature = "nightly", feature(const_fn))]
 
@@ -60,8 +75,19 @@ extern crate either;
 #[macro_use(defer)]
 extern crate scopeguard;
 
+#[cfg(any(test, feature = "internals"))]
+pub mod checked_alloc;
+
+pub mod channel;
 pub mod deque;
 pub mod epoch;
+pub mod set;
 pub mod stack;
 
+pub use set::LockFreeSet;
 pub use stack::Stack;
+
+/// All unit tests run with an allocator that detects double frees and writes after free.
+#[cfg(test)]
+#[global_allocator]
+static ALLOC: checked_alloc::CheckedAlloc = checked_alloc::CheckedAlloc;
@@ -0,0 +1,381 @@
+//! A lock-free ordered set.
+//!
+//! This is an implementation of the Harris-Michael linked list. Elements are kept in a sorted
+//! singly-linked list. Removing an element happens in two steps: first the node is logically
+//! deleted by tagging its next-pointer, and then it is unlinked from the list. Any thread that
+//! stumbles upon a logically deleted node while traversing the list helps unlinking it.
+//!
+//! Operations take linear time, so the set is suitable only for small numbers of elements.
+
+use std::cmp::Ordering::{Equal, Greater, Less};
+use std::hash::Hash;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed};
+
+use epoch::{self, Atomic, Owned, Ptr, Scope};
+
+/// A single node in a set.
+struct Node<T> {
+    /// The element.
+    key: T,
+    /// The next node in the list. If the tag is 1, this node is logically deleted.
+    next: Atomic<Node<T>>,
+}
+
+// A removed node may get dropped by any thread. That drops the key, but not the successor.
+unsafe impl<T: Send> Send for Node<T> {}
+
+/// A lock-free ordered set.
+///
+/// Elements are ordered by `Ord`. The `Hash` bound keeps the door open for a hashed variant that
+/// splits the list into buckets, without changing the element requirements.
+///
+/// It can be used by multiple threads inserting and removing elements at the same time.
+pub struct LockFreeSet<T> {
+    head: Atomic<Node<T>>,
+}
+
+unsafe impl<T: Send + Sync> Send for LockFreeSet<T> {}
+unsafe impl<T: Send + Sync> Sync for LockFreeSet<T> {}
+
+impl<T: Ord + Hash + Send + 'static> LockFreeSet<T> {
+    /// Returns a new, empty set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::LockFreeSet;
+    ///
+    /// let s = LockFreeSet::<i32>::new();
+    /// ```
+    pub fn new() -> Self {
+        LockFreeSet { head: Atomic::null() }
+    }
+
+    /// Searches for `key` and unlinks logically deleted nodes along the way.
+    ///
+    /// Returns the link pointing to the first node with a key not less than `key`, that node
+    /// (possibly null), and whether its key is equal to `key`.
+    fn find<'scope>(
+        &'scope self,
+        key: &T,
+        scope: &'scope Scope,
+    ) -> (&'scope Atomic<Node<T>>, Ptr<'scope, Node<T>>, bool) {
+        'retry: loop {
+            let mut pred = &self.head;
+            let mut curr = pred.load(Acquire, scope);
+
+            while let Some(c) = unsafe { curr.as_ref() } {
+                let (succ, deleted) = c.next.load_marked(0, Acquire, scope);
+
+                if deleted {
+                    // This node is logically deleted. Try unlinking it from the list.
+                    let succ = succ.with_tag(0);
+                    match pred.compare_and_swap(curr, succ, AcqRel, scope) {
+                        Ok(()) => {
+                            unsafe { scope.defer_drop(curr) }
+                            curr = succ;
+                        }
+                        // The predecessor has changed or got deleted. Start over.
+                        Err(_) => continue 'retry,
+                    }
+                    continue;
+                }
+
+                match c.key.cmp(key) {
+                    Less => {
+                        pred = &c.next;
+                        curr = succ;
+                    }
+                    Equal => return (pred, curr, true),
+                    Greater => return (pred, curr, false),
+                }
+            }
+
+            return (pred, curr, false);
+        }
+    }
+
+    /// Returns `true` if the set contains `key`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::LockFreeSet;
+    ///
+    /// let s = LockFreeSet::new();
+    /// s.insert(1);
+    /// assert!(s.contains(&1));
+    /// assert!(!s.contains(&2));
+    /// ```
+    pub fn contains(&self, key: &T) -> bool {
+        epoch::pin(|scope| self.find(key, scope).2)
+    }
+
+    /// Inserts `key` into the set.
+    ///
+    /// Returns `false` if the set already contained it, in which case `key` is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::LockFreeSet;
+    ///
+    /// let s = LockFreeSet::new();
+    /// assert!(s.insert(1));
+    /// assert!(!s.insert(1));
+    /// ```
+    pub fn insert(&self, key: T) -> bool {
+        let mut node = Owned::new(Node {
+            key,
+            next: Atomic::null(),
+        });
+
+        epoch::pin(|scope| loop {
+            let (pred, curr, found) = self.find(&node.key, scope);
+            if found {
+                drop(node.into_box());
+                return false;
+            }
+
+            node.next.store(curr, Relaxed);
+            match pred.compare_and_swap_owned(curr, node, AcqRel, scope) {
+                Ok(_) => return true,
+                Err((_, n)) => node = n,
+            }
+        })
+    }
+
+    /// Removes `key` from the set.
+    ///
+    /// Returns `false` if the set didn't contain it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use coco::LockFreeSet;
+    ///
+    /// let s = LockFreeSet::new();
+    /// s.insert(1);
+    /// assert!(s.remove(&1));
+    /// assert!(!s.remove(&1));
+    /// ```
+    pub fn remove(&self, key: &T) -> bool {
+        epoch::pin(|scope| loop {
+            let (pred, curr, found) = self.find(key, scope);
+            if !found {
+                return false;
+            }
+
+            let c = unsafe { curr.deref() };
+            let (succ, deleted) = c.next.load_marked(0, Acquire, scope);
+            if deleted {
+                // Another thread is removing this node. Let it finish.
+                continue;
+            }
+
+            // Logically delete the node. The thread that succeeds is the one removing the key.
+            if c.next.compare_and_swap(succ, succ.with_tag(1), AcqRel, scope).is_err() {
+                continue;
+            }
+
+            // Try unlinking the node. If that fails, let `find` take care of it.
+            match pred.compare_and_swap(curr, succ, AcqRel, scope) {
+                Ok(()) => unsafe { scope.defer_drop(curr) },
+                Err(_) => {
+                    self.find(key, scope);
+                }
+            }
+            return true;
+        })
+    }
+}
+
+impl<T: Ord + Hash + Send + 'static> Default for LockFreeSet<T> {
+    fn default() -> Self {
+        LockFreeSet::new()
+    }
+}
+
+impl<T> Drop for LockFreeSet<T> {
+    fn drop(&mut self) {
+        // Destruct all nodes in the set, including logically deleted ones still in the list.
+        unsafe {
+            epoch::unprotected(|scope| {
+                let mut curr = self.head.load(Relaxed, scope);
+                while !curr.is_null() {
+                    let next = curr.deref().next.load(Relaxed, scope).with_tag(0);
+                    drop(curr.into_box());
+                    curr = next;
+                }
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate rand;
+
+    use std::hash::{Hash, Hasher};
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::SeqCst;
+    use std::thread;
+
+    use checked_alloc;
+    use super::LockFreeSet;
+    use self::rand::Rng;
+
+    #[test]
+    fn smoke() {
+        checked_alloc::check_leaks(|| {
+            let s = LockFreeSet::new();
+            assert!(!s.contains(&2));
+            assert!(s.insert(2));
+            assert!(s.insert(1));
+            assert!(s.insert(3));
+            assert!(!s.insert(2));
+
+            assert!(s.contains(&1));
+            assert!(s.contains(&2));
+            assert!(s.contains(&3));
+
+            assert!(s.remove(&2));
+            assert!(!s.remove(&2));
+            assert!(!s.contains(&2));
+            assert!(s.contains(&1));
+            assert!(s.contains(&3));
+        });
+    }
+
+    #[test]
+    fn disjoint_keys() {
+        const THREADS: usize = 4;
+        const KEYS: usize = 500;
+
+        let s = Arc::new(LockFreeSet::new());
+
+        let threads = (0..THREADS).map(|t| {
+            let s = s.clone();
+            thread::spawn(move || {
+                // Every thread owns the keys congruent to `t`, so it knows exactly what's in.
+                let keys = (0..KEYS).map(|i| i * THREADS + t).collect::<Vec<_>>();
+                for &k in &keys {
+                    assert!(s.insert(k));
+                }
+                for &k in &keys {
+                    assert!(s.contains(&k));
+                }
+                for &k in keys.iter().filter(|&&k| k % 2 == 0) {
+                    assert!(s.remove(&k));
+                }
+                for &k in &keys {
+                    assert_eq!(s.contains(&k), k % 2 == 1);
+                }
+            })
+        }).collect::<Vec<_>>();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn stress() {
+        const THREADS: usize = 8;
+        const KEYS: usize = 32;
+
+        struct Elem(usize, Arc<AtomicUsize>);
+
+        impl Drop for Elem {
+            fn drop(&mut self) {
+                self.1.fetch_add(1, SeqCst);
+            }
+        }
+
+        impl PartialEq for Elem {
+            fn eq(&self, other: &Elem) -> bool {
+                self.0 == other.0
+            }
+        }
+
+        impl Eq for Elem {}
+
+        impl PartialOrd for Elem {
+            fn partial_cmp(&self, other: &Elem) -> Option<::std::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for Elem {
+            fn cmp(&self, other: &Elem) -> ::std::cmp::Ordering {
+                self.0.cmp(&other.0)
+            }
+        }
+
+        impl Hash for Elem {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                self.0.hash(state);
+            }
+        }
+
+        let s = Arc::new(LockFreeSet::new());
+        let created = Arc::new(AtomicUsize::new(0));
+        let dropped = Arc::new(AtomicUsize::new(0));
+        // Per key, the number of successful inserts minus the number of successful removals.
+        let balance = Arc::new((0..KEYS).map(|_| AtomicUsize::new(0)).collect::<Vec<_>>());
+
+        let threads = (0..THREADS).map(|_| {
+            let s = s.clone();
+            let created = created.clone();
+            let dropped = dropped.clone();
+            let balance = balance.clone();
+
+            thread::spawn(move || {
+                let mut rng = rand::thread_rng();
+                for _ in 0..20_000 {
+                    let k = rng.gen_range(0, KEYS);
+                    let probe = Elem(k, dropped.clone());
+                    created.fetch_add(1, SeqCst);
+
+                    match rng.gen_range(0, 3) {
+                        0 => {
+                            created.fetch_add(1, SeqCst);
+                            if s.insert(Elem(k, dropped.clone())) {
+                                balance[k].fetch_add(1, SeqCst);
+                            }
+                        }
+                        1 => {
+                            if s.remove(&probe) {
+                                balance[k].fetch_sub(1, SeqCst);
+                            }
+                        }
+                        _ => {
+                            s.contains(&probe);
+                        }
+                    }
+                }
+            })
+        }).collect::<Vec<_>>();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        // No update got lost: a key is present exactly if it was inserted once more than removed.
+        for k in 0..KEYS {
+            let b = balance[k].load(SeqCst);
+            assert!(b <= 1);
+            assert_eq!(s.contains(&Elem(k, Arc::new(AtomicUsize::new(0)))), b == 1);
+        }
+
+        // Every element gets dropped exactly once. Other tests might be pinning threads at the same
+        // time, so keep collecting until the removed ones are reclaimed.
+        drop(s);
+        while dropped.load(SeqCst) < created.load(SeqCst) {
+            ::epoch::collect_all();
+            thread::yield_now();
+        }
+        assert_eq!(dropped.load(SeqCst), created.load(SeqCst));
+    }
+}
@@ -5,7 +5,7 @@
 use std::ptr;
 use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed};
 
-use epoch::{self, Atomic, Owned};
+use epoch::{self, Atomic};
 
 /// A single node in a stack.
 struct Node<T> {
@@ -67,23 +67,13 @@ impl<T> Stack<T> {
     /// s.push(2);
     /// ```
     pub fn push(&self, value: T) {
-        let mut node = Owned::new(Node {
-            value: value,
-            next: Atomic::null(),
-        });
-
         epoch::pin(|scope| {
-            let mut head = self.head.load(Acquire, scope);
-            loop {
-                node.next.store(head, Relaxed);
-                match self.head.compare_and_swap_weak_owned(head, node, AcqRel, scope) {
-                    Ok(_) => break,
-                    Err((h, n)) => {
-                        head = h;
-                        node = n;
-                    }
-                }
-            }
+            self.head.push_like(
+                |head| Box::new(Node { value, next: Atomic::from_ptr(head) }),
+                |node, head| node.next.store(head, Relaxed),
+                AcqRel,
+                scope,
+            );
         })
     }
 
@@ -150,6 +140,7 @@ mod tests {
     use std::sync::atomic::Ordering::SeqCst;
     use std::thread;
 
+    use checked_alloc;
     use super::Stack;
     use self::rand::Rng;
 
@@ -163,19 +154,21 @@ mod tests {
 
     #[test]
     fn push_pop() {
-        let s = Stack::new();
-        s.push(1);
-        s.push(2);
-        s.push(3);
-        assert_eq!(s.pop(), Some(3));
-        s.push(4);
-        assert_eq!(s.pop(), Some(4));
-        assert_eq!(s.pop(), Some(2));
-        assert_eq!(s.pop(), Some(1));
-        assert_eq!(s.pop(), None);
-        s.push(5);
-        assert_eq!(s.pop(), Some(5));
-        assert_eq!(s.pop(), None);
+        checked_alloc::check_leaks(|| {
+            let s = Stack::new();
+            s.push(1);
+            s.push(2);
+            s.push(3);
+            assert_eq!(s.pop(), Some(3));
+            s.push(4);
+            assert_eq!(s.pop(), Some(4));
+            assert_eq!(s.pop(), Some(2));
+            assert_eq!(s.pop(), Some(1));
+            assert_eq!(s.pop(), None);
+            s.push(5);
+            assert_eq!(s.pop(), Some(5));
+            assert_eq!(s.pop(), None);
+        });
     }
 
     #[test]